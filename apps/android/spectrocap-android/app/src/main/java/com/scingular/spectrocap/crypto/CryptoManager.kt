@@ -1,9 +1,49 @@
-﻿package com.scingular.spectrocap.crypto
+package com.scingular.spectrocap.crypto
+
+import com.goterl.lazysodium.LazySodiumAndroid
+import com.goterl.lazysodium.SodiumAndroid
+import com.goterl.lazysodium.interfaces.Sign
 
 /**
- * TEMP STUB (Phase 2B build unblock)
- * Replace with real implementation later.
+ * Ed25519 signatures for Phase 2A E2EE.
+ *
+ * Wire format (shared with Windows `crypto::primitives`): detached
+ * signatures, the 64 bytes R || S of RFC 8032 over the raw message (for
+ * messages, the 32 metaHash bytes), base64-encoded in documents. Always
+ * cryptoSignDetached, never cryptoSign: combined mode emits
+ * signature || message, which Windows rejects.
+ * shared/remote-paste/signature_vectors.json pins the format.
+ *
+ * @param sodium libsodium binding (LazySodiumJava in JVM unit tests)
  */
-class CryptoManager {
-  // no-op
+class CryptoManager(private val sodium: Sign.Native = LazySodiumAndroid(SodiumAndroid())) {
+
+    /**
+     * Signs a message using Ed25519.
+     *
+     * @param message Message to sign (typically metaHash)
+     * @param secretKey Ed25519 secret key (64 bytes)
+     * @return Detached signature (64 bytes)
+     */
+    fun sign(message: ByteArray, secretKey: ByteArray): ByteArray {
+        require(secretKey.size == Sign.SECRETKEYBYTES) { "Ed25519 secret key must be ${Sign.SECRETKEYBYTES} bytes" }
+        val signature = ByteArray(Sign.BYTES)
+        check(sodium.cryptoSignDetached(signature, message, message.size.toLong(), secretKey)) { "Ed25519 signing failed" }
+        return signature
+    }
+
+    /**
+     * Verifies a detached Ed25519 signature.
+     *
+     * @param message Original message
+     * @param signature Detached signature (must be exactly 64 bytes)
+     * @param publicKey Ed25519 public key (32 bytes)
+     * @return true if valid, false otherwise
+     */
+    fun verify(message: ByteArray, signature: ByteArray, publicKey: ByteArray): Boolean {
+        if (signature.size != Sign.BYTES || publicKey.size != Sign.PUBLICKEYBYTES) {
+            return false
+        }
+        return sodium.cryptoSignVerifyDetached(signature, message, message.size, publicKey)
+    }
 }
@@ -1,9 +1,127 @@
-﻿package com.scingular.spectrocap.crypto
+package com.scingular.spectrocap.crypto
+
+import com.google.gson.Gson
+import com.google.gson.JsonObject
+import java.security.MessageDigest
+
+/**
+ * Nested `media` object of image and file messages.
+ */
+data class MediaInfo(
+    val ext: String,
+    val filename: String? = null,
+    val height: Int,
+    val width: Int
+)
 
 /**
- * TEMP STUB (Phase 2B build unblock)
- * Replace with real implementation later.
+ * Canonical metadata JSON (Phase 2A/2B).
+ *
+ * Keys are emitted in alphabetical order and serialized with Gson's default
+ * (HTML-safe) escaping, so the bytes, and therefore metaHash, match the
+ * Windows `crypto::format::CanonicalMetadata`. Both sides are checked against
+ * shared/remote-paste/canonical_vectors.json.
  */
-class Format {
-  // no-op
+object CanonicalMetadata {
+    private const val ALG = "xchacha20poly1305+sealedbox-x25519+ed25519"
+    private const val DEFAULT_MIME = "application/octet-stream"
+    private val gson = Gson()
+
+    /**
+     * Creates canonical metadata JSON for any message type.
+     *
+     * Order (alphabetical): alg, createdAtClient, ephemeral, expiresAt, media,
+     * messageId, mime, recipients (sorted), senderDeviceId, sizeBytesPlain,
+     * storagePath, type, version. `ephemeral`, `expiresAt` and `media` are
+     * only present when set; `media.filename` only when non-empty.
+     *
+     * @return Canonical JSON string (deterministic)
+     */
+    fun createCanonicalJson(
+        messageId: String,
+        senderDeviceId: String,
+        recipients: List<String>,
+        storagePath: String,
+        sizeBytesPlain: Long,
+        createdAtClient: String,
+        type: String = "text",
+        mime: String = DEFAULT_MIME,
+        media: MediaInfo? = null,
+        expiresAt: String? = null,
+        ephemeral: Boolean? = null
+    ): String {
+        // Insertion order is serialization order
+        val metadata = linkedMapOf<String, Any>()
+        metadata["alg"] = ALG
+        metadata["createdAtClient"] = createdAtClient
+        ephemeral?.let { metadata["ephemeral"] = it }
+        expiresAt?.let { metadata["expiresAt"] = it }
+        media?.let { metadata["media"] = mediaValue(it) }
+        metadata["messageId"] = messageId
+        metadata["mime"] = mime
+        metadata["recipients"] = recipients.sorted()
+        metadata["senderDeviceId"] = senderDeviceId
+        metadata["sizeBytesPlain"] = sizeBytesPlain
+        metadata["storagePath"] = storagePath
+        metadata["type"] = type
+        metadata["version"] = "2A"
+
+        return gson.toJson(metadata)
+    }
+
+    /**
+     * Reconstructs canonical JSON from a Firestore message document
+     * (for verification on the receiver side).
+     *
+     * @throws IllegalArgumentException if a signed field is missing
+     */
+    fun fromMessageDoc(doc: JsonObject): String {
+        fun string(key: String): String =
+            requireNotNull(doc.get(key)?.takeIf { it.isJsonPrimitive }?.asString) { "Missing $key" }
+
+        val recipients = requireNotNull(doc.get("recipients")?.takeIf { it.isJsonArray }?.asJsonArray) {
+            "Recipients must be an explicit device list"
+        }.map { it.asString }
+        val media = doc.get("media")?.takeIf { it.isJsonObject }?.asJsonObject?.let {
+            MediaInfo(
+                ext = it.get("ext")?.asString ?: "png",
+                filename = it.get("filename")?.asString,
+                height = it.get("height")?.asInt ?: 0,
+                width = it.get("width")?.asInt ?: 0
+            )
+        }
+
+        return createCanonicalJson(
+            messageId = string("messageId"),
+            senderDeviceId = string("senderDeviceId"),
+            recipients = recipients,
+            storagePath = string("storagePath"),
+            sizeBytesPlain = requireNotNull(doc.get("sizeBytesPlain")) { "Missing sizeBytesPlain" }.asLong,
+            createdAtClient = string("createdAtClient"),
+            type = string("type"),
+            mime = doc.get("mime")?.asString ?: DEFAULT_MIME,
+            media = media,
+            expiresAt = doc.get("expiresAt")?.asString,
+            ephemeral = doc.get("ephemeral")?.asBoolean
+        )
+    }
+
+    /**
+     * Computes metaHash = SHA256(canonicalJson).
+     *
+     * @return metaHash (32 bytes)
+     */
+    fun computeMetaHash(canonicalJson: String): ByteArray =
+        MessageDigest.getInstance("SHA-256").digest(canonicalJson.toByteArray(Charsets.UTF_8))
+
+    private fun mediaValue(media: MediaInfo): Map<String, Any> {
+        val value = linkedMapOf<String, Any>()
+        value["ext"] = media.ext
+        if (!media.filename.isNullOrEmpty()) {
+            value["filename"] = media.filename
+        }
+        value["height"] = media.height
+        value["width"] = media.width
+        return value
+    }
 }
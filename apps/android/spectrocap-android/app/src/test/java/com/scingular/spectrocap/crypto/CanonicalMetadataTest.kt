@@ -0,0 +1,46 @@
+package com.scingular.spectrocap.crypto
+
+import java.util.Base64
+import org.junit.Assert.assertEquals
+import org.junit.Test
+
+class CanonicalMetadataTest {
+
+    @Test
+    fun crossLanguageGoldenVectors() {
+        for (element in SharedVectors.load("canonical_vectors.json").getAsJsonArray("vectors")) {
+            val vector = element.asJsonObject
+            val name = vector.get("name").asString
+
+            val canonical = CanonicalMetadata.fromMessageDoc(vector.getAsJsonObject("doc"))
+
+            assertEquals("canonical JSON mismatch for $name", vector.get("canonicalJson").asString, canonical)
+            assertEquals(
+                "metaHash mismatch for $name",
+                vector.get("metaHash").asString,
+                Base64.getEncoder().encodeToString(CanonicalMetadata.computeMetaHash(canonical))
+            )
+        }
+    }
+
+    @Test
+    fun retentionHintsBoundInOrder() {
+        val json = CanonicalMetadata.createCanonicalJson(
+            messageId = "msg-1",
+            senderDeviceId = "dev-a",
+            recipients = listOf("dev-a"),
+            storagePath = "users/uid/messages/msg-1.bin",
+            sizeBytesPlain = 5,
+            createdAtClient = "2026-01-28T16:45:00Z",
+            expiresAt = "2026-01-28T16:50:00Z",
+            ephemeral = true
+        )
+        assertEquals(
+            "{\"alg\":\"xchacha20poly1305+sealedbox-x25519+ed25519\",\"createdAtClient\":\"2026-01-28T16:45:00Z\"," +
+                "\"ephemeral\":true,\"expiresAt\":\"2026-01-28T16:50:00Z\",\"messageId\":\"msg-1\"," +
+                "\"mime\":\"application/octet-stream\",\"recipients\":[\"dev-a\"],\"senderDeviceId\":\"dev-a\"," +
+                "\"sizeBytesPlain\":5,\"storagePath\":\"users/uid/messages/msg-1.bin\",\"type\":\"text\",\"version\":\"2A\"}",
+            json
+        )
+    }
+}
@@ -0,0 +1,38 @@
+package com.scingular.spectrocap.crypto
+
+import com.google.gson.JsonObject
+import com.goterl.lazysodium.LazySodiumJava
+import com.goterl.lazysodium.SodiumJava
+import com.goterl.lazysodium.interfaces.Sign
+import java.util.Base64
+import org.junit.Assert.assertArrayEquals
+import org.junit.Assert.assertFalse
+import org.junit.Assert.assertTrue
+import org.junit.Test
+
+class CryptoManagerTest {
+    private val sodium = LazySodiumJava(SodiumJava())
+    private val crypto = CryptoManager(sodium)
+
+    @Test
+    fun signatureWireFormatVectors() {
+        for (element in SharedVectors.load("signature_vectors.json").getAsJsonArray("vectors")) {
+            val vector = element.asJsonObject
+            val name = vector.get("name").asString
+            val publicKey = ByteArray(Sign.PUBLICKEYBYTES)
+            val secretKey = ByteArray(Sign.SECRETKEYBYTES)
+            assertTrue(sodium.cryptoSignSeedKeypair(publicKey, secretKey, vector.bytes("seed")))
+            val message = vector.bytes("message")
+            val expected = vector.bytes("signature")
+
+            assertArrayEquals(name, vector.bytes("publicKey"), publicKey)
+            assertArrayEquals(name, expected, crypto.sign(message, secretKey))
+            assertTrue(name, crypto.verify(message, expected, publicKey))
+            if (message.isNotEmpty()) {
+                assertFalse(name, crypto.verify(message, expected + message, publicKey))
+            }
+        }
+    }
+
+    private fun JsonObject.bytes(key: String): ByteArray = Base64.getDecoder().decode(get(key).asString)
+}
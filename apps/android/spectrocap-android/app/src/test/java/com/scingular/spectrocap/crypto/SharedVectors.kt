@@ -0,0 +1,13 @@
+package com.scingular.spectrocap.crypto
+
+import com.google.gson.JsonObject
+import com.google.gson.JsonParser
+import java.io.File
+
+/** Fixtures in shared/remote-paste (path set by app/build.gradle) */
+object SharedVectors {
+    fun load(name: String): JsonObject {
+        val dir = System.getProperty("sharedVectorsDir") ?: error("sharedVectorsDir not set")
+        return JsonParser.parseString(File(dir, name).readText()).asJsonObject
+    }
+}
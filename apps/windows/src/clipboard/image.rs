@@ -0,0 +1,304 @@
+// Clipboard image conversion
+// src/clipboard/image.rs
+//
+// Decodes PNG/JPEG clips to RGBA and builds the Windows clipboard's native
+// representations: CF_DIBV5 (32-bit BGRA with straight alpha), CF_DIB
+// (32-bit BI_RGB, alpha flattened onto white for readers that ignore it)
+// and the registered "PNG" format. Everything here is plain byte work so it
+// is tested on every platform; the Windows backend only places the buffers.
+
+use thiserror::Error;
+use crate::crypto::media::ImageValidator;
+
+/// Largest image we decode (~200 MB of RGBA)
+pub const MAX_PIXELS: u64 = 50_000_000;
+
+const BITMAPINFOHEADER_SIZE: u32 = 40;
+const BITMAPV5HEADER_SIZE: u32 = 124;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+/// 'sRGB'
+const LCS_SRGB: u32 = 0x7352_4742;
+const LCS_GM_IMAGES: u32 = 4;
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("Unsupported image format: {0}")]
+    Unsupported(String),
+    #[error("Failed to decode image: {0}")]
+    Decode(String),
+    #[error("Failed to encode image: {0}")]
+    Encode(String),
+    #[error("Image too large: {width}x{height}")]
+    TooLarge { width: u32, height: u32 },
+}
+
+/// Decoded image, 8-bit RGBA rows top to bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Buffers for each clipboard format an image is published as
+#[derive(Debug, Clone)]
+pub struct NativeImage {
+    pub dibv5: Vec<u8>,
+    pub dib: Vec<u8>,
+    pub png: Vec<u8>,
+}
+
+impl NativeImage {
+    /// Converts an encoded PNG or JPEG; PNG input is published as-is
+    pub fn from_encoded(bytes: &[u8]) -> Result<Self, ImageError> {
+        let image = RgbaImage::decode(bytes)?;
+        let png = match ImageValidator::detect_mime(bytes).as_str() {
+            "image/png" => bytes.to_vec(),
+            _ => image.to_png()?,
+        };
+
+        Ok(NativeImage { dibv5: image.to_dibv5(), dib: image.to_dib(), png })
+    }
+}
+
+impl RgbaImage {
+    /// Decodes a PNG or JPEG (detected from magic bytes)
+    pub fn decode(bytes: &[u8]) -> Result<Self, ImageError> {
+        match ImageValidator::detect_mime(bytes).as_str() {
+            "image/png" => Self::decode_png(bytes),
+            "image/jpeg" => Self::decode_jpeg(bytes),
+            other => Err(ImageError::Unsupported(other.to_string())),
+        }
+    }
+
+    fn decode_png(bytes: &[u8]) -> Result<Self, ImageError> {
+        let limits = png::Limits { bytes: (MAX_PIXELS * 4) as usize };
+        let mut decoder = png::Decoder::new_with_limits(bytes, limits);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| ImageError::Decode(e.to_string()))?;
+
+        let (width, height) = (reader.info().width, reader.info().height);
+        check_size(width, height)?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buf).map_err(|e| ImageError::Decode(e.to_string()))?;
+        buf.truncate(frame.buffer_size());
+
+        let pixels = match frame.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect(),
+            png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&l| [l, l, l, 0xFF]).collect(),
+            png::ColorType::Indexed => {
+                return Err(ImageError::Decode("Palette was not expanded".to_string()));
+            }
+        };
+
+        Self::from_parts(width, height, pixels)
+    }
+
+    fn decode_jpeg(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut decoder = jpeg_decoder::Decoder::new(bytes);
+        decoder.read_info().map_err(|e| ImageError::Decode(e.to_string()))?;
+        let info = decoder.info().ok_or_else(|| ImageError::Decode("Missing JPEG header".to_string()))?;
+
+        let (width, height) = (u32::from(info.width), u32::from(info.height));
+        check_size(width, height)?;
+
+        let buf = decoder.decode().map_err(|e| ImageError::Decode(e.to_string()))?;
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => {
+                buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect()
+            }
+            jpeg_decoder::PixelFormat::L8 => buf.iter().flat_map(|&l| [l, l, l, 0xFF]).collect(),
+            jpeg_decoder::PixelFormat::L16 => buf.chunks_exact(2)
+                .map(|p| (u16::from_ne_bytes([p[0], p[1]]) >> 8) as u8)
+                .flat_map(|l| [l, l, l, 0xFF])
+                .collect(),
+            // Adobe CMYK JPEGs are stored inverted
+            jpeg_decoder::PixelFormat::CMYK32 => buf.chunks_exact(4)
+                .flat_map(|p| {
+                    let k = u16::from(p[3]);
+                    let channel = |c: u8| (u16::from(c) * k / 255) as u8;
+                    [channel(p[0]), channel(p[1]), channel(p[2]), 0xFF]
+                })
+                .collect(),
+        };
+
+        Self::from_parts(width, height, pixels)
+    }
+
+    fn from_parts(width: u32, height: u32, pixels: Vec<u8>) -> Result<Self, ImageError> {
+        if pixels.len() as u64 != u64::from(width) * u64::from(height) * 4 {
+            return Err(ImageError::Decode(format!("Truncated {}x{} image", width, height)));
+        }
+        Ok(RgbaImage { width, height, pixels })
+    }
+
+    /// Re-encodes as PNG (for the registered "PNG" format)
+    pub fn to_png(&self) -> Result<Vec<u8>, ImageError> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(|e| ImageError::Encode(e.to_string()))?;
+        writer.write_image_data(&self.pixels).map_err(|e| ImageError::Encode(e.to_string()))?;
+        writer.finish().map_err(|e| ImageError::Encode(e.to_string()))?;
+        Ok(out)
+    }
+
+    /// CF_DIBV5: BITMAPV5HEADER + bottom-up BGRA with an alpha mask
+    pub fn to_dibv5(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(BITMAPV5HEADER_SIZE as usize + self.pixels.len());
+        self.write_info_header(&mut out, BITMAPV5HEADER_SIZE, BI_BITFIELDS);
+        for mask in [0x00FF_0000u32, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000] {
+            out.extend_from_slice(&mask.to_le_bytes());
+        }
+        out.extend_from_slice(&LCS_SRGB.to_le_bytes());
+        out.extend_from_slice(&[0; 36]); // bV5Endpoints (unused for sRGB)
+        out.extend_from_slice(&[0; 12]); // bV5GammaRed/Green/Blue
+        out.extend_from_slice(&LCS_GM_IMAGES.to_le_bytes());
+        out.extend_from_slice(&[0; 12]); // bV5ProfileData, bV5ProfileSize, bV5Reserved
+
+        self.write_bgra_rows(&mut out, |p| [p[2], p[1], p[0], p[3]]);
+        out
+    }
+
+    /// CF_DIB: BITMAPINFOHEADER + bottom-up BGRX, composited onto white
+    pub fn to_dib(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(BITMAPINFOHEADER_SIZE as usize + self.pixels.len());
+        self.write_info_header(&mut out, BITMAPINFOHEADER_SIZE, BI_RGB);
+
+        let over_white = |c: u8, a: u8| ((u16::from(c) * u16::from(a) + 255 * (255 - u16::from(a))) / 255) as u8;
+        self.write_bgra_rows(&mut out, |p| {
+            [over_white(p[2], p[3]), over_white(p[1], p[3]), over_white(p[0], p[3]), 0xFF]
+        });
+        out
+    }
+
+    /// Fields shared by BITMAPINFOHEADER and the start of BITMAPV5HEADER
+    fn write_info_header(&self, out: &mut Vec<u8>, header_size: u32, compression: u32) {
+        out.extend_from_slice(&header_size.to_le_bytes());
+        out.extend_from_slice(&(self.width as i32).to_le_bytes());
+        // Positive height: rows are stored bottom-up
+        out.extend_from_slice(&(self.height as i32).to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes()); // planes
+        out.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+        out.extend_from_slice(&compression.to_le_bytes());
+        out.extend_from_slice(&(self.pixels.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 16]); // pels per meter (x, y), colors used, colors important
+    }
+
+    fn write_bgra_rows(&self, out: &mut Vec<u8>, convert: impl Fn(&[u8]) -> [u8; 4]) {
+        let stride = self.width as usize * 4;
+        if stride == 0 {
+            return;
+        }
+        for row in self.pixels.chunks_exact(stride).rev() {
+            for pixel in row.chunks_exact(4) {
+                out.extend_from_slice(&convert(pixel));
+            }
+        }
+    }
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), ImageError> {
+    if width == 0 || height == 0 || u64::from(width) * u64::from(height) > MAX_PIXELS {
+        return Err(ImageError::TooLarge { width, height });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// 2x2: red, transparent / green, half-transparent blue
+    fn sample() -> RgbaImage {
+        RgbaImage {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                255, 0, 0, 255,   0, 0, 0, 0,
+                0, 255, 0, 255,   0, 0, 255, 128,
+            ],
+        }
+    }
+
+    #[test]
+    fn test_png_roundtrip_and_native_formats() {
+        let png = sample().to_png().unwrap();
+        assert!(ImageValidator::validate_image_magic(&png));
+        assert_eq!(RgbaImage::decode(&png).unwrap(), sample());
+
+        let native = NativeImage::from_encoded(&png).unwrap();
+        assert_eq!(native.png, png);
+
+        let v5 = &native.dibv5;
+        assert_eq!(v5.len(), 124 + 16);
+        assert_eq!(u32_at(v5, 0), 124);
+        assert_eq!(u32_at(v5, 16), BI_BITFIELDS);
+        assert_eq!(u32_at(v5, 52), 0xFF00_0000); // alpha mask
+        assert_eq!(u32_at(v5, 56), LCS_SRGB);
+        // Bottom row first, BGRA
+        assert_eq!(&v5[124..132], &[0, 255, 0, 255, 255, 0, 0, 128]);
+        assert_eq!(&v5[132..140], &[0, 0, 255, 255, 0, 0, 0, 0]);
+
+        let dib = &native.dib;
+        assert_eq!(dib.len(), 40 + 16);
+        assert_eq!(u32_at(dib, 0), 40);
+        assert_eq!(u32_at(dib, 16), BI_RGB);
+        // Transparent becomes white, half-transparent blue becomes light blue
+        assert_eq!(&dib[44..48], &[255, 127, 127, 255]);
+        assert_eq!(&dib[52..56], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_png_color_types_expand_to_rgba() {
+        let mut gray = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut gray, 2, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0xFF, 0xFF, 0x80, 0x00]).unwrap();
+        }
+
+        let image = RgbaImage::decode(&gray).unwrap();
+        assert_eq!(image.pixels, vec![255, 255, 255, 255, 128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_jpeg_decodes_and_gains_png() {
+        let mut jpeg = Vec::new();
+        let rgb: Vec<u8> = [200u8, 30, 30].repeat(16 * 8);
+        jpeg_encoder::Encoder::new(&mut jpeg, 100)
+            .encode(&rgb, 16, 8, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+
+        let image = RgbaImage::decode(&jpeg).unwrap();
+        assert_eq!((image.width, image.height), (16, 8));
+        let first = &image.pixels[..4];
+        assert!(first[0].abs_diff(200) <= 4 && first[1].abs_diff(30) <= 4 && first[3] == 255);
+
+        let native = NativeImage::from_encoded(&jpeg).unwrap();
+        assert_eq!(RgbaImage::decode(&native.png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_and_oversized() {
+        assert!(matches!(RgbaImage::decode(b"GIF89a"), Err(ImageError::Unsupported(_))));
+        assert!(matches!(
+            RgbaImage::decode(b"\x89PNG\r\n\x1a\ngarbage"),
+            Err(ImageError::Decode(_))
+        ));
+        assert!(matches!(check_size(10_000, 10_000), Err(ImageError::TooLarge { .. })));
+        assert!(check_size(4096, 4096).is_ok());
+    }
+}
@@ -0,0 +1,303 @@
+// Linux clipboard backend
+// src/clipboard/linux.rs
+//
+// Drives wl-clipboard (`wl-copy` / `wl-paste`) on Wayland and `xclip` on
+// X11. Both expose clipboard contents as MIME targets. The tools own one
+// target per selection, so a write publishes the richest representation
+// only (files, then image, HTML, RTF, text); for the same reason the
+// password-manager markers behind `ClipData::concealed` are read but never
+// written. Tools are run with argument vectors, never through a shell.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use super::{ClipData, ClipFormat, ClipImage, ClipboardBackend, ClipboardError};
+
+const TEXT_TARGETS: &[&str] = &["text/plain;charset=utf-8", "UTF8_STRING", "text/plain", "STRING", "TEXT"];
+const HTML_TARGETS: &[&str] = &["text/html"];
+const RTF_TARGETS: &[&str] = &["text/rtf", "application/rtf", "text/richtext"];
+const IMAGE_TARGETS: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/bmp", "image/webp"];
+const FILES_TARGETS: &[&str] = &["text/uri-list"];
+/// Offered by password managers (KDE hint, macOS-style concealed type)
+const CONCEALED_TARGETS: &[&str] = &["x-kde-passwordManagerHint", "org.nspasteboard.ConcealedType"];
+
+/// Command-line clipboard tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxTool {
+    /// wl-clipboard (Wayland)
+    WlClipboard,
+    /// xclip (X11)
+    Xclip,
+}
+
+impl LinuxTool {
+    /// wl-clipboard under a Wayland session, xclip otherwise
+    pub fn detect() -> Self {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            LinuxTool::WlClipboard
+        } else {
+            LinuxTool::Xclip
+        }
+    }
+
+    fn list_targets(self) -> Command {
+        match self {
+            LinuxTool::WlClipboard => command("wl-paste", &["--list-types"]),
+            LinuxTool::Xclip => command("xclip", &["-selection", "clipboard", "-o", "-t", "TARGETS"]),
+        }
+    }
+
+    fn read_target(self, target: &str) -> Command {
+        match self {
+            LinuxTool::WlClipboard => command("wl-paste", &["--no-newline", "--type", target]),
+            LinuxTool::Xclip => command("xclip", &["-selection", "clipboard", "-o", "-t", target]),
+        }
+    }
+
+    fn write_target(self, target: &str) -> Command {
+        match self {
+            LinuxTool::WlClipboard => command("wl-copy", &["--type", target]),
+            LinuxTool::Xclip => command("xclip", &["-selection", "clipboard", "-i", "-t", target]),
+        }
+    }
+}
+
+fn command(program: &str, args: &[&str]) -> Command {
+    let mut command = Command::new(program);
+    command.args(args);
+    command
+}
+
+pub struct LinuxClipboard {
+    tool: LinuxTool,
+}
+
+impl LinuxClipboard {
+    pub fn new(tool: LinuxTool) -> Self {
+        LinuxClipboard { tool }
+    }
+
+    /// Targets offered by the current selection owner (empty if none)
+    fn targets(&self) -> Result<Vec<String>, ClipboardError> {
+        let output = self.tool.list_targets()
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|e| ClipboardError::ReadError(format!("Failed to run {:?}: {}", self.tool, e)))?;
+
+        // Both tools fail when nothing owns the clipboard
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    fn read_target(&self, target: &str) -> Result<Vec<u8>, ClipboardError> {
+        let output = self.tool.read_target(target)
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|e| ClipboardError::ReadError(format!("Failed to run {:?}: {}", self.tool, e)))?;
+        if !output.status.success() {
+            return Err(ClipboardError::ReadError(format!("Could not read {} from the clipboard", target)));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl ClipboardBackend for LinuxClipboard {
+    fn formats(&self) -> Result<Vec<ClipFormat>, ClipboardError> {
+        Ok(formats_for_targets(&self.targets()?))
+    }
+
+    fn read(&self) -> Result<ClipData, ClipboardError> {
+        let targets = self.targets()?;
+        let text_of = |wanted: &[&str]| -> Result<Option<String>, ClipboardError> {
+            match pick_target(&targets, wanted) {
+                Some(target) => Ok(Some(String::from_utf8_lossy(&self.read_target(target)?).into_owned())),
+                None => Ok(None),
+            }
+        };
+
+        let mut data = ClipData {
+            text: text_of(TEXT_TARGETS)?,
+            html: text_of(HTML_TARGETS)?,
+            rtf: text_of(RTF_TARGETS)?,
+            concealed: pick_target(&targets, CONCEALED_TARGETS).is_some(),
+            ..Default::default()
+        };
+        if let Some(target) = pick_target(&targets, IMAGE_TARGETS) {
+            data.image = Some(ClipImage { mime: target.to_string(), bytes: self.read_target(target)? });
+        }
+        if let Some(uris) = text_of(FILES_TARGETS)? {
+            data.files = decode_uri_list(&uris);
+        }
+        Ok(data)
+    }
+
+    fn write(&self, data: &ClipData) -> Result<(), ClipboardError> {
+        let (target, bytes) = primary_target(data)
+            .ok_or_else(|| ClipboardError::WriteError("Nothing to write".to_string()))?;
+
+        let mut child = self.tool.write_target(&target)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| ClipboardError::WriteError(format!("Failed to run {:?}: {}", self.tool, e)))?;
+
+        // Dropping stdin closes it; the tool then forks to serve the selection
+        child.stdin.take()
+            .ok_or_else(|| ClipboardError::WriteError("Clipboard tool has no stdin".to_string()))?
+            .write_all(&bytes)
+            .map_err(|e| ClipboardError::WriteError(e.to_string()))?;
+
+        let status = child.wait().map_err(|e| ClipboardError::WriteError(e.to_string()))?;
+        if !status.success() {
+            return Err(ClipboardError::WriteError(format!("{:?} exited with {}", self.tool, status)));
+        }
+        Ok(())
+    }
+}
+
+fn pick_target<'a>(targets: &'a [String], wanted: &[&str]) -> Option<&'a str> {
+    wanted.iter()
+        .find_map(|w| targets.iter().find(|t| t.eq_ignore_ascii_case(w)))
+        .map(String::as_str)
+}
+
+fn formats_for_targets(targets: &[String]) -> Vec<ClipFormat> {
+    [
+        (ClipFormat::Text, TEXT_TARGETS),
+        (ClipFormat::Html, HTML_TARGETS),
+        (ClipFormat::Rtf, RTF_TARGETS),
+        (ClipFormat::Image, IMAGE_TARGETS),
+        (ClipFormat::Files, FILES_TARGETS),
+    ]
+    .into_iter()
+    .filter(|(_, wanted)| pick_target(targets, wanted).is_some())
+    .map(|(format, _)| format)
+    .collect()
+}
+
+/// Richest representation in `data` as (MIME target, bytes)
+fn primary_target(data: &ClipData) -> Option<(String, Vec<u8>)> {
+    if !data.files.is_empty() {
+        return Some((FILES_TARGETS[0].to_string(), encode_uri_list(&data.files).into_bytes()));
+    }
+    if let Some(image) = &data.image {
+        return Some((image.mime.clone(), image.bytes.clone()));
+    }
+    if let Some(html) = &data.html {
+        return Some((HTML_TARGETS[0].to_string(), html.clone().into_bytes()));
+    }
+    if let Some(rtf) = &data.rtf {
+        return Some((RTF_TARGETS[0].to_string(), rtf.clone().into_bytes()));
+    }
+    data.text.as_ref().map(|text| (TEXT_TARGETS[0].to_string(), text.clone().into_bytes()))
+}
+
+/// `text/uri-list` (RFC 2483) of `file://` URIs
+fn encode_uri_list(paths: &[PathBuf]) -> String {
+    paths.iter()
+        .map(|path| {
+            let encoded: String = path.to_string_lossy()
+                .bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                        (b as char).to_string()
+                    }
+                    _ => format!("%{:02X}", b),
+                })
+                .collect();
+            format!("file://{}\r\n", encoded)
+        })
+        .collect()
+}
+
+/// Local paths from a `text/uri-list`; comments and non-file URIs are skipped
+fn decode_uri_list(list: &str) -> Vec<PathBuf> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.strip_prefix("file://"))
+        // Drop an authority ("file://host/path"); "localhost" and "" mean local
+        .filter_map(|rest| rest.find('/').map(|slash| &rest[slash..]))
+        .filter_map(percent_decode)
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_formats_from_targets() {
+        let offered = targets(&["TIMESTAMP", "TARGETS", "text/html", "UTF8_STRING", "image/png"]);
+        assert_eq!(
+            formats_for_targets(&offered),
+            vec![ClipFormat::Text, ClipFormat::Html, ClipFormat::Image]
+        );
+        assert_eq!(pick_target(&offered, TEXT_TARGETS), Some("UTF8_STRING"));
+        assert_eq!(pick_target(&offered, CONCEALED_TARGETS), None);
+        let secret = targets(&["UTF8_STRING", "x-kde-passwordManagerHint"]);
+        assert_eq!(pick_target(&secret, CONCEALED_TARGETS), Some("x-kde-passwordManagerHint"));
+        assert!(formats_for_targets(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_primary_target_prefers_richest() {
+        let mut data = ClipData::text("plain");
+        data.html = Some("<b>rich</b>".to_string());
+        assert_eq!(primary_target(&data).unwrap().0, "text/html");
+
+        data.image = Some(ClipImage { mime: "image/jpeg".to_string(), bytes: vec![0xFF, 0xD8] });
+        assert_eq!(primary_target(&data).unwrap(), ("image/jpeg".to_string(), vec![0xFF, 0xD8]));
+        assert!(primary_target(&ClipData::default()).is_none());
+    }
+
+    #[test]
+    fn test_uri_list_roundtrip() {
+        let paths = vec![PathBuf::from("/home/me/My File.txt"), PathBuf::from("/tmp/ü#1")];
+        let list = encode_uri_list(&paths);
+        assert!(list.starts_with("file:///home/me/My%20File.txt\r\n"));
+        assert_eq!(decode_uri_list(&list), paths);
+
+        let mixed = "# comment\r\nhttps://example.com/x\r\nfile://localhost/etc/hosts\r\nfile:///bad%zz\r\n";
+        assert_eq!(decode_uri_list(mixed), vec![PathBuf::from("/etc/hosts")]);
+    }
+
+    #[test]
+    fn test_tool_commands_pass_targets_as_arguments() {
+        let command = LinuxTool::Xclip.read_target("text/html; rm -rf ~");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(command.get_program(), "xclip");
+        assert_eq!(args.last().unwrap().to_str(), Some("text/html; rm -rf ~"));
+
+        assert_eq!(LinuxTool::WlClipboard.write_target("image/png").get_program(), "wl-copy");
+    }
+}
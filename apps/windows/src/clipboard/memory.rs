@@ -0,0 +1,90 @@
+// In-memory clipboard backend
+// src/clipboard/memory.rs
+//
+// Holds the clip in process and counts simulated pastes, for tests and
+// platforms without a system backend.
+
+use std::sync::Mutex;
+use super::{ClipData, ClipFormat, ClipboardBackend, ClipboardError};
+
+#[derive(Default)]
+struct State {
+    data: ClipData,
+    writes: u64,
+    pastes: usize,
+}
+
+#[derive(Default)]
+pub struct MemoryClipboard {
+    state: Mutex<State>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current contents
+    pub fn contents(&self) -> ClipData {
+        self.lock().data.clone()
+    }
+
+    /// Number of `paste` calls so far
+    pub fn paste_count(&self) -> usize {
+        self.lock().pastes
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn formats(&self) -> Result<Vec<ClipFormat>, ClipboardError> {
+        Ok(self.lock().data.formats())
+    }
+
+    fn read(&self) -> Result<ClipData, ClipboardError> {
+        Ok(self.contents())
+    }
+
+    fn write(&self, data: &ClipData) -> Result<(), ClipboardError> {
+        let mut state = self.lock();
+        state.data = data.clone();
+        state.writes += 1;
+        Ok(())
+    }
+
+    fn change_count(&self) -> Option<u64> {
+        Some(self.lock().writes)
+    }
+
+    fn paste(&self) -> Result<(), ClipboardError> {
+        self.lock().pastes += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_write_replaces_all_formats() {
+        let clipboard = MemoryClipboard::new();
+        let mut rich = ClipData::text("plain");
+        rich.html = Some("<i>plain</i>".to_string());
+        clipboard.write(&rich).unwrap();
+        assert_eq!(clipboard.formats().unwrap(), vec![ClipFormat::Text, ClipFormat::Html]);
+
+        clipboard.write(&ClipData::files(vec![PathBuf::from("/tmp/a")])).unwrap();
+        let read = clipboard.read().unwrap();
+        assert_eq!(read.formats(), vec![ClipFormat::Files]);
+        assert_eq!(read.text, None);
+        assert_eq!(clipboard.change_count(), Some(2));
+
+        clipboard.paste().unwrap();
+        assert_eq!(clipboard.paste_count(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+// Clipboard operations module
+// src/clipboard/mod.rs
+//
+// Every clip type goes through `ClipboardBackend`: plain text, HTML, RTF,
+// images and file lists. `platform_backend` picks the implementation for
+// the running OS:
+// - `WindowsClipboard`: Win32 clipboard formats (Windows)
+// - `LinuxClipboard`: wl-clipboard (Wayland) or xclip (X11)
+// - `MemoryClipboard`: in-process, for tests
+//
+// `image` converts PNG/JPEG clips to the Windows bitmap formats.
+
+pub mod image;
+pub mod linux;
+pub mod memory;
+#[cfg(target_os = "windows")]
+pub mod windows;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde::Serialize;
+use thiserror::Error;
+
+pub use image::{ImageError, NativeImage, RgbaImage};
+pub use linux::{LinuxClipboard, LinuxTool};
+pub use memory::MemoryClipboard;
+#[cfg(target_os = "windows")]
+pub use windows::WindowsClipboard;
+
+#[derive(Debug, Error)]
+pub enum ClipboardError {
+    #[error("Failed to read clipboard: {0}")]
+    ReadError(String),
+    #[error("Failed to write clipboard: {0}")]
+    WriteError(String),
+    #[error("Not supported on this platform: {0}")]
+    Unsupported(String),
+    #[error(transparent)]
+    Image(#[from] ImageError),
+}
+
+/// Representations a clip can carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipFormat {
+    Text,
+    Html,
+    Rtf,
+    Image,
+    Files,
+}
+
+/// Encoded image (PNG, JPEG, BMP...)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipImage {
+    pub mime: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Clipboard contents; each field is one representation of the same clip
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClipData {
+    pub text: Option<String>,
+    /// HTML fragment (no CF_HTML header)
+    pub html: Option<String>,
+    pub rtf: Option<String>,
+    pub image: Option<ClipImage>,
+    pub files: Vec<PathBuf>,
+    /// Marked as a secret (password managers set this on copies). Written
+    /// with the platform markers that keep it out of clipboard history and
+    /// cloud clipboard sync.
+    pub concealed: bool,
+}
+
+impl ClipData {
+    pub fn text(text: &str) -> Self {
+        ClipData { text: Some(text.to_string()), ..Default::default() }
+    }
+
+    pub fn image(mime: &str, bytes: Vec<u8>) -> Self {
+        ClipData { image: Some(ClipImage { mime: mime.to_string(), bytes }), ..Default::default() }
+    }
+
+    pub fn files(files: Vec<PathBuf>) -> Self {
+        ClipData { files, ..Default::default() }
+    }
+
+    /// Representations present, richest last
+    pub fn formats(&self) -> Vec<ClipFormat> {
+        let mut formats = Vec::new();
+        if self.text.is_some() {
+            formats.push(ClipFormat::Text);
+        }
+        if self.html.is_some() {
+            formats.push(ClipFormat::Html);
+        }
+        if self.rtf.is_some() {
+            formats.push(ClipFormat::Rtf);
+        }
+        if self.image.is_some() {
+            formats.push(ClipFormat::Image);
+        }
+        if !self.files.is_empty() {
+            formats.push(ClipFormat::Files);
+        }
+        formats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.formats().is_empty()
+    }
+}
+
+/// System clipboard (or a stand-in for it)
+pub trait ClipboardBackend: Send + Sync {
+    /// Representations currently on the clipboard
+    fn formats(&self) -> Result<Vec<ClipFormat>, ClipboardError>;
+
+    /// Reads every representation the backend understands
+    fn read(&self) -> Result<ClipData, ClipboardError>;
+
+    /// Replaces the clipboard with the representations in `data`
+    fn write(&self, data: &ClipData) -> Result<(), ClipboardError>;
+
+    /// Counter that changes whenever the clipboard does
+    ///
+    /// Lets watchers skip reading an unchanged clipboard. None when the
+    /// platform has no such counter; callers then compare contents.
+    fn change_count(&self) -> Option<u64> {
+        None
+    }
+
+    /// Pastes the clipboard into the focused window
+    ///
+    /// Backends that cannot inject keystrokes return `Unsupported`; the clip
+    /// is still on the clipboard for the user to paste.
+    fn paste(&self) -> Result<(), ClipboardError> {
+        Err(ClipboardError::Unsupported("simulated paste".to_string()))
+    }
+}
+
+/// Backend for the OS this build runs on
+pub fn platform_backend() -> Arc<dyn ClipboardBackend> {
+    #[cfg(target_os = "windows")]
+    {
+        Arc::new(WindowsClipboard::new())
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Arc::new(LinuxClipboard::new(LinuxTool::detect()))
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        log::warn!("No system clipboard backend for this platform; using an in-process clipboard");
+        Arc::new(MemoryClipboard::new())
+    }
+}
+
+pub fn read_clipboard() -> Result<String, ClipboardError> {
+    platform_backend()
+        .read()?
+        .text
+        .ok_or_else(|| ClipboardError::ReadError("No text on the clipboard".to_string()))
+}
+
+pub fn write_clipboard(text: &str) -> Result<(), ClipboardError> {
+    platform_backend().write(&ClipData::text(text))
+}
+
+/// Sends Ctrl+V to the focused window
+pub fn simulate_paste() -> Result<(), ClipboardError> {
+    #[cfg(target_os = "windows")]
+    {
+        use enigo::{Enigo, Key, KeyboardControllable};
+        let mut enigo = Enigo::new();
+        enigo.key_down(Key::Control);
+        enigo.key_click(Key::Layout('v'));
+        enigo.key_up(Key::Control);
+        Ok(())
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        Err(ClipboardError::Unsupported("simulated paste".to_string()))
+    }
+}
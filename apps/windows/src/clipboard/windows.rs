@@ -0,0 +1,201 @@
+// Windows clipboard backend
+// src/clipboard/windows.rs
+//
+// Win32 formats: CF_UNICODETEXT, "HTML Format" (CF_HTML), "Rich Text Format",
+// CF_HDROP file lists, and images as the registered "PNG" format plus
+// CF_DIBV5/CF_DIB (built in-process by `image`). A write opens the clipboard
+// once, empties it and adds every representation so other applications see
+// a single clip. Concealed clips carry the formats password managers use to
+// keep a copy out of Win+V history and cloud clipboard sync.
+
+use std::path::PathBuf;
+use clipboard_win::options::NoClear;
+use clipboard_win::{formats, raw, register_format, Clipboard};
+use super::{simulate_paste, ClipData, ClipFormat, ClipImage, ClipboardBackend, ClipboardError, NativeImage};
+
+const OPEN_ATTEMPTS: usize = 10;
+
+/// Present on copies that clipboard monitors should ignore
+const EXCLUDE_FROM_MONITORS: &str = "ExcludeClipboardContentFromMonitorProcessing";
+/// DWORD 0 keeps the copy out of clipboard history / cloud clipboard
+const HISTORY_OPT_OUT: &[&str] = &["CanIncludeInClipboardHistory", "CanUploadToCloudClipboard"];
+
+pub struct WindowsClipboard;
+
+impl WindowsClipboard {
+    pub fn new() -> Self {
+        WindowsClipboard
+    }
+
+    fn open(&self) -> Result<Clipboard, ClipboardError> {
+        Clipboard::new_attempts(OPEN_ATTEMPTS)
+            .map_err(|e| ClipboardError::ReadError(format!("Clipboard is busy: {}", e)))
+    }
+
+    fn registered(name: &str) -> Result<u32, ClipboardError> {
+        register_format(name)
+            .map(|id| id.get())
+            .ok_or_else(|| ClipboardError::ReadError(format!("Failed to register clipboard format {}", name)))
+    }
+
+    fn html_format() -> Result<u32, ClipboardError> {
+        Self::registered("HTML Format")
+    }
+
+    fn rtf_format() -> Result<u32, ClipboardError> {
+        Self::registered("Rich Text Format")
+    }
+
+    fn png_format() -> Result<u32, ClipboardError> {
+        Self::registered("PNG")
+    }
+
+    fn get_bytes(format: u32) -> Result<Vec<u8>, ClipboardError> {
+        let mut out = Vec::new();
+        raw::get_vec(format, &mut out).map_err(|e| ClipboardError::ReadError(e.to_string()))?;
+        Ok(out)
+    }
+
+    fn set_bytes(format: u32, bytes: &[u8]) -> Result<(), ClipboardError> {
+        raw::set_without_clear(format, bytes).map_err(|e| ClipboardError::WriteError(e.to_string()))
+    }
+
+    /// Password-manager markers (clipboard must be open)
+    fn is_concealed() -> Result<bool, ClipboardError> {
+        if raw::is_format_avail(Self::registered(EXCLUDE_FROM_MONITORS)?) {
+            return Ok(true);
+        }
+        let history = Self::registered(HISTORY_OPT_OUT[0])?;
+        Ok(raw::is_format_avail(history) && Self::get_bytes(history)?.iter().all(|&b| b == 0))
+    }
+
+    fn set_concealed() -> Result<(), ClipboardError> {
+        let zero = 0u32.to_le_bytes();
+        Self::set_bytes(Self::registered(EXCLUDE_FROM_MONITORS)?, &zero)?;
+        for name in HISTORY_OPT_OUT {
+            Self::set_bytes(Self::registered(name)?, &zero)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for WindowsClipboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClipboardBackend for WindowsClipboard {
+    fn formats(&self) -> Result<Vec<ClipFormat>, ClipboardError> {
+        let _clipboard = self.open()?;
+        let mut available = Vec::new();
+        if raw::is_format_avail(formats::CF_UNICODETEXT) {
+            available.push(ClipFormat::Text);
+        }
+        if raw::is_format_avail(Self::html_format()?) {
+            available.push(ClipFormat::Html);
+        }
+        if raw::is_format_avail(Self::rtf_format()?) {
+            available.push(ClipFormat::Rtf);
+        }
+        if raw::is_format_avail(Self::png_format()?) || raw::is_format_avail(formats::CF_BITMAP) {
+            available.push(ClipFormat::Image);
+        }
+        if raw::is_format_avail(formats::CF_HDROP) {
+            available.push(ClipFormat::Files);
+        }
+        Ok(available)
+    }
+
+    fn read(&self) -> Result<ClipData, ClipboardError> {
+        let _clipboard = self.open()?;
+        let mut data = ClipData::default();
+
+        if raw::is_format_avail(formats::CF_UNICODETEXT) {
+            let mut out = Vec::new();
+            raw::get_string(&mut out).map_err(|e| ClipboardError::ReadError(e.to_string()))?;
+            let text = String::from_utf8_lossy(&out);
+            data.text = Some(text.trim_end_matches('\0').to_string());
+        }
+
+        let html = Self::html_format()?;
+        if raw::is_format_avail(html) {
+            let mut out = Vec::new();
+            raw::get_html(html, &mut out).map_err(|e| ClipboardError::ReadError(e.to_string()))?;
+            data.html = Some(String::from_utf8_lossy(&out).into_owned());
+        }
+
+        let rtf = Self::rtf_format()?;
+        if raw::is_format_avail(rtf) {
+            let bytes = Self::get_bytes(rtf)?;
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            data.rtf = Some(String::from_utf8_lossy(&bytes[..end]).into_owned());
+        }
+
+        let png = Self::png_format()?;
+        if raw::is_format_avail(png) {
+            data.image = Some(ClipImage { mime: "image/png".to_string(), bytes: Self::get_bytes(png)? });
+        } else if raw::is_format_avail(formats::CF_BITMAP) {
+            let mut out = Vec::new();
+            raw::get_bitmap(&mut out).map_err(|e| ClipboardError::ReadError(e.to_string()))?;
+            data.image = Some(ClipImage { mime: "image/bmp".to_string(), bytes: out });
+        }
+
+        if raw::is_format_avail(formats::CF_HDROP) {
+            let mut files: Vec<String> = Vec::new();
+            raw::get_file_list(&mut files).map_err(|e| ClipboardError::ReadError(e.to_string()))?;
+            data.files = files.into_iter().map(PathBuf::from).collect();
+        }
+
+        data.concealed = Self::is_concealed()?;
+        Ok(data)
+    }
+
+    fn write(&self, data: &ClipData) -> Result<(), ClipboardError> {
+        if data.is_empty() {
+            return Err(ClipboardError::WriteError("Nothing to write".to_string()));
+        }
+
+        // Decode before taking the clipboard so other apps aren't blocked
+        let image = data.image.as_ref()
+            .map(|image| NativeImage::from_encoded(&image.bytes))
+            .transpose()?;
+
+        let _clipboard = self.open()?;
+        raw::empty().map_err(|e| ClipboardError::WriteError(e.to_string()))?;
+
+        if let Some(text) = &data.text {
+            raw::set_string_with(text, NoClear).map_err(|e| ClipboardError::WriteError(e.to_string()))?;
+        }
+        if let Some(html) = &data.html {
+            raw::set_html_with(Self::html_format()?, html, NoClear)
+                .map_err(|e| ClipboardError::WriteError(e.to_string()))?;
+        }
+        if let Some(rtf) = &data.rtf {
+            let mut bytes = rtf.as_bytes().to_vec();
+            bytes.push(0);
+            Self::set_bytes(Self::rtf_format()?, &bytes)?;
+        }
+        if !data.files.is_empty() {
+            let paths: Vec<String> = data.files.iter().map(|p| p.to_string_lossy().into_owned()).collect();
+            raw::set_file_list_with(&paths, NoClear).map_err(|e| ClipboardError::WriteError(e.to_string()))?;
+        }
+        if let Some(image) = image {
+            Self::set_bytes(Self::png_format()?, &image.png)?;
+            Self::set_bytes(formats::CF_DIBV5, &image.dibv5)?;
+            Self::set_bytes(formats::CF_DIB, &image.dib)?;
+        }
+        if data.concealed {
+            Self::set_concealed()?;
+        }
+        Ok(())
+    }
+
+    fn change_count(&self) -> Option<u64> {
+        raw::seq_num().map(|seq| u64::from(seq.get()))
+    }
+
+    fn paste(&self) -> Result<(), ClipboardError> {
+        simulate_paste()
+    }
+}
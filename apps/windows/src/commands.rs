@@ -1,35 +1,119 @@
 // Tauri command handlers
 // src/commands.rs
 
-use std::path::PathBuf;
-use crate::crypto::media::{ClipboardImage, ImageValidator};
+use std::sync::Arc;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use tauri::{AppHandle, Manager};
+use thiserror::Error;
+use crate::clipboard::{platform_backend, ClipboardBackend};
+use crate::crypto::error::CryptoError;
+use crate::crypto::identity::DeviceIdentity;
+use crate::crypto::key_mgmt::KeyManager;
+use crate::crypto::media::{ClipboardImage, FileSanitizer, ImageValidator};
+use crate::crypto::receiver::E2EEReceiver;
+use crate::crypto::replay::{FreshnessWindow, ReplayGuard};
+use crate::crypto::revocation::DeviceRevocations;
+use crate::crypto::sender::E2EESender;
+use crate::crypto::trust::KeyPinning;
+use crate::db::{Database, DbError, HistoryQuery, SharedDatabase, SqliteOutbox, SqlitePinStore, SqliteRevocationStore, SqliteSeenLedger};
+use crate::history::{History, HistoryEntry, HistoryError};
+use crate::outbox::{OutboxError, OutboxFlusher, OutboxItemStatus, OutboxStore};
+use crate::sensitive::SensitivePolicy;
+use crate::sync::{InboxSync, SyncConfig, SyncEvent, SyncEventSink, SyncHandle, SYNC_EVENT};
+use crate::transport::{FirebaseConfig, FirebaseRestTransport, Transport, TransportError};
+use crate::watcher::{
+    ClipboardWatcher, OutboxClipSender, RecentClips, WatchConfig, WatchEvent, WatchEventSink, WatchRules,
+    WatcherHandle, WATCH_EVENT,
+};
+
+/// Error returned by every fallible command
+///
+/// Serializes to `{ "code", "message" }` like `CryptoError`; wrapped errors
+/// keep their own code so the frontend can tell "not a recipient" from
+/// "network" from "history locked".
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Storage(#[from] DbError),
+    #[error(transparent)]
+    History(#[from] HistoryError),
+    #[error(transparent)]
+    Outbox(#[from] OutboxError),
+    #[error("Invalid image format: magic bytes validation failed")]
+    InvalidImage,
+    #[error("{0}")]
+    Clipboard(String),
+    #[error("{0}")]
+    File(String),
+    #[error("Failed to get AppData directory")]
+    DataDirUnavailable,
+    #[error("History is locked; sign in first")]
+    HistoryLocked,
+    #[error("Background task failed: {0}")]
+    Task(String),
+}
+
+impl CommandError {
+    /// Stable machine-readable code for the frontend and telemetry
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandError::Crypto(e) => e.code(),
+            CommandError::Transport(e) => e.code(),
+            CommandError::Storage(e) => e.code(),
+            CommandError::History(e) => e.code(),
+            CommandError::Outbox(OutboxError::Storage(_)) => "storage",
+            CommandError::Outbox(OutboxError::Transport(e)) => e.code(),
+            CommandError::InvalidImage => "invalid_image",
+            CommandError::Clipboard(_) => "clipboard",
+            CommandError::File(_) => "file",
+            CommandError::DataDirUnavailable => "data_dir_unavailable",
+            CommandError::HistoryLocked => "history_locked",
+            CommandError::Task(_) => "task_failed",
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CommandError {
+    fn from(e: rusqlite::Error) -> Self {
+        CommandError::Storage(e.into())
+    }
+}
+
+impl Serialize for CommandError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CommandError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
 
 #[tauri::command]
 pub fn greet(name: &str) -> String {
     format!("Hello, {}!", name)
 }
 
-/// Copy image bytes to Windows clipboard
+/// Copy image bytes to the system clipboard
 /// 
 /// # Arguments
-/// * `image_bytes` - Base64-encoded image data
-/// * `temp_dir` - Temporary directory path for staging
+/// * `image_bytes` - Raw image data (PNG or JPEG)
 /// 
 /// # Returns
-/// Ok(null) on success; Err(String) with error message on failure
+/// Ok(null) on success; Err({ code, message }) on failure
 #[tauri::command]
-pub fn copy_image_to_clipboard(
-    image_bytes: Vec<u8>,
-    temp_dir: String,
-) -> Result<(), String> {
-    let temp_path = PathBuf::from(temp_dir);
-    
+pub fn copy_image_to_clipboard(image_bytes: Vec<u8>) -> CommandResult<()> {
     // Validate image magic bytes before clipboard operation
     if !ImageValidator::validate_image_magic(&image_bytes) {
-        return Err("Invalid image format: magic bytes validation failed".to_string());
+        return Err(CommandError::InvalidImage);
     }
 
-    ClipboardImage::set_clipboard_image(&image_bytes, &temp_path)
+    ClipboardImage::set_clipboard_image(&image_bytes).map_err(CommandError::Clipboard)
 }
 
 /// Save image bytes to file (for "Save As" dialog)
@@ -39,18 +123,47 @@ pub fn copy_image_to_clipboard(
 /// * `file_path` - Target file path (from save dialog)
 /// 
 /// # Returns
-/// Ok(null) on success; Err(String) with error message on failure
+/// Ok(null) on success; Err({ code, message }) on failure
 #[tauri::command]
 pub fn save_image_to_file(
     image_bytes: Vec<u8>,
     file_path: String,
-) -> Result<(), String> {
+) -> CommandResult<()> {
     // Validate image magic bytes before writing to disk
     if !ImageValidator::validate_image_magic(&image_bytes) {
-        return Err("Invalid image format: magic bytes validation failed".to_string());
+        return Err(CommandError::InvalidImage);
     }
 
-    ClipboardImage::save_image_to_file(&image_bytes, &file_path)
+    ClipboardImage::save_image_to_file(&image_bytes, &file_path).map_err(CommandError::File)
+}
+
+/// Save a received file (for "Save As" dialog)
+/// 
+/// # Arguments
+/// * `file_bytes` - Decrypted file contents
+/// * `file_path` - Target file path (from save dialog)
+/// 
+/// # Returns
+/// Ok(null) on success; Err({ code, message }) on failure
+#[tauri::command]
+pub fn save_file_to_path(
+    file_bytes: Vec<u8>,
+    file_path: String,
+) -> CommandResult<()> {
+    std::fs::write(&file_path, &file_bytes)
+        .map_err(|e| CommandError::File(format!("Failed to write file: {}", e)))
+}
+
+/// Sanitize a sender-supplied filename for the save dialog's default path
+/// 
+/// # Arguments
+/// * `filename` - Filename from the message's `media.filename`
+/// 
+/// # Returns
+/// Filename safe to use on Windows (no directories, reserved names or characters)
+#[tauri::command]
+pub fn sanitize_file_name(filename: String) -> String {
+    FileSanitizer::sanitize_filename(&filename)
 }
 
 /// Detect MIME type from image bytes
@@ -65,3 +178,413 @@ pub fn detect_image_mime(image_bytes: Vec<u8>) -> String {
     ImageValidator::detect_mime(&image_bytes)
 }
 
+/// Local history, unlocked at sign-in (managed state)
+pub struct HistoryState {
+    history: std::sync::Mutex<Option<Arc<History>>>,
+    clipboard: Arc<dyn ClipboardBackend>,
+    /// Received and pasted clips, shared with sync and the clipboard watcher
+    recent: Arc<RecentClips>,
+}
+
+impl Default for HistoryState {
+    fn default() -> Self {
+        HistoryState {
+            history: std::sync::Mutex::new(None),
+            clipboard: platform_backend(),
+            recent: Arc::new(RecentClips::default()),
+        }
+    }
+}
+
+impl HistoryState {
+    /// # Returns
+    /// Err if nobody is signed in
+    pub fn history(&self) -> CommandResult<Arc<History>> {
+        self.slot().clone().ok_or(CommandError::HistoryLocked)
+    }
+
+    fn unlock(&self, db: SharedDatabase) {
+        let history = History::new(db, self.clipboard.clone()).with_recent_clips(self.recent.clone());
+        *self.slot() = Some(Arc::new(history));
+    }
+
+    /// Drops the open database and its history key (logout)
+    pub fn lock(&self) {
+        *self.slot() = None;
+    }
+
+    fn slot(&self) -> std::sync::MutexGuard<'_, Option<Arc<History>>> {
+        self.history.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Runs a history operation off the main thread (clipboard writes can block)
+async fn with_history<T: Send + 'static>(
+    state: &HistoryState,
+    op: impl FnOnce(&History) -> Result<T, HistoryError> + Send + 'static,
+) -> CommandResult<T> {
+    let history = state.history()?;
+    tauri::async_runtime::spawn_blocking(move || op(&history))
+        .await
+        .map_err(|e| CommandError::Task(e.to_string()))?
+        .map_err(CommandError::from)
+}
+
+/// Most recently received message
+/// 
+/// # Returns
+/// The "last" history entry, or null if history is empty
+#[tauri::command]
+pub async fn get_last_message(state: tauri::State<'_, HistoryState>) -> CommandResult<Option<HistoryEntry>> {
+    with_history(&state, |history| history.last()).await
+}
+
+/// Page of history, newest first
+/// 
+/// # Arguments
+/// * `query` - Filters and paging (`senderDeviceId`, `messageType`, `favoritesOnly`, `limit`, `offset`)
+#[tauri::command]
+pub async fn get_history(
+    state: tauri::State<'_, HistoryState>,
+    query: Option<HistoryQuery>,
+) -> CommandResult<Vec<HistoryEntry>> {
+    let query = query.unwrap_or_default();
+    with_history(&state, move |history| history.list(&query)).await
+}
+
+/// Put the last message on the clipboard and paste it into the focused window
+/// 
+/// # Returns
+/// The pasted entry
+#[tauri::command]
+pub async fn paste_last(state: tauri::State<'_, HistoryState>) -> CommandResult<HistoryEntry> {
+    with_history(&state, |history| history.paste_last()).await
+}
+
+/// Paste a history entry and make it the last message ("Paste From...")
+/// 
+/// # Arguments
+/// * `message_id` - Entry to paste
+#[tauri::command]
+pub async fn paste_message(
+    state: tauri::State<'_, HistoryState>,
+    message_id: String,
+) -> CommandResult<HistoryEntry> {
+    with_history(&state, move |history| history.paste(&message_id)).await
+}
+
+/// Running inbox sync task (managed state)
+#[derive(Default)]
+pub struct SyncState(tokio::sync::Mutex<Option<SyncHandle>>);
+
+impl SyncState {
+    pub async fn stop(&self) {
+        if let Some(running) = self.0.lock().await.take() {
+            running.stop().await;
+        }
+    }
+}
+
+/// Forwards sync progress to the webview as `inbox-sync` events
+struct TauriSyncEvents(AppHandle);
+
+impl SyncEventSink for TauriSyncEvents {
+    fn emit(&self, event: SyncEvent) {
+        if let Err(e) = self.0.emit_all(SYNC_EVENT, event) {
+            log::warn!("Failed to emit sync event: {}", e);
+        }
+    }
+}
+
+/// Tray menu item id for pausing the clipboard watcher
+pub const PAUSE_SENDING_ITEM: &str = "pause_sending";
+
+/// Running clipboard watcher and the user's send rules (managed state)
+#[derive(Default)]
+pub struct WatcherState {
+    running: tokio::sync::Mutex<Option<WatcherHandle>>,
+    rules: Arc<std::sync::Mutex<WatchRules>>,
+}
+
+impl WatcherState {
+    pub async fn stop(&self) {
+        if let Some(running) = self.running.lock().await.take() {
+            running.stop().await;
+        }
+    }
+
+    pub fn rules(&self) -> WatchRules {
+        self.lock_rules().clone()
+    }
+
+    pub fn set_rules(&self, rules: WatchRules) {
+        *self.lock_rules() = rules;
+    }
+
+    /// Pauses or resumes sending local copies
+    ///
+    /// # Returns
+    /// true if now paused
+    pub fn toggle_paused(&self) -> bool {
+        let mut rules = self.lock_rules();
+        rules.paused = !rules.paused;
+        rules.paused
+    }
+
+    fn lock_rules(&self) -> std::sync::MutexGuard<'_, WatchRules> {
+        self.rules.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Sensitive-content policy shared by sync and the watcher (managed state)
+#[derive(Default)]
+pub struct SensitiveState {
+    policy: Arc<std::sync::Mutex<SensitivePolicy>>,
+}
+
+impl SensitiveState {
+    pub fn policy(&self) -> SensitivePolicy {
+        self.lock_policy().clone()
+    }
+
+    pub fn set_policy(&self, policy: SensitivePolicy) {
+        *self.lock_policy() = policy;
+    }
+
+    fn lock_policy(&self) -> std::sync::MutexGuard<'_, SensitivePolicy> {
+        self.policy.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Forwards watcher progress to the webview as `clipboard-watch` events
+struct TauriWatchEvents(AppHandle);
+
+impl WatchEventSink for TauriWatchEvents {
+    fn emit(&self, event: WatchEvent) {
+        if let Err(e) = self.0.emit_all(WATCH_EVENT, event) {
+            log::warn!("Failed to emit watcher event: {}", e);
+        }
+    }
+}
+
+/// Start (or restart) background inbox sync and the clipboard watcher for the
+/// signed-in account, and unlock history
+/// 
+/// On first run this provisions the device (UUID plus signing and box keys);
+/// every run (re)publishes `users/{uid}/devices/{deviceId}`. A failed
+/// registration is logged and retried on the next start.
+/// 
+/// # Arguments
+/// * `uid` - Firebase Auth user ID
+/// * `passphrase` - Key store passphrase
+/// * `id_token` - Firebase Auth ID token (optional with the emulator)
+/// * `use_emulator` - Talk to the local Firebase emulator suite
+/// 
+/// # Returns
+/// This device's UUID once both tasks are running; progress arrives as
+/// `inbox-sync` and `clipboard-watch` events
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn start_inbox_sync(
+    app: AppHandle,
+    state: tauri::State<'_, SyncState>,
+    history: tauri::State<'_, HistoryState>,
+    watcher: tauri::State<'_, WatcherState>,
+    sensitive: tauri::State<'_, SensitiveState>,
+    uid: String,
+    passphrase: String,
+    id_token: Option<String>,
+    project_id: String,
+    storage_bucket: String,
+    use_emulator: bool,
+) -> CommandResult<String> {
+    let db_path = Database::default_path().ok_or(CommandError::DataDirUnavailable)?;
+    if let Some(dir) = db_path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| CommandError::File(format!("Failed to create data directory: {}", e)))?;
+    }
+
+    let key_manager = KeyManager::default_windows(&passphrase)?;
+    let identity = DeviceIdentity::new(key_manager.clone());
+    let device_id = identity.ensure_provisioned()?;
+    let registration = identity.registration_doc(&DeviceIdentity::default_device_name())?;
+    // Sync and history share one connection so re-keying never races an insert
+    let db = Database::new(&db_path, &key_manager)?.into_shared();
+    history.unlock(db.clone());
+    let ledger = SqliteSeenLedger::open(&db_path)?;
+    let pins = SqlitePinStore::open(&db_path)?;
+    let revocation_store = SqliteRevocationStore::open(&db_path)?;
+    let outbox = SqliteOutbox::open(&db_path)?;
+    let watcher_outbox = SqliteOutbox::open(&db_path)?;
+
+    let pinning = KeyPinning::new(Arc::new(pins));
+    let sender = E2EESender::with_key_manager(key_manager.clone()).with_key_pinning(pinning.clone());
+    let receiver = E2EEReceiver::with_key_manager(key_manager.clone())
+        .with_replay_guard(ReplayGuard::new(Arc::new(ledger), FreshnessWindow::default()))
+        .with_key_pinning(pinning.clone())
+        .with_revocations(DeviceRevocations::new(key_manager, pinning, Arc::new(revocation_store)));
+
+    let config = if use_emulator {
+        FirebaseConfig::emulator(&project_id, &storage_bucket)
+    } else {
+        FirebaseConfig::production(&project_id, &storage_bucket)
+    };
+    let transport = FirebaseRestTransport::new(config)?;
+    if id_token.is_some() {
+        transport.set_id_token(id_token);
+    }
+    let transport = Arc::new(transport);
+
+    // Offline is fine: sync still starts and the next start registers
+    if let Err(e) = transport.upsert_device(&uid, &registration).await {
+        log::warn!("Failed to register device {}: {}", device_id, e);
+    }
+
+    let sync = InboxSync::new(
+        transport.clone(),
+        receiver,
+        db,
+        Arc::new(TauriSyncEvents(app.clone())),
+        SyncConfig::new(&uid, &device_id),
+    )
+    .with_outbox(OutboxFlusher::new(Arc::new(outbox), transport.clone()))
+    .with_recent_clips(history.recent.clone())
+    .with_sensitive_policy(sensitive.policy.clone());
+
+    let clip_sender = OutboxClipSender::new(
+        sender,
+        transport.clone(),
+        OutboxFlusher::new(Arc::new(watcher_outbox), transport),
+        &uid,
+        &device_id,
+    );
+    let clipboard_watcher = ClipboardWatcher::new(
+        history.clipboard.clone(),
+        Arc::new(clip_sender),
+        history.recent.clone(),
+        watcher.rules.clone(),
+        Arc::new(TauriWatchEvents(app)),
+        WatchConfig::default(),
+    )
+    .with_sensitive_policy(sensitive.policy.clone());
+
+    let mut running = state.0.lock().await;
+    if let Some(previous) = running.take() {
+        previous.stop().await;
+    }
+    *running = Some(sync.spawn());
+
+    let mut running = watcher.running.lock().await;
+    if let Some(previous) = running.take() {
+        previous.stop().await;
+    }
+    *running = Some(clipboard_watcher.spawn());
+    Ok(device_id)
+}
+
+/// Stop background inbox sync and the clipboard watcher
+#[tauri::command]
+pub async fn stop_inbox_sync(
+    state: tauri::State<'_, SyncState>,
+    watcher: tauri::State<'_, WatcherState>,
+) -> CommandResult<()> {
+    state.stop().await;
+    watcher.stop().await;
+    Ok(())
+}
+
+/// Sign out locally: stop sync and the clipboard watcher, and lock history
+#[tauri::command]
+pub async fn logout(
+    sync: tauri::State<'_, SyncState>,
+    watcher: tauri::State<'_, WatcherState>,
+    history: tauri::State<'_, HistoryState>,
+) -> CommandResult<()> {
+    sync.stop().await;
+    watcher.stop().await;
+    history.lock();
+    Ok(())
+}
+
+/// Rules for sending local copies
+#[tauri::command]
+pub fn get_watch_rules(watcher: tauri::State<'_, WatcherState>) -> WatchRules {
+    watcher.rules()
+}
+
+/// Change the rules for sending local copies (applies immediately)
+/// 
+/// # Arguments
+/// * `rules` - `{ paused, textOnly, maxBytes }`
+#[tauri::command]
+pub fn set_watch_rules(app: AppHandle, watcher: tauri::State<'_, WatcherState>, rules: WatchRules) {
+    let paused = rules.paused;
+    watcher.set_rules(rules);
+    update_pause_item(&app, paused);
+}
+
+/// Per-kind actions for clips that look like secrets
+#[tauri::command]
+pub fn get_sensitive_policy(sensitive: tauri::State<'_, SensitiveState>) -> SensitivePolicy {
+    sensitive.policy()
+}
+
+/// Change the sensitive-content policy (applies immediately to sending and
+/// receiving)
+/// 
+/// # Arguments
+/// * `policy` - `{ actions: { cardNumber: "shortExpiry", ... }, shortExpirySecs }`;
+///   kinds left out use their default action
+#[tauri::command]
+pub fn set_sensitive_policy(sensitive: tauri::State<'_, SensitiveState>, policy: SensitivePolicy) {
+    sensitive.set_policy(policy);
+}
+
+/// Keeps the tray's "Pause Sending" item in step with the rules
+pub fn update_pause_item(app: &AppHandle, paused: bool) {
+    let title = if paused { "Resume Sending" } else { "Pause Sending" };
+    if let Err(e) = app.tray_handle().get_item(PAUSE_SENDING_ITEM).set_title(title) {
+        log::warn!("Failed to update tray item: {}", e);
+    }
+}
+
+/// Re-encrypt local history under a new data key
+/// 
+/// Runs on the database sync writes to, so messages arriving meanwhile wait
+/// for the re-key instead of being sealed under a retired key.
+/// 
+/// # Returns
+/// Number of history entries re-encrypted
+#[tauri::command]
+pub async fn rekey_history(state: tauri::State<'_, HistoryState>) -> CommandResult<usize> {
+    with_history(&state, |history| history.rekey()).await
+}
+
+/// Delivery status of queued outgoing clips
+/// 
+/// # Returns
+/// Items in queue order (pending, sent or failed)
+#[tauri::command]
+pub fn get_outbox_status() -> CommandResult<Vec<OutboxItemStatus>> {
+    let db_path = Database::default_path().ok_or(CommandError::DataDirUnavailable)?;
+    let outbox = SqliteOutbox::open(&db_path)?;
+    let items = outbox.items()?;
+    Ok(items.iter().map(OutboxItemStatus::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapped_errors_keep_their_code() {
+        let err = CommandError::from(CryptoError::NotRecipient("dev-123".to_string()));
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["code"], "not_recipient");
+        assert_eq!(json["message"], CryptoError::NotRecipient("dev-123".to_string()).to_string());
+
+        let err = CommandError::from(OutboxError::Transport(TransportError::Network("reset".to_string())));
+        assert_eq!(serde_json::to_value(&err).unwrap()["code"], "network");
+        assert_eq!(serde_json::to_value(CommandError::HistoryLocked).unwrap()["code"], "history_locked");
+    }
+}
@@ -0,0 +1,450 @@
+//! Self-describing versioned blob header
+//!
+//! Version 3 blobs start with a header that names the format version, the
+//! AEAD suite and the body layout (single AEAD message or secretstream
+//! chunks). An HMAC computed with a key derived from the DEK authenticates
+//! the header:
+//!
+//! ```text
+//! [Magic "SCAP" (4)] [Version = 3 (1)] [Suite (1)] [Flags (1)] [Reserved = 0 (1)]
+//! single:  [Nonce (24)]
+//! chunked: [Chunk size, u32 LE (4)] [Secretstream header (24)]
+//! [Header MAC, HMAC-SHA256 (32)]
+//! [Body]
+//! ```
+//!
+//! `VersionedBlob::open` dispatches on the leading bytes. It also reads the
+//! legacy `SCAP2A` (single message) and `SCAP2S` (chunked) blobs, which have
+//! no header MAC. Senders only pick version 3 when every recipient device
+//! advertises it in `blobVersions`, so older clients keep working.
+
+use std::io::{self, Read, Write};
+use sodiumoxide::crypto::auth::hmacsha256;
+use sodiumoxide::crypto::kdf::blake2b as kdf;
+use super::primitives::CryptoPrimitives;
+use super::format::BlobFormat;
+use super::stream::{self, StreamBlobFormat, StreamDecryptor, StreamEncryptor};
+use super::error::{CryptoError, CryptoResult};
+
+/// Blob format generations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlobVersion {
+    /// `SCAP2A`: single AEAD message, no header MAC
+    Legacy2A,
+    /// `SCAP2S`: secretstream chunks, no header MAC
+    Legacy2S,
+    /// `SCAP` + version byte 3: authenticated header
+    V3,
+}
+
+impl BlobVersion {
+    /// Versions this client can read, as advertised in `blobVersions`
+    pub const SUPPORTED: &'static [BlobVersion] = &[
+        BlobVersion::Legacy2A,
+        BlobVersion::Legacy2S,
+        BlobVersion::V3,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlobVersion::Legacy2A => "2A",
+            BlobVersion::Legacy2S => "2S",
+            BlobVersion::V3 => "3",
+        }
+    }
+
+    /// Picks the newest single-message version every recipient can read
+    ///
+    /// Devices that don't advertise `blobVersions` only read `SCAP2A`.
+    pub fn negotiate<'a, I>(recipient_versions: I) -> BlobVersion
+    where
+        I: IntoIterator<Item = &'a [String]>,
+    {
+        let all_support_v3 = recipient_versions.into_iter()
+            .all(|versions| versions.iter().any(|v| v == BlobVersion::V3.as_str()));
+
+        if all_support_v3 { BlobVersion::V3 } else { BlobVersion::Legacy2A }
+    }
+
+    /// Version strings for a device document's `blobVersions`
+    pub fn advertised() -> Vec<String> {
+        Self::SUPPORTED.iter().map(|v| v.as_str().to_string()).collect()
+    }
+}
+
+/// AEAD suite identifiers (header byte)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadSuite {
+    /// XChaCha20-Poly1305 (IETF AEAD for single blobs, secretstream for chunked)
+    XChaCha20Poly1305,
+}
+
+impl AeadSuite {
+    fn id(&self) -> u8 {
+        match self {
+            AeadSuite::XChaCha20Poly1305 => 0x01,
+        }
+    }
+
+    fn from_id(id: u8) -> CryptoResult<Self> {
+        match id {
+            0x01 => Ok(AeadSuite::XChaCha20Poly1305),
+            other => Err(CryptoError::UnsupportedVersion(format!("AEAD suite 0x{:02x}", other))),
+        }
+    }
+}
+
+/// Parsed blob header (any version)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobHeader {
+    pub version: BlobVersion,
+    pub suite: AeadSuite,
+    pub compressed: bool,
+    /// Plaintext chunk size for chunked bodies
+    pub chunk_size: Option<u32>,
+    /// AEAD nonce (single) or secretstream header (chunked), 24 bytes either way
+    pub nonce: Vec<u8>,
+}
+
+impl BlobHeader {
+    const MAGIC: &'static [u8] = b"SCAP";
+    const V3: u8 = 3;
+    const FLAG_CHUNKED: u8 = 0x01;
+    const FLAG_COMPRESSED: u8 = 0x02;
+    const NONCE_LEN: usize = 24;
+    const MAC_LEN: usize = hmacsha256::TAGBYTES;
+    const MAC_CONTEXT: [u8; 8] = *b"SCAPHDR3";
+
+    pub fn is_chunked(&self) -> bool {
+        self.chunk_size.is_some()
+    }
+
+    /// Encodes a version 3 header, MAC included
+    pub fn encode(&self, dek: &[u8]) -> CryptoResult<Vec<u8>> {
+        if self.version != BlobVersion::V3 {
+            return Err(CryptoError::UnsupportedVersion(
+                format!("Cannot write blob version {}", self.version.as_str()),
+            ));
+        }
+        if self.nonce.len() != Self::NONCE_LEN {
+            return Err(CryptoError::InvalidKey(format!("Nonce must be {} bytes", Self::NONCE_LEN)));
+        }
+
+        let mut flags = 0u8;
+        if self.is_chunked() {
+            flags |= Self::FLAG_CHUNKED;
+        }
+        if self.compressed {
+            flags |= Self::FLAG_COMPRESSED;
+        }
+
+        let mut header = Vec::with_capacity(8 + 4 + Self::NONCE_LEN + Self::MAC_LEN);
+        header.extend_from_slice(Self::MAGIC);
+        header.extend_from_slice(&[Self::V3, self.suite.id(), flags, 0]);
+        if let Some(chunk_size) = self.chunk_size {
+            header.extend_from_slice(&chunk_size.to_le_bytes());
+        }
+        header.extend_from_slice(&self.nonce);
+
+        let mac = hmacsha256::authenticate(&header, &Self::mac_key(dek)?);
+        header.extend_from_slice(mac.as_ref());
+
+        Ok(header)
+    }
+
+    /// Reads a header of any known version, leaving `reader` at the body
+    ///
+    /// The version 3 header MAC is verified against `dek`.
+    pub fn read_from<R: Read>(reader: &mut R, dek: &[u8]) -> CryptoResult<Self> {
+        let mut prefix = [0u8; 6];
+        read_exact(reader, &mut prefix)?;
+
+        if prefix == *b"SCAP2A" {
+            let mut nonce = vec![0u8; Self::NONCE_LEN];
+            read_exact(reader, &mut nonce)?;
+            return Ok(Self::legacy(BlobVersion::Legacy2A, None, nonce));
+        }
+
+        if prefix == StreamBlobFormat::MAGIC {
+            let mut chunk_size = [0u8; 4];
+            read_exact(reader, &mut chunk_size)?;
+            let mut nonce = vec![0u8; Self::NONCE_LEN];
+            read_exact(reader, &mut nonce)?;
+            return Ok(Self::legacy(BlobVersion::Legacy2S, Some(u32::from_le_bytes(chunk_size)), nonce));
+        }
+
+        if &prefix[..4] != Self::MAGIC {
+            return Err(CryptoError::MalformedBlob("Invalid blob magic".to_string()));
+        }
+        if prefix[4] != Self::V3 {
+            return Err(CryptoError::UnsupportedVersion(format!("Blob format version {}", prefix[4])));
+        }
+
+        let mut header = prefix.to_vec();
+        let mut rest = [0u8; 2];
+        read_exact(reader, &mut rest)?;
+        header.extend_from_slice(&rest);
+        let suite = AeadSuite::from_id(prefix[5])?;
+        let (flags, reserved) = (rest[0], rest[1]);
+
+        if flags & !(Self::FLAG_CHUNKED | Self::FLAG_COMPRESSED) != 0 || reserved != 0 {
+            return Err(CryptoError::MalformedBlob("Unknown blob header flags".to_string()));
+        }
+
+        let chunk_size = if flags & Self::FLAG_CHUNKED != 0 {
+            let mut bytes = [0u8; 4];
+            read_exact(reader, &mut bytes)?;
+            header.extend_from_slice(&bytes);
+            Some(u32::from_le_bytes(bytes))
+        } else {
+            None
+        };
+
+        let mut nonce = vec![0u8; Self::NONCE_LEN];
+        read_exact(reader, &mut nonce)?;
+        header.extend_from_slice(&nonce);
+
+        let mut mac = [0u8; Self::MAC_LEN];
+        read_exact(reader, &mut mac)?;
+        let tag = hmacsha256::Tag::from_slice(&mac).expect("MAC_LEN bytes");
+        if !hmacsha256::verify(&tag, &header, &Self::mac_key(dek)?) {
+            return Err(CryptoError::Tampered("Blob header MAC mismatch".to_string()));
+        }
+
+        Ok(BlobHeader {
+            version: BlobVersion::V3,
+            suite,
+            compressed: flags & Self::FLAG_COMPRESSED != 0,
+            chunk_size,
+            nonce,
+        })
+    }
+
+    fn legacy(version: BlobVersion, chunk_size: Option<u32>, nonce: Vec<u8>) -> Self {
+        BlobHeader {
+            version,
+            suite: AeadSuite::XChaCha20Poly1305,
+            compressed: false,
+            chunk_size,
+            nonce,
+        }
+    }
+
+    /// Header MAC key: KDF subkey of the DEK, so the DEK itself only keys the AEAD
+    fn mac_key(dek: &[u8]) -> CryptoResult<hmacsha256::Key> {
+        let master = kdf::Key::from_slice(dek)
+            .ok_or_else(|| CryptoError::InvalidKey("DEK must be 32 bytes".to_string()))?;
+        let mut subkey = [0u8; hmacsha256::KEYBYTES];
+        kdf::derive_from_key(&mut subkey, 1, Self::MAC_CONTEXT, &master)
+            .map_err(|_| CryptoError::InvalidKey("Failed to derive header MAC key".to_string()))?;
+
+        Ok(hmacsha256::Key(subkey))
+    }
+}
+
+/// Encrypts and decrypts blobs of any supported version
+pub struct VersionedBlob;
+
+impl VersionedBlob {
+    /// Encrypts a payload held in memory
+    ///
+    /// # Arguments
+    /// * `version` - `Legacy2A` or `V3` (see `BlobVersion::negotiate`)
+    /// * `nonce` - 24-byte nonce
+    /// * `dek` - 32-byte data encryption key
+    /// * `aad` - Additional authenticated data (metaHash)
+    pub fn seal(
+        version: BlobVersion,
+        plaintext: &[u8],
+        nonce: &[u8],
+        dek: &[u8],
+        aad: &[u8],
+    ) -> CryptoResult<Vec<u8>> {
+        let ciphertext = CryptoPrimitives::encrypt_aead(plaintext, nonce, dek, aad)?;
+
+        match version {
+            BlobVersion::Legacy2A => BlobFormat::create_blob(nonce, &ciphertext),
+            BlobVersion::V3 => {
+                let header = BlobHeader {
+                    version,
+                    suite: AeadSuite::XChaCha20Poly1305,
+                    compressed: false,
+                    chunk_size: None,
+                    nonce: nonce.to_vec(),
+                };
+                let mut blob = header.encode(dek)?;
+                blob.extend_from_slice(&ciphertext);
+                Ok(blob)
+            }
+            BlobVersion::Legacy2S => Err(CryptoError::UnsupportedVersion(
+                "SCAP2S blobs are written with StreamBlobFormat".to_string(),
+            )),
+        }
+    }
+
+    /// Encrypts everything from `reader` into a chunked version 3 blob
+    ///
+    /// # Returns
+    /// Number of plaintext bytes encrypted
+    pub fn seal_stream<R: Read, W: Write>(
+        reader: &mut R,
+        mut writer: W,
+        dek: &[u8],
+        aad: &[u8],
+    ) -> CryptoResult<u64> {
+        let chunk_size = StreamBlobFormat::DEFAULT_CHUNK_SIZE;
+        let (mut encryptor, stream_header) = StreamEncryptor::without_prefix(&mut writer, dek, aad, chunk_size)?;
+
+        let header = BlobHeader {
+            version: BlobVersion::V3,
+            suite: AeadSuite::XChaCha20Poly1305,
+            compressed: false,
+            chunk_size: Some(chunk_size as u32),
+            nonce: stream_header,
+        };
+        // Nothing has reached the writer yet; the encryptor only writes whole chunks
+        encryptor.get_mut().write_all(&header.encode(dek)?).map_err(stream::from_io_error)?;
+
+        let copied = io::copy(reader, &mut encryptor).map_err(stream::from_io_error)?;
+        encryptor.finish()?;
+
+        Ok(copied)
+    }
+
+    /// Decrypts a blob held in memory, whatever its version
+    pub fn open(blob: &[u8], dek: &[u8], aad: &[u8]) -> CryptoResult<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        Self::open_stream(blob, &mut plaintext, dek, aad)?;
+        Ok(plaintext)
+    }
+
+    /// Decrypts a blob from `reader` into `writer`, whatever its version
+    ///
+    /// Chunked bodies are decrypted in bounded memory. Single-message bodies
+    /// are read whole.
+    ///
+    /// # Returns
+    /// Number of plaintext bytes written
+    pub fn open_stream<R: Read, W: Write>(
+        mut reader: R,
+        writer: &mut W,
+        dek: &[u8],
+        aad: &[u8],
+    ) -> CryptoResult<u64> {
+        let header = BlobHeader::read_from(&mut reader, dek)?;
+
+        if header.compressed {
+            return Err(CryptoError::UnsupportedVersion("Compressed blobs".to_string()));
+        }
+
+        match header.chunk_size {
+            Some(chunk_size) => {
+                let mut decryptor = StreamDecryptor::without_prefix(
+                    reader, &header.nonce, chunk_size as usize, dek, aad,
+                )?;
+                io::copy(&mut decryptor, writer).map_err(stream::from_io_error)
+            }
+            None => {
+                let mut ciphertext = Vec::new();
+                reader.read_to_end(&mut ciphertext)
+                    .map_err(|e| CryptoError::MalformedBlob(format!("Failed to read blob: {}", e)))?;
+                let plaintext = CryptoPrimitives::decrypt_aead(&ciphertext, &header.nonce, dek, aad)?;
+                writer.write_all(&plaintext)
+                    .map_err(|e| CryptoError::MalformedBlob(format!("Failed to write plaintext: {}", e)))?;
+                Ok(plaintext.len() as u64)
+            }
+        }
+    }
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> CryptoResult<()> {
+    reader.read_exact(buf)
+        .map_err(|_| CryptoError::MalformedBlob("Blob header too short".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (Vec<u8>, Vec<u8>) {
+        CryptoPrimitives::init();
+        (CryptoPrimitives::gen_dek(), CryptoPrimitives::gen_nonce())
+    }
+
+    #[test]
+    fn test_open_dispatches_on_every_version() {
+        let (dek, nonce) = setup();
+        let plaintext = b"hello versioned world".to_vec();
+
+        let legacy = VersionedBlob::seal(BlobVersion::Legacy2A, &plaintext, &nonce, &dek, b"meta").unwrap();
+        assert!(legacy.starts_with(b"SCAP2A"));
+
+        let v3 = VersionedBlob::seal(BlobVersion::V3, &plaintext, &nonce, &dek, b"meta").unwrap();
+        assert_eq!(BlobHeader::read_from(&mut &v3[..], &dek).unwrap().version, BlobVersion::V3);
+
+        let mut v3_chunked = Vec::new();
+        VersionedBlob::seal_stream(&mut &plaintext[..], &mut v3_chunked, &dek, b"meta").unwrap();
+
+        let mut legacy_stream = Vec::new();
+        StreamBlobFormat::encrypt(&mut &plaintext[..], &mut legacy_stream, &dek, b"meta").unwrap();
+
+        for blob in [legacy, v3, v3_chunked, legacy_stream] {
+            assert_eq!(VersionedBlob::open(&blob, &dek, b"meta").unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn test_header_mac_detects_tampering() {
+        let (dek, nonce) = setup();
+        let blob = VersionedBlob::seal(BlobVersion::V3, b"payload", &nonce, &dek, b"meta").unwrap();
+
+        // Flip the chunked flag: the header no longer matches its MAC
+        let mut flipped = blob.clone();
+        flipped[6] ^= BlobHeader::FLAG_CHUNKED;
+        assert!(matches!(
+            VersionedBlob::open(&flipped, &dek, b"meta"),
+            Err(CryptoError::Tampered(_))
+        ));
+
+        // Wrong DEK fails at the header, before any decryption
+        let other_dek = CryptoPrimitives::gen_dek();
+        assert!(matches!(
+            BlobHeader::read_from(&mut &blob[..], &other_dek),
+            Err(CryptoError::Tampered(_))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_versions_and_suites_rejected() {
+        let (dek, nonce) = setup();
+        let blob = VersionedBlob::seal(BlobVersion::V3, b"payload", &nonce, &dek, b"meta").unwrap();
+
+        let mut future_version = blob.clone();
+        future_version[4] = 9;
+        assert!(matches!(
+            VersionedBlob::open(&future_version, &dek, b"meta"),
+            Err(CryptoError::UnsupportedVersion(_))
+        ));
+
+        let mut unknown_suite = blob.clone();
+        unknown_suite[5] = 0x7f;
+        assert!(matches!(
+            VersionedBlob::open(&unknown_suite, &dek, b"meta"),
+            Err(CryptoError::UnsupportedVersion(_))
+        ));
+
+        assert!(matches!(
+            VersionedBlob::open(b"NOTABLOB-at-all-really-long-enough-for-a-header", &dek, b"meta"),
+            Err(CryptoError::MalformedBlob(_))
+        ));
+    }
+
+    #[test]
+    fn test_negotiation_falls_back_to_legacy() {
+        let modern = BlobVersion::advertised();
+        let legacy: Vec<String> = Vec::new();
+
+        assert_eq!(BlobVersion::negotiate([modern.as_slice(), modern.as_slice()]), BlobVersion::V3);
+        assert_eq!(BlobVersion::negotiate([modern.as_slice(), legacy.as_slice()]), BlobVersion::Legacy2A);
+    }
+}
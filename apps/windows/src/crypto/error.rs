@@ -0,0 +1,145 @@
+//! Crypto subsystem error hierarchy
+//!
+//! Every crypto API returns `CryptoError`. Each variant maps to a stable
+//! machine-readable code (`CryptoError::code`) so the Tauri frontend can pick
+//! the right message and telemetry bucket; serializing yields
+//! `{ "code": "...", "message": "..." }`.
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use thiserror::Error;
+use super::schema::SchemaError;
+
+pub type CryptoResult<T> = Result<T, CryptoError>;
+
+#[derive(Debug, Clone, Error)]
+pub enum CryptoError {
+    /// metaHash mismatch or AEAD/sealed-box authentication failure
+    #[error("Message tampered: {0}")]
+    Tampered(String),
+    /// Ed25519 signature does not verify against the sender's key
+    #[error("Signature verification failed")]
+    InvalidSignature,
+    /// No envelope addressed to this device
+    #[error("Device {0} is not a recipient")]
+    NotRecipient(String),
+    /// Sender device document does not belong to the message's senderDeviceId
+    #[error("Sender document {document} does not match senderDeviceId {claimed}")]
+    SenderMismatch { claimed: String, document: String },
+    /// Peer device keys differ from the locally pinned keys
+    #[error("Keys for device {0} changed since they were pinned; approval required")]
+    KeyChanged(String),
+    /// Pinned-key store could not be read or written
+    #[error("Pin store unavailable: {0}")]
+    PinStoreUnavailable(String),
+    /// Sender device has been revoked
+    #[error("Sender device {0} is revoked")]
+    SenderRevoked(String),
+    /// Message schema version this client cannot handle
+    #[error("Unsupported message version: {0}")]
+    UnsupportedVersion(String),
+    /// Message type this client cannot handle
+    #[error("Unsupported message type: {0}")]
+    UnsupportedMessageType(String),
+    /// Key material missing, unreadable or not yet provisioned
+    #[error("Key store unavailable: {0}")]
+    KeyStoreUnavailable(String),
+    /// Envelope opens with none of this device's box keys, typically one
+    /// sealed to a generation whose rotation grace period has expired
+    #[error("No box key opens the envelope: {0}")]
+    NoMatchingKey(String),
+    /// Key or nonce has the wrong length/encoding
+    #[error("Invalid key material: {0}")]
+    InvalidKey(String),
+    /// Blob does not follow a known format
+    #[error("Malformed blob: {0}")]
+    MalformedBlob(String),
+    /// Firestore document missing fields or failing validation
+    #[error("Malformed document: {0}")]
+    MalformedDocument(String),
+    /// Decrypted payload does not match its declared type
+    #[error("Invalid payload: {0}")]
+    InvalidPayload(String),
+    /// Send attempted with no recipient devices
+    #[error("No active devices to send to")]
+    NoRecipients,
+    /// Message already accepted once (re-delivery or replay)
+    #[error("Message {0} was already received")]
+    Replayed(String),
+    /// Signed createdAtClient outside the freshness window
+    #[error("Message outside freshness window: {0}")]
+    Stale(String),
+    /// Seen-message ledger could not be read or written
+    #[error("Replay ledger unavailable: {0}")]
+    LedgerUnavailable(String),
+    /// Pairing ceremony could not be verified or completed
+    #[error("Pairing failed: {0}")]
+    PairingFailed(String),
+    /// Revocation statement unsigned, untrusted or malformed
+    #[error("Invalid revocation: {0}")]
+    InvalidRevocation(String),
+    /// Revocation cache could not be read or written
+    #[error("Revocation store unavailable: {0}")]
+    RevocationStoreUnavailable(String),
+}
+
+impl CryptoError {
+    /// Stable machine-readable code for the frontend and telemetry
+    pub fn code(&self) -> &'static str {
+        match self {
+            CryptoError::Tampered(_) => "tampered",
+            CryptoError::InvalidSignature => "invalid_signature",
+            CryptoError::NotRecipient(_) => "not_recipient",
+            CryptoError::SenderMismatch { .. } => "sender_mismatch",
+            CryptoError::KeyChanged(_) => "key_changed",
+            CryptoError::PinStoreUnavailable(_) => "pin_store_unavailable",
+            CryptoError::SenderRevoked(_) => "sender_revoked",
+            CryptoError::UnsupportedVersion(_) => "unsupported_version",
+            CryptoError::UnsupportedMessageType(_) => "unsupported_message_type",
+            CryptoError::KeyStoreUnavailable(_) => "key_store_unavailable",
+            CryptoError::NoMatchingKey(_) => "no_matching_key",
+            CryptoError::InvalidKey(_) => "invalid_key",
+            CryptoError::MalformedBlob(_) => "malformed_blob",
+            CryptoError::MalformedDocument(_) => "malformed_document",
+            CryptoError::InvalidPayload(_) => "invalid_payload",
+            CryptoError::NoRecipients => "no_recipients",
+            CryptoError::Replayed(_) => "replayed",
+            CryptoError::Stale(_) => "stale",
+            CryptoError::LedgerUnavailable(_) => "ledger_unavailable",
+            CryptoError::PairingFailed(_) => "pairing_failed",
+            CryptoError::InvalidRevocation(_) => "invalid_revocation",
+            CryptoError::RevocationStoreUnavailable(_) => "revocation_store_unavailable",
+        }
+    }
+}
+
+impl From<SchemaError> for CryptoError {
+    fn from(e: SchemaError) -> Self {
+        match e {
+            SchemaError::UnsupportedType(kind) => CryptoError::UnsupportedMessageType(kind),
+            e => CryptoError::MalformedDocument(e.to_string()),
+        }
+    }
+}
+
+impl Serialize for CryptoError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CryptoError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_code_and_message() {
+        let err = CryptoError::NotRecipient("dev-123".to_string());
+        let value = serde_json::to_value(&err).unwrap();
+
+        assert_eq!(value["code"], "not_recipient");
+        assert_eq!(value["message"], "Device dev-123 is not a recipient");
+    }
+}
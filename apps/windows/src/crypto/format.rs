@@ -1,7 +1,9 @@
-/// Phase 2A blob format and canonical JSON utilities
+//! Phase 2A blob format and canonical JSON utilities
 
 use serde_json::{json, Value};
 use super::primitives::CryptoPrimitives;
+use super::error::{CryptoError, CryptoResult};
+use super::schema::{MediaInfo, MessageDoc, RetentionHints};
 
 pub struct BlobFormat;
 
@@ -20,9 +22,9 @@ impl BlobFormat {
     /// 
     /// # Returns
     /// Complete blob
-    pub fn create_blob(nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+    pub fn create_blob(nonce: &[u8], ciphertext: &[u8]) -> CryptoResult<Vec<u8>> {
         if nonce.len() != Self::NONCE_LEN {
-            return Err(format!("Nonce must be {} bytes", Self::NONCE_LEN));
+            return Err(CryptoError::InvalidKey(format!("Nonce must be {} bytes", Self::NONCE_LEN)));
         }
 
         let mut blob = Vec::with_capacity(Self::MAGIC_LEN + Self::NONCE_LEN + ciphertext.len());
@@ -37,14 +39,14 @@ impl BlobFormat {
     /// 
     /// # Returns
     /// Tuple of (nonce, ciphertext), or error if parsing fails
-    pub fn parse_blob(blob: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    pub fn parse_blob(blob: &[u8]) -> CryptoResult<(Vec<u8>, Vec<u8>)> {
         if blob.len() < Self::MAGIC_LEN + Self::NONCE_LEN {
-            return Err("Blob too short".to_string());
+            return Err(CryptoError::MalformedBlob("Blob too short".to_string()));
         }
 
         // Verify magic
         if &blob[0..Self::MAGIC_LEN] != Self::MAGIC {
-            return Err("Invalid blob magic".to_string());
+            return Err(CryptoError::MalformedBlob("Invalid blob magic".to_string()));
         }
 
         let nonce = blob[Self::MAGIC_LEN..Self::MAGIC_LEN + Self::NONCE_LEN].to_vec();
@@ -54,11 +56,28 @@ impl BlobFormat {
     }
 }
 
-/// Canonical metadata for Phase 2A (stable JSON key order)
+/// Builds the nested canonical `media` object (alphabetical key order)
+///
+/// `filename` is omitted when absent or empty, matching the schema's optional field.
+fn media_canonical_value(media: &MediaInfo) -> Value {
+    let mut map = serde_json::Map::new();
+    map.insert("ext".to_string(), json!(media.ext));
+    if let Some(filename) = media.filename.as_deref().filter(|f| !f.is_empty()) {
+        map.insert("filename".to_string(), json!(filename));
+    }
+    map.insert("height".to_string(), json!(media.height));
+    map.insert("width".to_string(), json!(media.width));
+    Value::Object(map)
+}
+
+/// Canonical metadata for Phase 2A/2B (stable JSON key order)
 pub struct CanonicalMetadata;
 
 impl CanonicalMetadata {
-    /// Creates canonical metadata JSON (alphabetical key order)
+    const ALG: &'static str = "xchacha20poly1305+sealedbox-x25519+ed25519";
+    const DEFAULT_MIME: &'static str = "application/octet-stream";
+
+    /// Creates canonical metadata JSON for text messages (alphabetical key order)
     /// 
     /// Order (ALPHABETICAL):
     /// - alg
@@ -78,24 +97,68 @@ impl CanonicalMetadata {
         storage_path: &str,
         size_bytes_plain: usize,
         created_at_client: &str,
+    ) -> String {
+        Self::create_canonical_json_typed(
+            message_id,
+            sender_device_id,
+            recipients,
+            storage_path,
+            size_bytes_plain,
+            created_at_client,
+            "text",
+            Self::DEFAULT_MIME,
+            None,
+            &RetentionHints::default(),
+        )
+    }
+
+    /// Creates canonical metadata JSON for any message type (alphabetical key order)
+    ///
+    /// Same ordering as `create_canonical_json`, with `ephemeral`, `expiresAt`
+    /// and `media` (each only if present) inserted between `createdAtClient`
+    /// and `messageId`, so messages without them hash as before. Output is
+    /// escaped the way Gson does by default so the Android encoder hashes
+    /// identical bytes (both are tested on `shared/remote-paste/canonical_vectors.json`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_canonical_json_typed(
+        message_id: &str,
+        sender_device_id: &str,
+        recipients: &[String],
+        storage_path: &str,
+        size_bytes_plain: usize,
+        created_at_client: &str,
+        message_type: &str,
+        mime: &str,
+        media: Option<&MediaInfo>,
+        retention: &RetentionHints,
     ) -> String {
         let mut sorted_recipients = recipients.to_vec();
         sorted_recipients.sort();
 
         // Build in alphabetical order
         let mut map = serde_json::Map::new();
-        map.insert("alg".to_string(), json!("xchacha20poly1305+sealedbox-x25519+ed25519"));
+        map.insert("alg".to_string(), json!(Self::ALG));
         map.insert("createdAtClient".to_string(), json!(created_at_client));
+        if let Some(ephemeral) = retention.ephemeral {
+            map.insert("ephemeral".to_string(), json!(ephemeral));
+        }
+        if let Some(expires_at) = &retention.expires_at {
+            map.insert("expiresAt".to_string(), json!(expires_at));
+        }
+        if let Some(media) = media {
+            map.insert("media".to_string(), media_canonical_value(media));
+        }
         map.insert("messageId".to_string(), json!(message_id));
-        map.insert("mime".to_string(), json!("application/octet-stream"));
+        map.insert("mime".to_string(), json!(mime));
         map.insert("recipients".to_string(), json!(sorted_recipients));
         map.insert("senderDeviceId".to_string(), json!(sender_device_id));
         map.insert("sizeBytesPlain".to_string(), json!(size_bytes_plain));
         map.insert("storagePath".to_string(), json!(storage_path));
-        map.insert("type".to_string(), json!("text"));
+        map.insert("type".to_string(), json!(message_type));
         map.insert("version".to_string(), json!("2A"));
 
-        serde_json::to_string(&Value::Object(map)).expect("Failed to serialize")
+        let json = serde_json::to_string(&Value::Object(map)).expect("Failed to serialize");
+        Self::escape_like_gson(&json)
     }
 
     /// Computes metaHash = SHA256(canonicalJson)
@@ -111,48 +174,61 @@ impl CanonicalMetadata {
 
     /// Reconstructs canonical JSON from Firestore document
     /// (for verification on receiver side)
-    pub fn from_firestore_doc(doc: &Value) -> Result<String, String> {
-        let message_id = doc.get("messageId")
-            .and_then(|v| v.as_str())
-            .ok_or("Missing messageId")?;
-        
-        let sender_device_id = doc.get("senderDeviceId")
-            .and_then(|v| v.as_str())
-            .ok_or("Missing senderDeviceId")?;
-        
-        let recipients = doc.get("recipients")
-            .and_then(|v| v.as_array())
-            .ok_or("Missing recipients")?
-            .iter()
-            .filter_map(|v| v.as_str().map(String::from))
-            .collect::<Vec<_>>();
-        
-        let storage_path = doc.get("storagePath")
-            .and_then(|v| v.as_str())
-            .ok_or("Missing storagePath")?;
-        
-        let size_bytes_plain = doc.get("sizeBytesPlain")
-            .and_then(|v| v.as_u64())
-            .ok_or("Missing sizeBytesPlain")? as usize;
-        
-        let created_at_client = doc.get("createdAtClient")
-            .and_then(|v| v.as_str())
-            .ok_or("Missing createdAtClient")?;
+    pub fn from_firestore_doc(doc: &Value) -> CryptoResult<String> {
+        let doc = MessageDoc::from_value(doc)?;
+        Self::from_message_doc(&doc)
+    }
 
-        Ok(Self::create_canonical_json(
-            message_id,
-            sender_device_id,
-            &recipients,
-            storage_path,
+    /// Reconstructs canonical JSON from a typed message document
+    pub fn from_message_doc(doc: &MessageDoc) -> CryptoResult<String> {
+        let missing = |what: &str| CryptoError::MalformedDocument(what.to_string());
+
+        let recipients = doc.recipients.device_ids()
+            .ok_or_else(|| missing("Recipients must be an explicit device list"))?;
+
+        let size_bytes_plain = doc.size_bytes_plain
+            .ok_or_else(|| missing("Missing sizeBytesPlain"))? as usize;
+
+        let created_at_client = doc.created_at_client.as_deref()
+            .ok_or_else(|| missing("Missing createdAtClient"))?;
+
+        Ok(Self::create_canonical_json_typed(
+            &doc.message_id,
+            &doc.sender_device_id,
+            recipients,
+            &doc.storage_path,
             size_bytes_plain,
             created_at_client,
+            doc.message_type.as_str(),
+            &doc.mime,
+            doc.media.as_ref(),
+            &doc.retention_hints(),
         ))
     }
+
+    /// Applies Gson's default HTML-safe escaping to serde_json output
+    ///
+    /// Gson escapes `<`, `>`, `&`, `=`, `'`, U+2028 and U+2029 as `\uXXXX`;
+    /// all other escapes already match serde_json. These characters can only
+    /// occur inside string literals, so replacing over the whole document is safe.
+    fn escape_like_gson(json: &str) -> String {
+        let mut out = String::with_capacity(json.len());
+        for c in json.chars() {
+            match c {
+                '<' | '>' | '&' | '=' | '\'' | '\u{2028}' | '\u{2029}' => {
+                    out.push_str(&format!("\\u{:04x}", c as u32));
+                }
+                _ => out.push(c),
+            }
+        }
+        out
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::{Engine, engine::general_purpose};
 
     #[test]
     fn test_canonical_json_alphabetical() {
@@ -177,4 +253,73 @@ mod tests {
         sorted_keys.sort();
         assert_eq!(keys, sorted_keys, "Keys not in alphabetical order");
     }
+
+    #[test]
+    fn test_text_canonical_unchanged_by_typed_builder() {
+        let recipients = ["dev-b".to_string(), "dev-a".to_string()];
+        let legacy = CanonicalMetadata::create_canonical_json(
+            "msg-1", "dev-a", &recipients, "users/uid/messages/msg-1.bin", 5, "2026-01-28T16:45:00Z",
+        );
+        let typed = CanonicalMetadata::create_canonical_json_typed(
+            "msg-1", "dev-a", &recipients, "users/uid/messages/msg-1.bin", 5, "2026-01-28T16:45:00Z",
+            "text", "application/octet-stream", None, &RetentionHints::default(),
+        );
+        assert_eq!(legacy, typed);
+    }
+
+    #[test]
+    fn test_retention_hints_bound_in_order() {
+        let retention = RetentionHints {
+            expires_at: Some("2026-01-28T16:50:00Z".to_string()),
+            ephemeral: Some(true),
+        };
+        let json = CanonicalMetadata::create_canonical_json_typed(
+            "msg-1", "dev-a", &["dev-a".to_string()], "users/uid/messages/msg-1.bin", 5,
+            "2026-01-28T16:45:00Z", "text", "application/octet-stream", None, &retention,
+        );
+        assert!(json.contains(
+            "\"createdAtClient\":\"2026-01-28T16:45:00Z\",\"ephemeral\":true,\"expiresAt\":\"2026-01-28T16:50:00Z\",\"messageId\""
+        ));
+    }
+
+    #[test]
+    fn test_media_filename_omitted_when_empty() {
+        let media = MediaInfo {
+            ext: "png".to_string(),
+            filename: Some(String::new()),
+            height: 10,
+            width: 20,
+        };
+        let json = CanonicalMetadata::create_canonical_json_typed(
+            "msg-1", "dev-a", &["dev-a".to_string()], "users/uid/messages/msg-1.bin", 5,
+            "2026-01-28T16:45:00Z", "image", "image/png", Some(&media), &RetentionHints::default(),
+        );
+        assert!(json.contains("\"media\":{\"ext\":\"png\",\"height\":10,\"width\":20}"));
+    }
+
+    #[test]
+    fn test_cross_language_golden_vectors() {
+        let fixture: Value = serde_json::from_str(include_str!(
+            "../../../../shared/remote-paste/canonical_vectors.json"
+        ))
+        .expect("Valid vector file");
+
+        for vector in fixture["vectors"].as_array().expect("vectors array") {
+            let name = vector["name"].as_str().unwrap();
+            let expected_json = vector["canonicalJson"].as_str().unwrap();
+            let expected_hash = general_purpose::STANDARD
+                .decode(vector["metaHash"].as_str().unwrap())
+                .unwrap();
+
+            let canonical = CanonicalMetadata::from_firestore_doc(&vector["doc"])
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+            assert_eq!(canonical, expected_json, "canonical JSON mismatch for {}", name);
+            assert!(
+                CanonicalMetadata::verify_meta_hash(&canonical, &expected_hash),
+                "metaHash mismatch for {}",
+                name
+            );
+        }
+    }
 }
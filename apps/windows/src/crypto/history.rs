@@ -0,0 +1,136 @@
+//! Local history encryption at rest
+//!
+//! Decrypted clips are stored sealed with XChaCha20-Poly1305 under the local
+//! history data key held by `KeyManager`. The row ID is the associated data,
+//! so a sealed value copied into another row fails to open. Each value
+//! records the key generation that sealed it, which lets `db::Database`
+//! re-key history row by row and then retire the old key.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use super::key_mgmt::{HistoryKey, KeyManager};
+use super::primitives::CryptoPrimitives;
+use super::error::{CryptoError, CryptoResult};
+
+const AAD_PREFIX: &str = "scap-history:";
+const NONCE_LEN: usize = 24;
+
+/// `nonce || ciphertext` and the history key generation that produced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedValue {
+    pub generation: u32,
+    pub bytes: Vec<u8>,
+}
+
+pub struct HistoryCipher {
+    key_manager: KeyManager,
+    /// Opening keys by generation (a generation's key never changes)
+    keys: Mutex<HashMap<u32, Vec<u8>>>,
+}
+
+impl HistoryCipher {
+    pub fn new(key_manager: KeyManager) -> Self {
+        HistoryCipher { key_manager, keys: Mutex::new(HashMap::new()) }
+    }
+
+    /// Current data key (created on first use)
+    pub fn current_key(&self) -> CryptoResult<HistoryKey> {
+        self.key_manager.history_key()
+    }
+
+    /// Seals `plaintext` for `row_id` under the current key
+    pub fn seal(&self, row_id: &str, plaintext: &[u8]) -> CryptoResult<SealedValue> {
+        Self::seal_with(&self.current_key()?, row_id, plaintext)
+    }
+
+    /// Seals `plaintext` for `row_id` under a specific key
+    pub fn seal_with(key: &HistoryKey, row_id: &str, plaintext: &[u8]) -> CryptoResult<SealedValue> {
+        let nonce = CryptoPrimitives::gen_nonce();
+        let ciphertext = CryptoPrimitives::encrypt_aead(plaintext, &nonce, &key.key, &Self::aad(row_id))?;
+
+        let mut bytes = nonce;
+        bytes.extend_from_slice(&ciphertext);
+        Ok(SealedValue { generation: key.generation, bytes })
+    }
+
+    /// Opens a value sealed for `row_id`
+    ///
+    /// # Returns
+    /// Plaintext, or `Tampered` if the value was modified or belongs to another row
+    pub fn open(&self, row_id: &str, sealed: &SealedValue) -> CryptoResult<Vec<u8>> {
+        if sealed.bytes.len() < NONCE_LEN {
+            return Err(CryptoError::Tampered(format!("Sealed history value for {} is truncated", row_id)));
+        }
+        let (nonce, ciphertext) = sealed.bytes.split_at(NONCE_LEN);
+        let key = self.key_for(sealed.generation)?;
+        CryptoPrimitives::decrypt_aead(ciphertext, nonce, &key, &Self::aad(row_id))
+    }
+
+    /// Generates a new current key (see `KeyManager::rotate_history_key`)
+    pub fn rotate(&self) -> CryptoResult<HistoryKey> {
+        self.key_manager.rotate_history_key()
+    }
+
+    /// Drops keys older than the current generation except `keep`
+    pub fn retire_keys(&self, keep: &[u32]) -> CryptoResult<()> {
+        self.key_manager.retire_history_keys(keep)?;
+        self.lock_keys()?.retain(|generation, _| keep.contains(generation));
+        Ok(())
+    }
+
+    fn key_for(&self, generation: u32) -> CryptoResult<Vec<u8>> {
+        if let Some(key) = self.lock_keys()?.get(&generation) {
+            return Ok(key.clone());
+        }
+        let key = self.key_manager.history_key_for(generation)?;
+        self.lock_keys()?.insert(generation, key.clone());
+        Ok(key)
+    }
+
+    fn lock_keys(&self) -> CryptoResult<std::sync::MutexGuard<'_, HashMap<u32, Vec<u8>>>> {
+        self.keys.lock()
+            .map_err(|_| CryptoError::KeyStoreUnavailable("History key cache lock poisoned".to_string()))
+    }
+
+    fn aad(row_id: &str) -> Vec<u8> {
+        format!("{}{}", AAD_PREFIX, row_id).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open_bound_to_row() {
+        CryptoPrimitives::init();
+        let cipher = HistoryCipher::new(KeyManager::in_memory());
+
+        let sealed = cipher.seal("row-1", b"clipboard text").unwrap();
+        assert_eq!(sealed.generation, 1);
+        assert_eq!(cipher.open("row-1", &sealed).unwrap(), b"clipboard text");
+
+        assert!(matches!(cipher.open("row-2", &sealed), Err(CryptoError::Tampered(_))));
+
+        let mut flipped = sealed.clone();
+        *flipped.bytes.last_mut().unwrap() ^= 1;
+        assert!(matches!(cipher.open("row-1", &flipped), Err(CryptoError::Tampered(_))));
+    }
+
+    #[test]
+    fn test_rotation_keeps_old_generation_until_retired() {
+        CryptoPrimitives::init();
+        let key_manager = KeyManager::in_memory();
+        let cipher = HistoryCipher::new(key_manager.clone());
+        let old = cipher.seal("row-1", b"old").unwrap();
+
+        let new_key = cipher.rotate().unwrap();
+        assert_eq!(cipher.seal("row-2", b"new").unwrap().generation, new_key.generation);
+        assert_eq!(cipher.open("row-1", &old).unwrap(), b"old");
+
+        cipher.retire_keys(&[]).unwrap();
+        assert!(cipher.open("row-1", &old).is_err());
+        // A fresh cipher over the same key store agrees
+        assert!(HistoryCipher::new(key_manager).open("row-1", &old).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Device identity provisioning
+//!
+//! On first run generates the Ed25519 signing keypair, the X25519 box keypair
+//! and a device UUID, persisting all of them through KeyManager. Later runs
+//! reuse what is stored, so `ensure_provisioned` is safe to call at every
+//! startup. `registration_doc` produces the `users/{uid}/devices/{deviceId}`
+//! document to write to Firestore, with `boxKeySig` binding the box key and
+//! key generation to the signing key and `signKeyChain` linking every sign
+//! key to the one before; `rotate` re-issues it after key rotation.
+
+use std::time::Duration;
+use base64::{Engine, engine::general_purpose};
+use super::primitives::CryptoPrimitives;
+use super::blob::BlobVersion;
+use super::key_mgmt::KeyManager;
+use super::schema::{DeviceDoc, DeviceStatus, Platform};
+use super::trust::BoxKeyBinding;
+use super::error::{CryptoError, CryptoResult};
+
+pub struct DeviceIdentity {
+    key_manager: KeyManager,
+}
+
+impl DeviceIdentity {
+    pub fn new(key_manager: KeyManager) -> Self {
+        DeviceIdentity { key_manager }
+    }
+
+    /// Provisions this device if needed and returns its device ID
+    ///
+    /// - No device ID stored: a new UUID v4 is assigned
+    /// - No key slots at all: both keypairs are generated
+    /// - Some key slots present but the current keypairs not readable:
+    ///   `KeyStoreUnavailable`, so a damaged store never replaces the
+    ///   identity peers have pinned
+    /// - Everything present: nothing is written
+    pub fn ensure_provisioned(&self) -> CryptoResult<String> {
+        let device_id = match self.key_manager.get_device_id()? {
+            Some(id) => id,
+            None => {
+                let id = uuid::Uuid::new_v4().to_string();
+                self.key_manager.store_device_id(&id)?;
+                id
+            }
+        };
+
+        if !self.key_manager.has_keys() {
+            if self.key_manager.has_key_material() {
+                return Err(CryptoError::KeyStoreUnavailable(
+                    "Key store is only partly readable; refusing to regenerate device keys".to_string(),
+                ));
+            }
+            let (sign_sk, sign_pk) = CryptoPrimitives::gen_sign_keypair();
+            let (box_sk, box_pk) = CryptoPrimitives::gen_box_keypair();
+            self.key_manager.store_sign_keys(&sign_sk, &sign_pk)?;
+            self.key_manager.store_box_keys(&box_sk, &box_pk)?;
+        }
+
+        Ok(device_id)
+    }
+
+    /// Builds the Firestore registration document for this device
+    ///
+    /// `createdAt` / `lastSeenAt` are left for the server to set.
+    ///
+    /// # Arguments
+    /// * `name` - User-friendly device name (see `default_device_name`)
+    pub fn registration_doc(&self, name: &str) -> CryptoResult<DeviceDoc> {
+        let device_id = self.ensure_provisioned()?;
+        let generation = self.key_manager.current_generation()?;
+        let sign_pk = self.key_manager.get_sign_public_key()?;
+        let box_pk = self.key_manager.get_box_public_key()?;
+        let box_key_sig = BoxKeyBinding::sign(
+            &device_id,
+            generation,
+            &box_pk,
+            &self.key_manager.get_sign_private_key()?,
+        )?;
+
+        let doc = DeviceDoc {
+            device_id,
+            name: name.to_string(),
+            platform: Platform::Windows,
+            created_at: None,
+            last_seen_at: None,
+            status: DeviceStatus::Active,
+            pub_sign_key: Some(general_purpose::STANDARD.encode(&sign_pk)),
+            pub_box_key: Some(general_purpose::STANDARD.encode(&box_pk)),
+            key_generation: Some(generation),
+            box_key_sig: Some(box_key_sig),
+            sign_key_chain: self.key_manager.sign_key_chain()?,
+            blob_versions: BlobVersion::advertised(),
+        };
+        doc.validate()?;
+
+        Ok(doc)
+    }
+
+    /// Rotates this device's keys and returns the updated registration document
+    ///
+    /// The returned document carries the new public keys and must be written
+    /// back to Firestore; the previous box key keeps decrypting for `grace`.
+    /// Pinned peers accept it through the extended `signKeyChain`.
+    pub fn rotate(&self, name: &str, grace: Duration) -> CryptoResult<DeviceDoc> {
+        self.ensure_provisioned()?;
+        self.key_manager.rotate_keys(grace)?;
+        self.registration_doc(name)
+    }
+
+    /// Default device name from the machine's hostname
+    pub fn default_device_name() -> String {
+        std::env::var("COMPUTERNAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .ok()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| "Windows PC".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use super::super::key_store::{KeyStore, MemoryKeyStore};
+    use super::super::trust::{KeyPinning, PinStatus};
+
+    /// Lets a test reach into the slots behind a KeyManager
+    struct SharedStore(Arc<MemoryKeyStore>);
+
+    impl KeyStore for SharedStore {
+        fn put(&self, name: &str, key_bytes: &[u8]) -> CryptoResult<()> {
+            self.0.put(name, key_bytes)
+        }
+
+        fn get(&self, name: &str) -> CryptoResult<Vec<u8>> {
+            self.0.get(name)
+        }
+
+        fn contains(&self, name: &str) -> bool {
+            self.0.contains(name)
+        }
+
+        fn remove(&self, name: &str) -> CryptoResult<()> {
+            self.0.remove(name)
+        }
+    }
+
+    #[test]
+    fn test_provisioning_is_idempotent() {
+        CryptoPrimitives::init();
+        let key_manager = KeyManager::in_memory();
+        let identity = DeviceIdentity::new(key_manager.clone());
+
+        let first = identity.registration_doc("Laptop").unwrap();
+        let second = identity.registration_doc("Laptop").unwrap();
+
+        assert_eq!(first, second);
+        assert!(uuid::Uuid::parse_str(&first.device_id).is_ok());
+        assert_eq!(key_manager.get_device_id().unwrap(), Some(first.device_id.clone()));
+    }
+
+    #[test]
+    fn test_registration_doc_shape() {
+        CryptoPrimitives::init();
+        let identity = DeviceIdentity::new(KeyManager::in_memory());
+        let doc = identity.registration_doc("Laptop").unwrap().to_value();
+
+        assert_eq!(doc["platform"], "windows");
+        assert_eq!(doc["status"], "active");
+        assert_eq!(doc["name"], "Laptop");
+        assert!(doc["pubSignKey"].is_string());
+        assert!(doc["pubBoxKey"].is_string());
+    }
+
+    #[test]
+    fn test_lost_keys_regenerated_under_same_device_id() {
+        CryptoPrimitives::init();
+        let key_manager = KeyManager::in_memory();
+        let identity = DeviceIdentity::new(key_manager.clone());
+
+        let before = identity.registration_doc("Laptop").unwrap();
+        key_manager.clear_keys().unwrap();
+        let after = identity.registration_doc("Laptop").unwrap();
+
+        assert_eq!(before.device_id, after.device_id);
+        assert_ne!(before.pub_sign_key, after.pub_sign_key);
+    }
+
+    #[test]
+    fn test_partly_readable_store_is_not_regenerated() {
+        CryptoPrimitives::init();
+        let store = Arc::new(MemoryKeyStore::new());
+        let key_manager = KeyManager::with_store(SharedStore(store.clone()));
+        let identity = DeviceIdentity::new(key_manager.clone());
+        let before = identity.registration_doc("Laptop").unwrap();
+
+        // Lost box slot
+        let box_private = store.get("box_private").unwrap();
+        store.remove("box_private").unwrap();
+        assert!(matches!(identity.ensure_provisioned(), Err(CryptoError::KeyStoreUnavailable(_))));
+        store.put("box_private", &box_private).unwrap();
+
+        // Unreadable generation pointer
+        store.put("box_key_generation", b"not a number").unwrap();
+        assert!(matches!(identity.ensure_provisioned(), Err(CryptoError::KeyStoreUnavailable(_))));
+        store.remove("box_key_generation").unwrap();
+
+        assert_eq!(identity.registration_doc("Laptop").unwrap(), before);
+    }
+
+    #[test]
+    fn test_rotate_republishes_new_box_key() {
+        CryptoPrimitives::init();
+        let identity = DeviceIdentity::new(KeyManager::in_memory());
+
+        let before = identity.registration_doc("Laptop").unwrap();
+        let after = identity.rotate("Laptop", Duration::from_secs(60)).unwrap();
+
+        assert_eq!(before.device_id, after.device_id);
+        assert_ne!(before.pub_sign_key, after.pub_sign_key);
+        assert_ne!(before.pub_box_key, after.pub_box_key);
+        assert_eq!((before.key_generation, after.key_generation), (Some(1), Some(2)));
+        assert_eq!(after.sign_key_chain.len(), 1);
+        assert_eq!(after.sign_key_chain[0].pub_sign_key, after.pub_sign_key.unwrap());
+    }
+
+    #[test]
+    fn test_peers_accept_rotated_box_key_without_approval() {
+        CryptoPrimitives::init();
+        let identity = DeviceIdentity::new(KeyManager::in_memory());
+        let pinning = KeyPinning::in_memory();
+
+        let before = identity.registration_doc("Laptop").unwrap();
+        pinning.pin(&before).unwrap();
+
+        // Peer offline for two rotations follows the whole chain
+        identity.rotate("Laptop", Duration::from_secs(60)).unwrap();
+        let after = identity.rotate("Laptop", Duration::from_secs(60)).unwrap();
+        assert_eq!(pinning.check(&after).unwrap(), PinStatus::Match);
+        let pin = pinning.pinned(&after.device_id).unwrap().unwrap();
+        assert_eq!(pin.pub_sign_key, after.pub_sign_key.clone().unwrap());
+        assert_eq!(pin.pub_box_key, after.pub_box_key.clone().unwrap());
+        assert_eq!(pin.key_generation, 3);
+
+        // The server replays the original document: the pin does not roll back
+        assert!(matches!(pinning.check(&before), Err(CryptoError::KeyChanged(_))));
+        assert_eq!(pinning.pinned(&after.device_id).unwrap().unwrap().key_generation, 3);
+    }
+}
@@ -1,215 +1,627 @@
-/// Windows key management using DPAPI (Data Protection API)
-/// 
-/// Stores encrypted private keys securely using Windows DPAPI.
-/// Keys are encrypted with user/machine context.
+//! Device key management
+//!
+//! Stores signing (Ed25519) and box (X25519) keypairs through a pluggable
+//! `KeyStore` backend chosen at construction time:
+//! - `with_passphrase` / `default_windows`: passphrase-protected files
+//! - `in_memory`: tests and ephemeral sessions
+//!
+//! Private keys are never written to disk unencrypted.
+//!
+//! The local history data key (see `history::HistoryCipher`) lives in the
+//! same store, versioned by generation so history can be re-keyed.
+//!
+//! Key rotation replaces both keypairs and keeps retired box keys for a
+//! grace period so envelopes sealed to the previous public key stay
+//! decryptable; every generation is recorded with its timestamps in the
+//! `key_generations` slot. The outgoing sign key signs the new one, and the
+//! `sign_key_chain` slot keeps those links for the device document so pinned
+//! peers can follow the rotation (see `trust::SignKeyRotation`).
+//!
+//! Rotated keys live in generation-numbered slots selected by the
+//! `box_key_generation` pointer. Rotation writes the new keypairs, then the
+//! generations record and chain, then moves the pointer; a rotation
+//! interrupted before the pointer moved is rolled back the next time
+//! generations are read.
 
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use base64::{Engine, engine::general_purpose};
+use super::key_store::{KeyStore, MemoryKeyStore, PassphraseFileKeyStore};
 use super::primitives::CryptoPrimitives;
+use super::schema::SignKeyLink;
+use super::trust::SignKeyRotation;
+use super::error::{CryptoError, CryptoResult};
+
+/// Default overlap during which retired box keys still decrypt
+pub const DEFAULT_ROTATION_GRACE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// One generation of device keys (timestamps are Unix seconds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyGeneration {
+    pub generation: u32,
+    pub created_at: i64,
+    pub retired_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub sign_public: String,  // base64
+    pub box_public: String,  // base64
+}
+
+impl KeyGeneration {
+    pub fn is_current(&self) -> bool {
+        self.retired_at.is_none()
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Box keypair usable for opening envelopes
+#[derive(Debug, Clone)]
+pub struct BoxKeys {
+    pub generation: u32,
+    pub public_key: Vec<u8>,
+    pub private_key: Vec<u8>,
+}
+
+/// Local data key for encrypting history at rest
+#[derive(Clone)]
+pub struct HistoryKey {
+    pub generation: u32,
+    pub key: Vec<u8>,
+}
 
+/// Cheap to clone; clones share the same backend
+#[derive(Clone)]
 pub struct KeyManager {
-    key_dir: PathBuf,
+    store: Arc<dyn KeyStore>,
 }
 
 impl KeyManager {
-    const SIGN_PRIVATE_FILE: &'static str = "sign_private.bin";
-    const SIGN_PUBLIC_FILE: &'static str = "sign_public.bin";
-    const BOX_PRIVATE_FILE: &'static str = "box_private.bin";
-    const BOX_PUBLIC_FILE: &'static str = "box_public.bin";
+    const SIGN_PRIVATE: &'static str = "sign_private";
+    const SIGN_PUBLIC: &'static str = "sign_public";
+    const BOX_PRIVATE: &'static str = "box_private";
+    const BOX_PUBLIC: &'static str = "box_public";
+    const DEVICE_ID: &'static str = "device_id";
+    const GENERATIONS: &'static str = "key_generations";
+    const BOX_GENERATION: &'static str = "box_key_generation";
+    const SIGN_KEY_CHAIN: &'static str = "sign_key_chain";
+    const HISTORY_KEY: &'static str = "history_key";
+    const HISTORY_GENERATION: &'static str = "history_key_generation";
+
+    /// Unnumbered slots holding device key material or its bookkeeping
+    const KEY_SLOTS: [&'static str; 7] = [
+        Self::SIGN_PRIVATE,
+        Self::SIGN_PUBLIC,
+        Self::BOX_PRIVATE,
+        Self::BOX_PUBLIC,
+        Self::BOX_GENERATION,
+        Self::GENERATIONS,
+        Self::SIGN_KEY_CHAIN,
+    ];
+
+    /// Pre-KeyStore releases wrote these as plain base64 files
+    const LEGACY_FILES: [(&'static str, &'static str); 4] = [
+        ("sign_private.bin", Self::SIGN_PRIVATE),
+        ("sign_public.bin", Self::SIGN_PUBLIC),
+        ("box_private.bin", Self::BOX_PRIVATE),
+        ("box_public.bin", Self::BOX_PUBLIC),
+    ];
+
+    /// Creates KeyManager over an arbitrary backend
+    pub fn with_store(store: impl KeyStore + 'static) -> Self {
+        KeyManager { store: Arc::new(store) }
+    }
 
-    /// Creates KeyManager with specified key directory
-    /// 
+    /// Creates KeyManager backed by memory only
+    pub fn in_memory() -> Self {
+        Self::with_store(MemoryKeyStore::new())
+    }
+
+    /// Creates KeyManager with passphrase-protected files in `key_dir`
+    ///
+    /// Legacy plaintext key files found in `key_dir` are imported into the
+    /// encrypted store and deleted.
+    ///
     /// # Arguments
     /// * `key_dir` - Directory to store encrypted keys
-    pub fn new(key_dir: impl AsRef<Path>) -> Result<Self, String> {
-        let key_dir = key_dir.as_ref().to_path_buf();
-        
-        // Create directory if it doesn't exist
-        fs::create_dir_all(&key_dir)
-            .map_err(|e| format!("Failed to create key directory: {}", e))?;
-
-        Ok(KeyManager { key_dir })
+    /// * `passphrase` - Passphrase the wrapping key is derived from
+    pub fn with_passphrase(key_dir: impl AsRef<Path>, passphrase: &str) -> CryptoResult<Self> {
+        let store = PassphraseFileKeyStore::open(key_dir.as_ref(), passphrase)?;
+        let manager = Self::with_store(store);
+        manager.migrate_legacy_files(key_dir.as_ref())?;
+        Ok(manager)
     }
 
     /// Creates default KeyManager in user's AppData (Windows)
-    pub fn default_windows() -> Result<Self, String> {
+    pub fn default_windows(passphrase: &str) -> CryptoResult<Self> {
         let app_data = dirs::data_dir()
-            .ok_or("Failed to get AppData directory")?;
-        
+            .ok_or_else(|| CryptoError::KeyStoreUnavailable("Failed to get AppData directory".to_string()))?;
+
         let key_dir = app_data.join("ScingOS").join("spectrocap_phase2a");
-        Self::new(key_dir)
+        Self::with_passphrase(key_dir, passphrase)
     }
 
-    /// Stores signing keypair (with DPAPI encryption)
-    pub fn store_sign_keys(&self, private_key: &[u8], public_key: &[u8]) -> Result<(), String> {
-        self.store_key(&self.sign_private_path(), private_key)?;
-        self.store_public_key(&self.sign_public_path(), public_key)?;
+    /// Stores signing keypair as the current generation
+    pub fn store_sign_keys(&self, private_key: &[u8], public_key: &[u8]) -> CryptoResult<()> {
+        let (private_slot, public_slot) = self.current_sign_slots()?;
+        self.store.put(&private_slot, private_key)?;
+        self.store.put(&public_slot, public_key)?;
         Ok(())
     }
 
-    /// Stores box keypair (with DPAPI encryption)
-    pub fn store_box_keys(&self, private_key: &[u8], public_key: &[u8]) -> Result<(), String> {
-        self.store_key(&self.box_private_path(), private_key)?;
-        self.store_public_key(&self.box_public_path(), public_key)?;
+    /// Stores box keypair as the current generation
+    pub fn store_box_keys(&self, private_key: &[u8], public_key: &[u8]) -> CryptoResult<()> {
+        let (private_slot, public_slot) = self.current_box_slots()?;
+        self.store.put(&private_slot, private_key)?;
+        self.store.put(&public_slot, public_key)?;
         Ok(())
     }
 
     /// Retrieves stored signing private key
-    pub fn get_sign_private_key(&self) -> Result<Vec<u8>, String> {
-        self.retrieve_key(&self.sign_private_path())
+    pub fn get_sign_private_key(&self) -> CryptoResult<Vec<u8>> {
+        self.store.get(&self.current_sign_slots()?.0)
     }
 
-    /// Retrieves stored signing public key (not encrypted)
-    pub fn get_sign_public_key(&self) -> Result<Vec<u8>, String> {
-        self.retrieve_public_key(&self.sign_public_path())
+    /// Retrieves stored signing public key
+    pub fn get_sign_public_key(&self) -> CryptoResult<Vec<u8>> {
+        self.store.get(&self.current_sign_slots()?.1)
     }
 
     /// Retrieves stored box private key
-    pub fn get_box_private_key(&self) -> Result<Vec<u8>, String> {
-        self.retrieve_key(&self.box_private_path())
+    pub fn get_box_private_key(&self) -> CryptoResult<Vec<u8>> {
+        self.store.get(&self.current_box_slots()?.0)
+    }
+
+    /// Retrieves stored box public key
+    pub fn get_box_public_key(&self) -> CryptoResult<Vec<u8>> {
+        self.store.get(&self.current_box_slots()?.1)
     }
 
-    /// Retrieves stored box public key (not encrypted)
-    pub fn get_box_public_key(&self) -> Result<Vec<u8>, String> {
-        self.retrieve_public_key(&self.box_public_path())
+    /// Stores this device's UUID alongside its keys
+    pub fn store_device_id(&self, device_id: &str) -> CryptoResult<()> {
+        self.store.put(Self::DEVICE_ID, device_id.as_bytes())
+    }
+
+    /// Retrieves this device's UUID, if provisioned
+    pub fn get_device_id(&self) -> CryptoResult<Option<String>> {
+        if !self.store.contains(Self::DEVICE_ID) {
+            return Ok(None);
+        }
+        let bytes = self.store.get(Self::DEVICE_ID)?;
+        String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| CryptoError::KeyStoreUnavailable("Stored device ID is not valid UTF-8".to_string()))
     }
 
     /// Checks if both signing and box keys exist
     pub fn has_keys(&self) -> bool {
-        self.sign_private_path().exists() && self.box_private_path().exists()
-    }
-
-    /// Clears all stored keys
-    pub fn clear_keys(&self) -> Result<(), String> {
-        for path in &[
-            self.sign_private_path(),
-            self.sign_public_path(),
-            self.box_private_path(),
-            self.box_public_path(),
-        ] {
-            if path.exists() {
-                fs::remove_file(path)
-                    .map_err(|e| format!("Failed to remove key file: {}", e))?;
-            }
+        self.current_sign_slots().is_ok_and(|(private_slot, _)| self.store.contains(&private_slot))
+            && self.current_box_slots().is_ok_and(|(private_slot, _)| self.store.contains(&private_slot))
+    }
+
+    /// Checks if any device key slot exists, readable or not
+    ///
+    /// `has_keys() == false` with this true means the store is only partly
+    /// readable, not empty.
+    pub fn has_key_material(&self) -> bool {
+        Self::KEY_SLOTS.iter().any(|name| self.store.contains(name))
+    }
+
+    /// Clears all stored keys, including retired generations
+    pub fn clear_keys(&self) -> CryptoResult<()> {
+        for generation in self.load_generations()? {
+            self.remove_generation_keys(generation.generation)?;
+        }
+        if let Some(generation) = self.box_generation_pointer()? {
+            self.remove_generation_keys(generation)?;
+        }
+
+        for name in &Self::KEY_SLOTS {
+            self.store.remove(name)?;
         }
         Ok(())
     }
 
-    // Private helpers
+    /// Recorded key generations, oldest first
+    ///
+    /// Keys provisioned before rotation existed are reported as generation 1.
+    pub fn key_generations(&self) -> CryptoResult<Vec<KeyGeneration>> {
+        self.recover_interrupted_rotation()?;
+        let mut generations = self.load_generations()?;
+        if generations.is_empty() && self.has_keys() {
+            generations.push(self.current_generation_record(1, Self::now())?);
+        }
+        Ok(generations)
+    }
 
-    fn sign_private_path(&self) -> PathBuf {
-        self.key_dir.join(Self::SIGN_PRIVATE_FILE)
+    /// Current key generation (1 until the first rotation)
+    pub fn current_generation(&self) -> CryptoResult<u32> {
+        self.recover_interrupted_rotation()?;
+        self.current_generation_number()
     }
 
-    fn sign_public_path(&self) -> PathBuf {
-        self.key_dir.join(Self::SIGN_PUBLIC_FILE)
+    /// Sign key rotations performed so far, oldest first
+    pub fn sign_key_chain(&self) -> CryptoResult<Vec<SignKeyLink>> {
+        self.recover_interrupted_rotation()?;
+        self.load_sign_key_chain()
     }
 
-    fn box_private_path(&self) -> PathBuf {
-        self.key_dir.join(Self::BOX_PRIVATE_FILE)
+    /// Rotates the sign and box keypairs
+    ///
+    /// The current box keypair is archived and keeps opening envelopes until
+    /// `grace` has elapsed. The new sign key is signed by the current one and
+    /// the link appended to `sign_key_chain`; both public keys must be
+    /// re-published in the device document. Expired generations are pruned.
+    /// Requires the device ID, which the chain links are bound to.
+    ///
+    /// # Returns
+    /// The new current generation
+    pub fn rotate_keys(&self, grace: Duration) -> CryptoResult<KeyGeneration> {
+        self.rotate_keys_at(Self::now(), grace)
     }
 
-    fn box_public_path(&self) -> PathBuf {
-        self.key_dir.join(Self::BOX_PUBLIC_FILE)
+    /// Removes retired generations whose grace period has elapsed
+    pub fn prune_expired_generations(&self) -> CryptoResult<()> {
+        self.prune_expired_generations_at(Self::now())
     }
 
-    /// Stores encrypted private key using DPAPI
-    /// 
-    /// On Windows, DPAPI encrypts with user/machine context.
-    /// On non-Windows systems, this is a no-op (plaintext storage for development).
-    fn store_key(&self, path: &Path, key_bytes: &[u8]) -> Result<(), String> {
-        #[cfg(target_os = "windows")]
-        {
-            // Use Windows DPAPI
-            use std::os::windows::ffi::OsStrExt;
-            use std::ffi::OsStr;
-            use winapi::um::dpapi::CryptProtectData;
-            use winapi::um::wincrypt::{CRYPT_UI_PROTECT_FAIL_IF_DKML};
+    /// Box keypairs to try when opening an envelope: current first, then
+    /// unexpired retired generations from newest to oldest
+    pub fn decryption_box_keys(&self) -> CryptoResult<Vec<BoxKeys>> {
+        self.recover_interrupted_rotation()?;
+        let now = Self::now();
+        let mut keys = vec![BoxKeys {
+            generation: self.current_generation_number()?,
+            public_key: self.get_box_public_key()?,
+            private_key: self.get_box_private_key()?,
+        }];
+
+        let mut retired: Vec<KeyGeneration> = self.load_generations()?
+            .into_iter()
+            .filter(|g| !g.is_current() && !g.is_expired(now))
+            .collect();
+        retired.sort_by_key(|g| std::cmp::Reverse(g.generation));
+
+        for generation in retired {
+            let private_slot = Self::generation_slot(Self::BOX_PRIVATE, generation.generation);
+            let public_slot = Self::generation_slot(Self::BOX_PUBLIC, generation.generation);
+            if !self.store.contains(&private_slot) {
+                continue;
+            }
+            keys.push(BoxKeys {
+                generation: generation.generation,
+                public_key: self.store.get(&public_slot)?,
+                private_key: self.store.get(&private_slot)?,
+            });
+        }
+
+        Ok(keys)
+    }
 
-            // Encode with DPAPI
-            let encrypted = dpapi_protect(key_bytes)?;
-            fs::write(path, &encrypted)
-                .map_err(|e| format!("Failed to write key file: {}", e))?;
+    /// Current history data key, created on first use
+    pub fn history_key(&self) -> CryptoResult<HistoryKey> {
+        match self.current_history_generation()? {
+            Some(generation) => Ok(HistoryKey { generation, key: self.history_key_for(generation)? }),
+            None => self.rotate_history_key(),
         }
+    }
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            // For development on non-Windows: just base64 encode (NOT SECURE!)
-            // In production, use appropriate OS key storage
-            let encoded = general_purpose::STANDARD.encode(key_bytes);
-            fs::write(path, &encoded)
-                .map_err(|e| format!("Failed to write key file: {}", e))?;
+    /// History data key of a specific generation (current or not yet retired)
+    pub fn history_key_for(&self, generation: u32) -> CryptoResult<Vec<u8>> {
+        let slot = Self::generation_slot(Self::HISTORY_KEY, generation);
+        if !self.store.contains(&slot) {
+            return Err(CryptoError::KeyStoreUnavailable(format!(
+                "History key generation {} not found",
+                generation
+            )));
         }
+        self.store.get(&slot)
+    }
 
+    /// Generates a new current history data key
+    ///
+    /// Earlier generations stay available until `retire_history_keys`, so
+    /// rows sealed under them can still be opened while they are re-sealed.
+    pub fn rotate_history_key(&self) -> CryptoResult<HistoryKey> {
+        let generation = self.current_history_generation()?.map_or(1, |g| g + 1);
+        let key = CryptoPrimitives::gen_dek();
+
+        self.store.put(&Self::generation_slot(Self::HISTORY_KEY, generation), &key)?;
+        self.store.put(Self::HISTORY_GENERATION, generation.to_string().as_bytes())?;
+        Ok(HistoryKey { generation, key })
+    }
+
+    /// Removes history keys older than the current generation
+    ///
+    /// # Arguments
+    /// * `keep` - Generations still referenced by stored data
+    pub fn retire_history_keys(&self, keep: &[u32]) -> CryptoResult<()> {
+        let Some(current) = self.current_history_generation()? else {
+            return Ok(());
+        };
+        for generation in (1..current).filter(|g| !keep.contains(g)) {
+            self.store.remove(&Self::generation_slot(Self::HISTORY_KEY, generation))?;
+        }
         Ok(())
     }
 
-    /// Stores unencrypted public key (public keys don't need encryption)
-    fn store_public_key(&self, path: &Path, key_bytes: &[u8]) -> Result<(), String> {
-        let encoded = general_purpose::STANDARD.encode(key_bytes);
-        fs::write(path, &encoded)
-            .map_err(|e| format!("Failed to write public key file: {}", e))?;
+    // Private helpers
+
+    fn current_history_generation(&self) -> CryptoResult<Option<u32>> {
+        if !self.store.contains(Self::HISTORY_GENERATION) {
+            return Ok(None);
+        }
+        let bytes = self.store.get(Self::HISTORY_GENERATION)?;
+        std::str::from_utf8(&bytes).ok()
+            .and_then(|s| s.parse().ok())
+            .map(Some)
+            .ok_or_else(|| CryptoError::KeyStoreUnavailable("Corrupted history key generation".to_string()))
+    }
+
+    fn rotate_keys_at(&self, now: i64, grace: Duration) -> CryptoResult<KeyGeneration> {
+        if !self.has_keys() {
+            return Err(CryptoError::KeyStoreUnavailable("No keys to rotate".to_string()));
+        }
+
+        let device_id = self.get_device_id()?
+            .ok_or_else(|| CryptoError::KeyStoreUnavailable("Device ID not provisioned".to_string()))?;
+
+        let mut generations = self.key_generations()?;
+        let current_number = self.current_generation_number()?;
+        let new_number = generations.iter()
+            .map(|g| g.generation)
+            .max()
+            .unwrap_or(current_number) + 1;
+        self.move_to_generation_slots(current_number)?;
+
+        // Step 1: New keypairs in their own slots; nothing points at them yet
+        let (sign_sk, sign_pk) = CryptoPrimitives::gen_sign_keypair();
+        let (box_sk, box_pk) = CryptoPrimitives::gen_box_keypair();
+        self.store.put(&Self::generation_slot(Self::SIGN_PRIVATE, new_number), &sign_sk)?;
+        self.store.put(&Self::generation_slot(Self::SIGN_PUBLIC, new_number), &sign_pk)?;
+        self.store.put(&Self::generation_slot(Self::BOX_PRIVATE, new_number), &box_sk)?;
+        self.store.put(&Self::generation_slot(Self::BOX_PUBLIC, new_number), &box_pk)?;
+
+        // Step 2: Hand the identity over to the new sign key and record the
+        // new generation, retiring the current one for the grace period
+        let mut chain = self.load_sign_key_chain()?;
+        chain.push(SignKeyRotation::sign(&device_id, new_number, &sign_pk, now, &self.get_sign_private_key()?)?);
+        self.save_sign_key_chain(&chain)?;
+
+        for generation in generations.iter_mut().filter(|g| g.is_current()) {
+            generation.retired_at = Some(now);
+            generation.expires_at = Some(now + grace.as_secs() as i64);
+        }
+        let new_generation = KeyGeneration {
+            generation: new_number,
+            created_at: now,
+            retired_at: None,
+            expires_at: None,
+            sign_public: general_purpose::STANDARD.encode(&sign_pk),
+            box_public: general_purpose::STANDARD.encode(&box_pk),
+        };
+        generations.push(new_generation.clone());
+        self.save_generations(&generations)?;
+
+        // Step 3: Switch the current pointer
+        self.store.put(Self::BOX_GENERATION, new_number.to_string().as_bytes())?;
+
+        self.prune_expired_generations_at(now)?;
+        Ok(new_generation)
+    }
+
+    /// Moves keys provisioned before generation slots existed into the
+    /// slots of `generation` and points at them
+    fn move_to_generation_slots(&self, generation: u32) -> CryptoResult<()> {
+        if self.store.contains(Self::BOX_GENERATION) {
+            return Ok(());
+        }
+
+        let slots = [Self::SIGN_PRIVATE, Self::SIGN_PUBLIC, Self::BOX_PRIVATE, Self::BOX_PUBLIC];
+        for slot in slots {
+            self.store.put(&Self::generation_slot(slot, generation), &self.store.get(slot)?)?;
+        }
+        self.store.put(Self::BOX_GENERATION, generation.to_string().as_bytes())?;
+        for slot in slots {
+            self.store.remove(slot)?;
+        }
         Ok(())
     }
 
-    /// Retrieves encrypted private key and decrypts
-    fn retrieve_key(&self, path: &Path) -> Result<Vec<u8>, String> {
-        let encrypted = fs::read(path)
-            .map_err(|e| format!("Failed to read key file: {}", e))?;
+    /// Rolls back a rotation that recorded its generation but never switched
+    /// the pointer; its public key was never published
+    fn recover_interrupted_rotation(&self) -> CryptoResult<()> {
+        let Some(current) = self.box_generation_pointer()? else {
+            return Ok(());
+        };
+        let generations = self.load_generations()?;
+        let chain = self.load_sign_key_chain()?;
+        if generations.iter().all(|g| g.generation <= current) && chain.iter().all(|l| l.generation <= current) {
+            return Ok(());
+        }
+
+        let (abandoned, mut kept): (Vec<_>, Vec<_>) = generations
+            .into_iter()
+            .partition(|g| g.generation > current);
+        for generation in &abandoned {
+            self.remove_generation_keys(generation.generation)?;
+        }
+        for generation in kept.iter_mut().filter(|g| g.generation == current) {
+            generation.retired_at = None;
+            generation.expires_at = None;
+        }
+        self.save_generations(&kept)?;
+
+        let chain: Vec<_> = chain.into_iter().filter(|l| l.generation <= current).collect();
+        self.save_sign_key_chain(&chain)
+    }
+
+    fn prune_expired_generations_at(&self, now: i64) -> CryptoResult<()> {
+        self.recover_interrupted_rotation()?;
+        let generations = self.load_generations()?;
+        let (expired, kept): (Vec<_>, Vec<_>) = generations
+            .into_iter()
+            .partition(|g| !g.is_current() && g.is_expired(now));
 
-        #[cfg(target_os = "windows")]
-        {
-            dpapi_unprotect(&encrypted)
+        if expired.is_empty() {
+            return Ok(());
         }
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            // For development: just base64 decode
-            general_purpose::STANDARD.decode(&encrypted)
-                .map_err(|e| format!("Failed to decode key: {}", e))
+        for generation in &expired {
+            self.remove_generation_keys(generation.generation)?;
         }
+        self.save_generations(&kept)
     }
 
-    /// Retrieves unencrypted public key
-    fn retrieve_public_key(&self, path: &Path) -> Result<Vec<u8>, String> {
-        let encoded = fs::read_to_string(path)
-            .map_err(|e| format!("Failed to read public key file: {}", e))?;
-        general_purpose::STANDARD.decode(&encoded)
-            .map_err(|e| format!("Failed to decode public key: {}", e))
+    fn current_generation_number(&self) -> CryptoResult<u32> {
+        if let Some(generation) = self.box_generation_pointer()? {
+            return Ok(generation);
+        }
+        Ok(self.load_generations()?
+            .iter()
+            .find(|g| g.is_current())
+            .map_or(1, |g| g.generation))
     }
-}
 
-/// DPAPI helper (Windows-only)
-#[cfg(target_os = "windows")]
-fn dpapi_protect(data: &[u8]) -> Result<Vec<u8>, String> {
-    // Placeholder: Would need winapi/dpapi binding
-    // For Phase 2A MVP, we'll use a simple approach
-    Ok(data.to_vec())
-}
+    fn box_generation_pointer(&self) -> CryptoResult<Option<u32>> {
+        if !self.store.contains(Self::BOX_GENERATION) {
+            return Ok(None);
+        }
+        let bytes = self.store.get(Self::BOX_GENERATION)?;
+        std::str::from_utf8(&bytes).ok()
+            .and_then(|s| s.parse().ok())
+            .map(Some)
+            .ok_or_else(|| CryptoError::KeyStoreUnavailable("Corrupted box key generation".to_string()))
+    }
+
+    /// Private and public slots of the current sign keypair
+    ///
+    /// Generation slots when the current generation has them; keys from
+    /// before sign key rotation stay in the unnumbered slots.
+    fn current_sign_slots(&self) -> CryptoResult<(String, String)> {
+        if let Some(generation) = self.box_generation_pointer()? {
+            let private_slot = Self::generation_slot(Self::SIGN_PRIVATE, generation);
+            if self.store.contains(&private_slot) {
+                return Ok((private_slot, Self::generation_slot(Self::SIGN_PUBLIC, generation)));
+            }
+        }
+        Ok((Self::SIGN_PRIVATE.to_string(), Self::SIGN_PUBLIC.to_string()))
+    }
+
+    /// Private and public slots of the current box keypair
+    fn current_box_slots(&self) -> CryptoResult<(String, String)> {
+        Ok(match self.box_generation_pointer()? {
+            Some(generation) => (
+                Self::generation_slot(Self::BOX_PRIVATE, generation),
+                Self::generation_slot(Self::BOX_PUBLIC, generation),
+            ),
+            None => (Self::BOX_PRIVATE.to_string(), Self::BOX_PUBLIC.to_string()),
+        })
+    }
+
+    fn current_generation_record(&self, generation: u32, now: i64) -> CryptoResult<KeyGeneration> {
+        Ok(KeyGeneration {
+            generation,
+            created_at: now,
+            retired_at: None,
+            expires_at: None,
+            sign_public: general_purpose::STANDARD.encode(self.get_sign_public_key()?),
+            box_public: general_purpose::STANDARD.encode(self.get_box_public_key()?),
+        })
+    }
+
+    fn load_generations(&self) -> CryptoResult<Vec<KeyGeneration>> {
+        if !self.store.contains(Self::GENERATIONS) {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice(&self.store.get(Self::GENERATIONS)?)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Corrupted key generation record: {}", e)))
+    }
+
+    fn save_generations(&self, generations: &[KeyGeneration]) -> CryptoResult<()> {
+        let bytes = serde_json::to_vec(generations)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to encode key generations: {}", e)))?;
+        self.store.put(Self::GENERATIONS, &bytes)
+    }
 
-#[cfg(target_os = "windows")]
-fn dpapi_unprotect(encrypted: &[u8]) -> Result<Vec<u8>, String> {
-    // Placeholder: Would need winapi/dpapi binding
-    // For Phase 2A MVP, we'll use a simple approach
-    Ok(encrypted.to_vec())
+    fn load_sign_key_chain(&self) -> CryptoResult<Vec<SignKeyLink>> {
+        if !self.store.contains(Self::SIGN_KEY_CHAIN) {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice(&self.store.get(Self::SIGN_KEY_CHAIN)?)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Corrupted sign key chain: {}", e)))
+    }
+
+    fn save_sign_key_chain(&self, chain: &[SignKeyLink]) -> CryptoResult<()> {
+        let bytes = serde_json::to_vec(chain)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to encode sign key chain: {}", e)))?;
+        self.store.put(Self::SIGN_KEY_CHAIN, &bytes)
+    }
+
+    fn remove_generation_keys(&self, generation: u32) -> CryptoResult<()> {
+        for slot in [Self::SIGN_PRIVATE, Self::SIGN_PUBLIC, Self::BOX_PRIVATE, Self::BOX_PUBLIC] {
+            self.store.remove(&Self::generation_slot(slot, generation))?;
+        }
+        Ok(())
+    }
+
+    fn generation_slot(base: &str, generation: u32) -> String {
+        format!("{}.g{}", base, generation)
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    /// Imports legacy base64 key files into the store, then deletes them
+    fn migrate_legacy_files(&self, key_dir: &Path) -> CryptoResult<()> {
+        for (file, slot) in Self::LEGACY_FILES {
+            let path = key_dir.join(file);
+            if !path.exists() {
+                continue;
+            }
+
+            if !self.store.contains(slot) {
+                let encoded = fs::read_to_string(&path)
+                    .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to read legacy key file: {}", e)))?;
+                let key_bytes = general_purpose::STANDARD.decode(encoded.trim())
+                    .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to decode legacy key file: {}", e)))?;
+                self.store.put(slot, &key_bytes)?;
+            }
+
+            fs::remove_file(&path)
+                .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to remove legacy key file: {}", e)))?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use tempfile::TempDir;
+    use super::super::primitives::CryptoPrimitives;
 
     #[test]
     fn test_key_storage_roundtrip() {
+        CryptoPrimitives::init();
         let temp_dir = TempDir::new().unwrap();
-        let manager = KeyManager::new(temp_dir.path()).unwrap();
+        let manager = KeyManager::with_passphrase(temp_dir.path(), "passphrase").unwrap();
 
         let sign_private = vec![1u8; 32];
         let sign_public = vec![2u8; 32];
 
         manager.store_sign_keys(&sign_private, &sign_public).unwrap();
+        assert!(!manager.has_keys());  // box keys still missing
+
+        manager.store_box_keys(&[3u8; 32], &[4u8; 32]).unwrap();
         assert!(manager.has_keys());
 
         let retrieved_private = manager.get_sign_private_key().unwrap();
@@ -218,4 +630,169 @@ mod tests {
         assert_eq!(sign_private, retrieved_private);
         assert_eq!(sign_public, retrieved_public);
     }
+
+    #[test]
+    fn test_legacy_plaintext_files_migrated() {
+        CryptoPrimitives::init();
+        let temp_dir = TempDir::new().unwrap();
+        let legacy_path = temp_dir.path().join("box_private.bin");
+        fs::write(&legacy_path, general_purpose::STANDARD.encode([5u8; 32])).unwrap();
+
+        let manager = KeyManager::with_passphrase(temp_dir.path(), "passphrase").unwrap();
+
+        assert_eq!(manager.get_box_private_key().unwrap(), vec![5u8; 32]);
+        assert!(!legacy_path.exists());
+    }
+
+    #[test]
+    fn test_in_memory_clear_keys() {
+        let manager = KeyManager::in_memory();
+        manager.store_sign_keys(&[1u8; 64], &[2u8; 32]).unwrap();
+        manager.store_box_keys(&[3u8; 32], &[4u8; 32]).unwrap();
+        assert!(manager.has_keys());
+
+        manager.clear_keys().unwrap();
+        assert!(!manager.has_keys());
+    }
+
+    #[test]
+    fn test_history_key_rotation_and_retirement() {
+        CryptoPrimitives::init();
+        let manager = KeyManager::in_memory();
+
+        let first = manager.history_key().unwrap();
+        assert_eq!(first.generation, 1);
+        assert_eq!(manager.history_key().unwrap().key, first.key);
+
+        let second = manager.rotate_history_key().unwrap();
+        assert_eq!(second.generation, 2);
+        assert_ne!(second.key, first.key);
+        assert_eq!(manager.history_key_for(1).unwrap(), first.key);
+
+        manager.retire_history_keys(&[]).unwrap();
+        assert!(manager.history_key_for(1).is_err());
+        assert_eq!(manager.history_key().unwrap().key, second.key);
+    }
+
+    fn provisioned_manager() -> KeyManager {
+        CryptoPrimitives::init();
+        let manager = KeyManager::in_memory();
+        let (sign_sk, sign_pk) = CryptoPrimitives::gen_sign_keypair();
+        let (box_sk, box_pk) = CryptoPrimitives::gen_box_keypair();
+        manager.store_sign_keys(&sign_sk, &sign_pk).unwrap();
+        manager.store_box_keys(&box_sk, &box_pk).unwrap();
+        manager.store_device_id("dev-pc").unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_rotation_keeps_previous_box_key_during_grace() {
+        let manager = provisioned_manager();
+        let old_box_pk = manager.get_box_public_key().unwrap();
+        let old_sign_pk = manager.get_sign_public_key().unwrap();
+
+        let new_generation = manager.rotate_keys(Duration::from_secs(3600)).unwrap();
+        assert_eq!(new_generation.generation, 2);
+        assert_eq!(manager.current_generation().unwrap(), 2);
+        assert_ne!(manager.get_box_public_key().unwrap(), old_box_pk);
+        assert_ne!(manager.get_sign_public_key().unwrap(), old_sign_pk);
+
+        // The old sign key vouches for the new one
+        let chain = manager.sign_key_chain().unwrap();
+        assert_eq!(chain.len(), 1);
+        assert_eq!(chain[0].generation, 2);
+        assert_eq!(chain[0].pub_sign_key, new_generation.sign_public);
+        assert!(SignKeyRotation::verify("dev-pc", &chain[0], &old_sign_pk));
+
+        let keys = manager.decryption_box_keys().unwrap();
+        assert_eq!(keys.iter().map(|k| k.generation).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(keys[1].public_key, old_box_pk);
+
+        let generations = manager.key_generations().unwrap();
+        assert_eq!(generations.len(), 2);
+        assert!(generations[0].retired_at.is_some());
+        assert!(generations[1].is_current());
+    }
+
+    #[test]
+    fn test_expired_generations_pruned() {
+        let manager = provisioned_manager();
+        let now = chrono::Utc::now().timestamp();
+
+        manager.rotate_keys_at(now - 7200, Duration::from_secs(3600)).unwrap();
+        manager.rotate_keys_at(now, Duration::from_secs(3600)).unwrap();
+
+        // Generation 1 expired an hour ago; generation 2 is within its grace
+        let keys = manager.decryption_box_keys().unwrap();
+        assert_eq!(keys.iter().map(|k| k.generation).collect::<Vec<_>>(), vec![3, 2]);
+        assert_eq!(manager.key_generations().unwrap().len(), 2);
+    }
+
+    /// Memory store whose writes to one slot fail while `fail` is set
+    struct FailingSlotStore {
+        inner: MemoryKeyStore,
+        slot: &'static str,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl KeyStore for FailingSlotStore {
+        fn put(&self, name: &str, key_bytes: &[u8]) -> CryptoResult<()> {
+            if name == self.slot && self.fail.load(Ordering::SeqCst) {
+                return Err(CryptoError::KeyStoreUnavailable("Simulated crash".to_string()));
+            }
+            self.inner.put(name, key_bytes)
+        }
+
+        fn get(&self, name: &str) -> CryptoResult<Vec<u8>> {
+            self.inner.get(name)
+        }
+
+        fn contains(&self, name: &str) -> bool {
+            self.inner.contains(name)
+        }
+
+        fn remove(&self, name: &str) -> CryptoResult<()> {
+            self.inner.remove(name)
+        }
+    }
+
+    #[test]
+    fn test_rotation_interrupted_before_switch_is_rolled_back() {
+        CryptoPrimitives::init();
+        let fail = Arc::new(AtomicBool::new(false));
+        let manager = KeyManager::with_store(FailingSlotStore {
+            inner: MemoryKeyStore::new(),
+            slot: KeyManager::BOX_GENERATION,
+            fail: fail.clone(),
+        });
+        let (sign_sk, sign_pk) = CryptoPrimitives::gen_sign_keypair();
+        let (box_sk, box_pk) = CryptoPrimitives::gen_box_keypair();
+        manager.store_sign_keys(&sign_sk, &sign_pk).unwrap();
+        manager.store_box_keys(&box_sk, &box_pk).unwrap();
+        manager.store_device_id("dev-pc").unwrap();
+        manager.rotate_keys(Duration::from_secs(3600)).unwrap();
+        let published_box_pk = manager.get_box_public_key().unwrap();
+        let published_sign_pk = manager.get_sign_public_key().unwrap();
+
+        // Generations record saved, pointer switch lost
+        fail.store(true, Ordering::SeqCst);
+        assert!(manager.rotate_keys(Duration::from_secs(3600)).is_err());
+        fail.store(false, Ordering::SeqCst);
+
+        assert_eq!(manager.get_box_public_key().unwrap(), published_box_pk);
+        assert_eq!(manager.get_sign_public_key().unwrap(), published_sign_pk);
+        assert_eq!(manager.sign_key_chain().unwrap().len(), 1);
+        let generations = manager.key_generations().unwrap();
+        assert_eq!(generations.iter().map(|g| g.generation).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(generations[1].is_current());
+        assert_eq!(generations[1].box_public, general_purpose::STANDARD.encode(&published_box_pk));
+        assert_eq!(
+            manager.decryption_box_keys().unwrap().iter().map(|k| k.generation).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+
+        let retried = manager.rotate_keys(Duration::from_secs(3600)).unwrap();
+        assert_eq!(retried.generation, 3);
+        assert_eq!(general_purpose::STANDARD.encode(manager.get_box_public_key().unwrap()), retried.box_public);
+    }
 }
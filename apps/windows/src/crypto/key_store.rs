@@ -0,0 +1,316 @@
+//! Pluggable key storage backends for KeyManager
+//!
+//! - `PassphraseFileKeyStore`: one file per key slot, each wrapped with
+//!   XChaCha20-Poly1305 under an Argon2id-derived key (production)
+//! - `MemoryKeyStore`: process-local map, never touches disk (tests)
+//!
+//! Slots are opaque named byte strings; KeyManager decides what goes in them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use base64::{Engine, engine::general_purpose};
+use sodiumoxide::crypto::pwhash::argon2id13;
+use super::primitives::CryptoPrimitives;
+use super::error::{CryptoError, CryptoResult};
+
+/// Storage backend for key material
+pub trait KeyStore: Send + Sync {
+    /// Stores (or replaces) a key slot
+    fn put(&self, name: &str, key_bytes: &[u8]) -> CryptoResult<()>;
+
+    /// Retrieves a key slot; `KeyStoreUnavailable` if missing
+    fn get(&self, name: &str) -> CryptoResult<Vec<u8>>;
+
+    /// Checks whether a key slot exists
+    fn contains(&self, name: &str) -> bool;
+
+    /// Removes a key slot (no-op if missing)
+    fn remove(&self, name: &str) -> CryptoResult<()>;
+}
+
+/// In-memory backend (tests and ephemeral sessions)
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    slots: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn put(&self, name: &str, key_bytes: &[u8]) -> CryptoResult<()> {
+        self.slots.lock().unwrap().insert(name.to_string(), key_bytes.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> CryptoResult<Vec<u8>> {
+        self.slots.lock().unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| CryptoError::KeyStoreUnavailable(format!("Key {} not found", name)))
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.slots.lock().unwrap().contains_key(name)
+    }
+
+    fn remove(&self, name: &str) -> CryptoResult<()> {
+        self.slots.lock().unwrap().remove(name);
+        Ok(())
+    }
+}
+
+/// KDF parameters persisted next to the wrapped keys
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyStoreHeader {
+    version: u32,
+    kdf: String,
+    salt: String,  // base64
+    opslimit: usize,
+    memlimit: usize,
+    check: String,  // base64 wrapped known value, detects a wrong passphrase on open
+}
+
+/// Passphrase-protected file backend
+///
+/// Layout in `key_dir`:
+/// - `keystore.json`: KDF parameters + passphrase check value
+/// - `{slot}.key`: [Magic "SCKS1" (5 bytes)] + [Nonce (24 bytes)] + [Ciphertext]
+///
+/// Each slot is encrypted with AAD = slot name, so files cannot be swapped
+/// between slots undetected.
+pub struct PassphraseFileKeyStore {
+    key_dir: PathBuf,
+    wrapping_key: Vec<u8>,
+}
+
+impl PassphraseFileKeyStore {
+    const HEADER_FILE: &'static str = "keystore.json";
+    const KEY_EXT: &'static str = "key";
+    const MAGIC: &'static [u8] = b"SCKS1";
+    const NONCE_LEN: usize = 24;
+    const VERSION: u32 = 1;
+    const KDF: &'static str = "argon2id13";
+    const CHECK_SLOT: &'static str = "__check__";
+    const CHECK_VALUE: &'static [u8] = b"scing-keystore-v1";
+
+    /// Opens (or initializes) a key store in `key_dir`
+    ///
+    /// On first use a random salt is generated and the KDF parameters are
+    /// written to `keystore.json`. Later opens fail with
+    /// `KeyStoreUnavailable` if the passphrase does not match.
+    pub fn open(key_dir: impl AsRef<Path>, passphrase: &str) -> CryptoResult<Self> {
+        Self::open_with_params(
+            key_dir,
+            passphrase,
+            argon2id13::OPSLIMIT_INTERACTIVE.0,
+            argon2id13::MEMLIMIT_INTERACTIVE.0,
+        )
+    }
+
+    /// Opens with explicit Argon2id cost parameters (used only when initializing)
+    pub fn open_with_params(
+        key_dir: impl AsRef<Path>,
+        passphrase: &str,
+        opslimit: usize,
+        memlimit: usize,
+    ) -> CryptoResult<Self> {
+        let key_dir = key_dir.as_ref().to_path_buf();
+
+        fs::create_dir_all(&key_dir)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to create key directory: {}", e)))?;
+
+        let header_path = key_dir.join(Self::HEADER_FILE);
+
+        if header_path.exists() {
+            let header: KeyStoreHeader = fs::read(&header_path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to read key store header: {}", e)))?;
+
+            if header.version != Self::VERSION || header.kdf != Self::KDF {
+                return Err(CryptoError::KeyStoreUnavailable(format!(
+                    "Unsupported key store format: v{} {}",
+                    header.version, header.kdf
+                )));
+            }
+
+            let salt = Self::decode_b64(&header.salt)?;
+            let wrapping_key = CryptoPrimitives::derive_key_argon2id(
+                passphrase.as_bytes(),
+                &salt,
+                header.opslimit,
+                header.memlimit,
+            )?;
+
+            let store = PassphraseFileKeyStore { key_dir, wrapping_key };
+            let check = store.unwrap(Self::CHECK_SLOT, &Self::decode_b64(&header.check)?)
+                .map_err(|_| CryptoError::KeyStoreUnavailable("Incorrect key store passphrase".to_string()))?;
+
+            if check != Self::CHECK_VALUE {
+                return Err(CryptoError::KeyStoreUnavailable("Incorrect key store passphrase".to_string()));
+            }
+
+            return Ok(store);
+        }
+
+        // First use: generate salt and persist parameters
+        let salt = CryptoPrimitives::gen_salt();
+        let wrapping_key = CryptoPrimitives::derive_key_argon2id(
+            passphrase.as_bytes(),
+            &salt,
+            opslimit,
+            memlimit,
+        )?;
+
+        let store = PassphraseFileKeyStore { key_dir, wrapping_key };
+        let header = KeyStoreHeader {
+            version: Self::VERSION,
+            kdf: Self::KDF.to_string(),
+            salt: general_purpose::STANDARD.encode(&salt),
+            opslimit,
+            memlimit,
+            check: general_purpose::STANDARD.encode(store.wrap(Self::CHECK_SLOT, Self::CHECK_VALUE)?),
+        };
+
+        let header_json = serde_json::to_vec_pretty(&header)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to encode key store header: {}", e)))?;
+        Self::write_atomic(&header_path, &header_json)?;
+
+        Ok(store)
+    }
+
+    // Private helpers
+
+    fn slot_path(&self, name: &str) -> PathBuf {
+        self.key_dir.join(format!("{}.{}", name, Self::KEY_EXT))
+    }
+
+    fn wrap(&self, name: &str, key_bytes: &[u8]) -> CryptoResult<Vec<u8>> {
+        let nonce = CryptoPrimitives::gen_nonce();
+        let ciphertext = CryptoPrimitives::encrypt_aead(key_bytes, &nonce, &self.wrapping_key, name.as_bytes())?;
+
+        let mut wrapped = Vec::with_capacity(Self::MAGIC.len() + Self::NONCE_LEN + ciphertext.len());
+        wrapped.extend_from_slice(Self::MAGIC);
+        wrapped.extend_from_slice(&nonce);
+        wrapped.extend_from_slice(&ciphertext);
+        Ok(wrapped)
+    }
+
+    fn unwrap(&self, name: &str, wrapped: &[u8]) -> CryptoResult<Vec<u8>> {
+        let header_len = Self::MAGIC.len() + Self::NONCE_LEN;
+        if wrapped.len() < header_len || &wrapped[..Self::MAGIC.len()] != Self::MAGIC {
+            return Err(CryptoError::KeyStoreUnavailable(format!("Key file {} is corrupted", name)));
+        }
+
+        let nonce = &wrapped[Self::MAGIC.len()..header_len];
+        CryptoPrimitives::decrypt_aead(&wrapped[header_len..], nonce, &self.wrapping_key, name.as_bytes())
+            .map_err(|_| CryptoError::KeyStoreUnavailable(format!("Key file {} failed authentication", name)))
+    }
+
+    fn decode_b64(value: &str) -> CryptoResult<Vec<u8>> {
+        general_purpose::STANDARD.decode(value)
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Corrupted key store header: {}", e)))
+    }
+
+    /// Writes via temp file + rename so a crash never leaves a torn key file
+    fn write_atomic(path: &Path, bytes: &[u8]) -> CryptoResult<()> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to write key file: {}", e)))
+    }
+}
+
+impl KeyStore for PassphraseFileKeyStore {
+    fn put(&self, name: &str, key_bytes: &[u8]) -> CryptoResult<()> {
+        let wrapped = self.wrap(name, key_bytes)?;
+        Self::write_atomic(&self.slot_path(name), &wrapped)
+    }
+
+    fn get(&self, name: &str) -> CryptoResult<Vec<u8>> {
+        let wrapped = fs::read(self.slot_path(name))
+            .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to read key {}: {}", name, e)))?;
+        self.unwrap(name, &wrapped)
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.slot_path(name).exists()
+    }
+
+    fn remove(&self, name: &str) -> CryptoResult<()> {
+        let path = self.slot_path(name);
+        if path.exists() {
+            fs::remove_file(&path)
+                .map_err(|e| CryptoError::KeyStoreUnavailable(format!("Failed to remove key file: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Minimum Argon2id cost keeps tests fast; production uses INTERACTIVE
+    const TEST_OPS: usize = 1;
+    const TEST_MEM: usize = 8192;
+
+    #[test]
+    fn test_passphrase_store_roundtrip_and_reopen() {
+        CryptoPrimitives::init();
+        let dir = TempDir::new().unwrap();
+
+        let store = PassphraseFileKeyStore::open_with_params(dir.path(), "hunter2", TEST_OPS, TEST_MEM).unwrap();
+        store.put("box_private", &[9u8; 32]).unwrap();
+
+        // Never at rest in cleartext
+        let on_disk = fs::read(dir.path().join("box_private.key")).unwrap();
+        assert!(!on_disk.windows(32).any(|w| w == [9u8; 32]));
+
+        let reopened = PassphraseFileKeyStore::open(dir.path(), "hunter2").unwrap();
+        assert_eq!(reopened.get("box_private").unwrap(), vec![9u8; 32]);
+    }
+
+    #[test]
+    fn test_passphrase_store_rejects_wrong_passphrase() {
+        CryptoPrimitives::init();
+        let dir = TempDir::new().unwrap();
+        PassphraseFileKeyStore::open_with_params(dir.path(), "hunter2", TEST_OPS, TEST_MEM).unwrap();
+
+        assert!(matches!(
+            PassphraseFileKeyStore::open(dir.path(), "hunter3"),
+            Err(CryptoError::KeyStoreUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_passphrase_store_detects_swapped_slots() {
+        CryptoPrimitives::init();
+        let dir = TempDir::new().unwrap();
+        let store = PassphraseFileKeyStore::open_with_params(dir.path(), "hunter2", TEST_OPS, TEST_MEM).unwrap();
+        store.put("sign_private", &[1u8; 64]).unwrap();
+
+        fs::copy(dir.path().join("sign_private.key"), dir.path().join("box_private.key")).unwrap();
+        assert!(store.get("box_private").is_err());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryKeyStore::new();
+        assert!(!store.contains("sign_private"));
+
+        store.put("sign_private", &[1, 2, 3]).unwrap();
+        assert_eq!(store.get("sign_private").unwrap(), vec![1, 2, 3]);
+
+        store.remove("sign_private").unwrap();
+        assert!(matches!(store.get("sign_private"), Err(CryptoError::KeyStoreUnavailable(_))));
+    }
+}
@@ -1,22 +1,25 @@
-/// Phase 2B: Image Validation and Windows Clipboard Support
-/// 
-/// Handles image magic byte validation for:
-/// - PNG (0x89 0x50 0x4E 0x47...)
-/// - JPEG (0xFF 0xD8 0xFF...)
-/// 
-/// Provides Windows clipboard integration for image display.
-
-use std::path::{Path, PathBuf};
+//! Phase 2B: Image Validation and Windows Clipboard Support
+//! 
+//! Handles image magic byte validation for:
+//! - PNG (0x89 0x50 0x4E 0x47...)
+//! - JPEG (0xFF 0xD8 0xFF...)
+//! 
+//! Provides clipboard integration for image display, and filename
+//! sanitization for generic file transfers.
+
 use std::fs::File;
 use std::io::Write;
-use std::process::Command;
+use crate::clipboard::{platform_backend, ClipData};
 
 /// Image validator for magic byte detection
 pub struct ImageValidator;
 
-/// Windows clipboard image handler
+/// Clipboard image handler
 pub struct ClipboardImage;
 
+/// Filename and extension helpers for `type: "file"` messages
+pub struct FileSanitizer;
+
 impl ImageValidator {
     /// PNG magic bytes: 0x89 0x50 0x4E 0x47 0x0D 0x0A 0x1A 0x0A
     const PNG_MAGIC: &'static [u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
@@ -65,65 +68,201 @@ impl ImageValidator {
             "image/octet-stream".to_string()
         }
     }
+
+    /// Detect file extension from image magic bytes
+    /// 
+    /// # Returns
+    /// "png", "jpg", or "bin" (matches `media.ext` in Firestore)
+    pub fn detect_ext(bytes: &[u8]) -> String {
+        if bytes.len() >= 8 && bytes.starts_with(Self::PNG_MAGIC) {
+            "png".to_string()
+        } else if bytes.len() >= 3 && bytes.starts_with(Self::JPEG_MAGIC) {
+            "jpg".to_string()
+        } else {
+            "bin".to_string()
+        }
+    }
+
+    /// Read image dimensions from PNG IHDR or JPEG SOF header
+    /// 
+    /// # Arguments
+    /// * `bytes` - Raw image bytes
+    /// 
+    /// # Returns
+    /// Some((width, height)) if the header could be parsed; None otherwise
+    pub fn detect_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+        if bytes.starts_with(Self::PNG_MAGIC) {
+            // IHDR is always the first chunk: length(4) + "IHDR"(4) + width(4) + height(4)
+            if bytes.len() < 24 || &bytes[12..16] != b"IHDR" {
+                return None;
+            }
+            let width = u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
+            let height = u32::from_be_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]);
+            return Some((width, height));
+        }
+
+        if bytes.starts_with(Self::JPEG_MAGIC) {
+            return Self::jpeg_dimensions(bytes);
+        }
+
+        None
+    }
+
+    /// Walk JPEG segments until a Start-Of-Frame marker is found
+    fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+        let mut pos = 2;  // Skip SOI (0xFFD8)
+
+        while pos + 4 <= bytes.len() {
+            if bytes[pos] != 0xFF {
+                return None;
+            }
+            let marker = bytes[pos + 1];
+
+            // Fill bytes between segments
+            if marker == 0xFF {
+                pos += 1;
+                continue;
+            }
+
+            let segment_len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+
+            // SOF0..SOF15, excluding DHT (C4), JPG (C8) and DAC (CC)
+            let is_sof = (0xC0..=0xCF).contains(&marker)
+                && marker != 0xC4
+                && marker != 0xC8
+                && marker != 0xCC;
+
+            if is_sof {
+                // length(2) + precision(1) + height(2) + width(2)
+                if pos + 9 > bytes.len() {
+                    return None;
+                }
+                let height = u16::from_be_bytes([bytes[pos + 5], bytes[pos + 6]]) as u32;
+                let width = u16::from_be_bytes([bytes[pos + 7], bytes[pos + 8]]) as u32;
+                return Some((width, height));
+            }
+
+            pos += 2 + segment_len;
+        }
+
+        None
+    }
+}
+
+impl FileSanitizer {
+    /// Used when nothing usable remains after sanitizing
+    pub const FALLBACK_NAME: &'static str = "spectrocap_file";
+    const MAX_NAME_BYTES: usize = 200;
+    const MAX_EXT_LEN: usize = 10;
+    const RESERVED_NAMES: &'static [&'static str] = &[
+        "CON", "PRN", "AUX", "NUL",
+        "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    /// Turns a sender-supplied filename into one safe to save on Windows
+    ///
+    /// - Directory components are dropped (no path traversal)
+    /// - Control characters and `<>:"/\|?*` become `_`
+    /// - Leading dots and trailing dots/spaces are trimmed
+    /// - Reserved device names (`CON`, `NUL`, `COM1`...) are prefixed with `_`
+    /// - Length is capped, keeping the extension
+    pub fn sanitize_filename(name: &str) -> String {
+        let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+        let replaced: String = base.chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+
+        let mut cleaned = replaced
+            .trim_start_matches(['.', ' '])
+            .trim_end_matches(['.', ' '])
+            .to_string();
+
+        if cleaned.is_empty() {
+            return Self::FALLBACK_NAME.to_string();
+        }
+
+        let stem = cleaned.split('.').next().unwrap_or("").trim_end();
+        if Self::RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+            cleaned.insert(0, '_');
+        }
+
+        Self::truncate_keeping_ext(&cleaned)
+    }
+
+    /// Lowercase extension of a (sanitized) filename, or "bin"
+    ///
+    /// Only short alphanumeric extensions are kept, matching `media.ext`.
+    pub fn ext_from_filename(name: &str) -> String {
+        name.rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .filter(|ext| {
+                !ext.is_empty()
+                    && ext.len() <= Self::MAX_EXT_LEN
+                    && ext.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+            .unwrap_or_else(|| "bin".to_string())
+    }
+
+    /// Best-effort MIME type for a file extension
+    pub fn mime_for_ext(ext: &str) -> &'static str {
+        match ext {
+            "pdf" => "application/pdf",
+            "zip" => "application/zip",
+            "gz" => "application/gzip",
+            "json" => "application/json",
+            "txt" | "log" => "text/plain",
+            "csv" => "text/csv",
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "mp4" => "video/mp4",
+            "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            _ => "application/octet-stream",
+        }
+    }
+
+    fn truncate_keeping_ext(name: &str) -> String {
+        if name.len() <= Self::MAX_NAME_BYTES {
+            return name.to_string();
+        }
+
+        let ext = match name.rsplit_once('.') {
+            Some((_, ext)) if ext.len() <= Self::MAX_EXT_LEN => format!(".{}", ext),
+            _ => String::new(),
+        };
+
+        let mut stem_budget = Self::MAX_NAME_BYTES - ext.len();
+        while !name.is_char_boundary(stem_budget) {
+            stem_budget -= 1;
+        }
+
+        format!("{}{}", &name[..stem_budget], ext)
+    }
 }
 
 impl ClipboardImage {
-    /// Set image to Windows clipboard
+    /// Set image to the system clipboard
     /// 
-    /// Uses PowerShell with System.Drawing.Image API to copy image to clipboard.
-    /// Writes image to temp file, executes PowerShell command, then cleans up temp file.
+    /// Decodes the image in-process and publishes it through the platform
+    /// clipboard backend (on Windows: "PNG", CF_DIBV5 and CF_DIB). No temp
+    /// files or subprocesses are involved.
     /// 
     /// # Arguments
     /// * `image_bytes` - Raw image bytes (PNG or JPEG)
-    /// * `temp_dir` - Temporary directory for temp file
     /// 
     /// # Returns
     /// Ok(()) on success; Err(String) with error message on failure
-    pub fn set_clipboard_image(image_bytes: &[u8], temp_dir: &PathBuf) -> Result<(), String> {
-        // Create temp file
-        let temp_file = temp_dir.join("spectrocap_image.tmp");
-        
-        // Write image bytes to temp file
-        let mut file = File::create(&temp_file)
-            .map_err(|e| format!("Failed to create temp file: {}", e))?;
-        
-        file.write_all(image_bytes)
-            .map_err(|e| format!("Failed to write temp file: {}", e))?;
-        
-        drop(file);  // Ensure file is closed before PowerShell reads it
-
-        // Build PowerShell command
-        let ps_command = format!(
-            "try {{ \
-                $img = [System.Drawing.Image]::FromFile('{}'); \
-                [System.Windows.Forms.Clipboard]::SetImage($img); \
-                $img.Dispose(); \
-            }} catch {{ \
-                Write-Error $_; exit 1; \
-            }}",
-            temp_file.display()
-        );
-
-        // Execute PowerShell
-        let output = Command::new("powershell")
-            .arg("-NoProfile")
-            .arg("-Command")
-            .arg(&ps_command)
-            .output()
-            .map_err(|e| format!("Failed to execute PowerShell: {}", e))?;
-
-        // Check PowerShell success
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let _ = std::fs::remove_file(&temp_file);  // Best effort cleanup
-            return Err(format!("PowerShell failed: {}", stderr));
-        }
-
-        // Clean up temp file
-        std::fs::remove_file(&temp_file)
-            .map_err(|e| format!("Failed to clean up temp file: {}", e))?;
-
-        Ok(())
+    pub fn set_clipboard_image(image_bytes: &[u8]) -> Result<(), String> {
+        let mime = ImageValidator::detect_mime(image_bytes);
+        platform_backend()
+            .write(&ClipData::image(&mime, image_bytes.to_vec()))
+            .map_err(|e| e.to_string())
     }
 
     /// Save image to file (for "Save As" dialog)
@@ -203,4 +342,65 @@ mod tests {
         let unknown_bytes = vec![0x00, 0x01, 0x02, 0x03];
         assert_eq!(ImageValidator::detect_mime(&unknown_bytes), "image/octet-stream");
     }
+
+    #[test]
+    fn test_detect_png_dimensions() {
+        let mut png_bytes = vec![
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A,
+            0x00, 0x00, 0x00, 0x0D, b'I', b'H', b'D', b'R',
+        ];
+        png_bytes.extend_from_slice(&1920u32.to_be_bytes());
+        png_bytes.extend_from_slice(&1080u32.to_be_bytes());
+        assert_eq!(ImageValidator::detect_dimensions(&png_bytes), Some((1920, 1080)));
+        assert_eq!(ImageValidator::detect_ext(&png_bytes), "png");
+    }
+
+    #[test]
+    fn test_detect_jpeg_dimensions() {
+        let jpeg_bytes = vec![
+            0xFF, 0xD8,  // SOI
+            0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00,  // APP0 (2 bytes payload)
+            0xFF, 0xC0, 0x00, 0x11, 0x08,  // SOF0, precision 8
+            0x01, 0xE0,  // height 480
+            0x02, 0x80,  // width 640
+        ];
+        assert_eq!(ImageValidator::detect_dimensions(&jpeg_bytes), Some((640, 480)));
+        assert_eq!(ImageValidator::detect_ext(&jpeg_bytes), "jpg");
+    }
+
+    #[test]
+    fn test_detect_dimensions_truncated() {
+        let png_bytes = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        assert_eq!(ImageValidator::detect_dimensions(&png_bytes), None);
+    }
+
+    #[test]
+    fn test_sanitize_filename_strips_paths_and_reserved_chars() {
+        assert_eq!(FileSanitizer::sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(FileSanitizer::sanitize_filename("C:\\Users\\me\\report.pdf"), "report.pdf");
+        assert_eq!(FileSanitizer::sanitize_filename("what?<is>:this*.txt"), "what__is__this_.txt");
+        assert_eq!(FileSanitizer::sanitize_filename("tab\tname.log"), "tab_name.log");
+        assert_eq!(FileSanitizer::sanitize_filename("..."), FileSanitizer::FALLBACK_NAME);
+        assert_eq!(FileSanitizer::sanitize_filename("notes.txt. . "), "notes.txt");
+        assert_eq!(FileSanitizer::sanitize_filename("con.txt"), "_con.txt");
+        assert_eq!(FileSanitizer::sanitize_filename("Q3 report.pdf"), "Q3 report.pdf");
+    }
+
+    #[test]
+    fn test_sanitize_filename_caps_length_keeping_ext() {
+        let long = format!("{}.zip", "é".repeat(300));
+        let sanitized = FileSanitizer::sanitize_filename(&long);
+
+        assert!(sanitized.len() <= 200);
+        assert!(sanitized.ends_with(".zip"));
+    }
+
+    #[test]
+    fn test_ext_and_mime_from_filename() {
+        assert_eq!(FileSanitizer::ext_from_filename("Report.PDF"), "pdf");
+        assert_eq!(FileSanitizer::ext_from_filename("archive"), "bin");
+        assert_eq!(FileSanitizer::ext_from_filename("weird.e x e"), "bin");
+        assert_eq!(FileSanitizer::mime_for_ext("pdf"), "application/pdf");
+        assert_eq!(FileSanitizer::mime_for_ext("xyz"), "application/octet-stream");
+    }
 }
@@ -1,24 +1,56 @@
-/// Phase 2A: End-to-End Encryption module for Windows (Tauri)
-///
-/// Handles all cryptographic operations:
-/// - Ed25519 signing/verification
-/// - X25519 key wrapping (sealed box)
-/// - XChaCha20-Poly1305 AEAD encryption/decryption
-/// - DPAPI-based key storage (Windows)
-///
-/// All crypto operations use sodiumoxide for safe libsodium bindings.
+//! Phase 2A: End-to-End Encryption module for Windows (Tauri)
+//!
+//! Handles all cryptographic operations:
+//! - Ed25519 signing/verification
+//! - X25519 key wrapping (sealed box)
+//! - XChaCha20-Poly1305 AEAD encryption/decryption
+//! - Chunked secretstream encryption for large payloads
+//! - Passphrase-protected key storage (Argon2id + XChaCha20-Poly1305)
+//! - Local history encryption at rest
+//!
+//! All crypto operations use sodiumoxide for safe libsodium bindings.
 
 pub mod key_mgmt;
+pub mod key_store;
 pub mod format;
+pub mod blob;
+pub mod stream;
 pub mod primitives;
 pub mod receiver;
+pub mod sender;
 pub mod media;
+pub mod schema;
+pub mod error;
+pub mod identity;
+pub mod replay;
+pub mod trust;
+pub mod pairing;
+pub mod revocation;
+pub mod history;
 
-pub use key_mgmt::KeyManager;
+#[cfg(test)]
+pub(crate) mod test_util;
+
+pub use key_mgmt::{BoxKeys, HistoryKey, KeyGeneration, KeyManager, DEFAULT_ROTATION_GRACE};
+pub use key_store::{KeyStore, MemoryKeyStore, PassphraseFileKeyStore};
 pub use format::BlobFormat;
+pub use blob::{BlobHeader, BlobVersion, VersionedBlob};
+pub use stream::{StreamBlobFormat, StreamDecryptor, StreamEncryptor};
 pub use primitives::CryptoPrimitives;
 pub use receiver::E2EEReceiver;
+pub use sender::E2EESender;
 pub use media::{ImageValidator, ClipboardImage};
+pub use schema::{DeviceDoc, MessageDoc};
+pub use error::{CryptoError, CryptoResult};
+pub use identity::DeviceIdentity;
+pub use replay::{FreshnessWindow, MemorySeenLedger, ReplayGuard, SeenMessageLedger};
+pub use trust::{KeyPinning, MemoryPinStore, PinStatus, PinStore, PinnedKeys, SafetyNumber};
+pub use pairing::{
+    PairingConfirmation, PairingInitiator, PairingOffer, PairingResponder, PairingResponse,
+    PendingPairing, ResponderPairing, DEFAULT_OFFER_TTL,
+};
+pub use revocation::{DeviceRevocations, MemoryRevocationStore, RevocationStatement, RevocationStore};
+pub use history::{HistoryCipher, SealedValue};
 
 #[cfg(test)]
 mod tests {
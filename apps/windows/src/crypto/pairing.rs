@@ -0,0 +1,457 @@
+//! QR pairing ceremony with short authentication string (SAS)
+//!
+//! 1. Initiator (desktop) shows a QR payload: its device ID, public keys and
+//!    a one-time 32-byte secret, valid for a few minutes
+//! 2. Responder (phone) scans it and returns its own keys. The response
+//!    carries HMAC(secret, transcript), proving it saw the QR code, plus its
+//!    Ed25519 signature over the transcript
+//! 3. Both screens show a 6-digit SAS derived from both devices' keys; the
+//!    user confirms they match
+//! 4. Initiator sends a confirmation (HMAC + signature). Each side then pins
+//!    the other's keys through `KeyPinning`
+//!
+//! The response and confirmation may travel through Firestore: without the
+//! QR secret the server can neither forge nor substitute keys.
+
+use std::time::Duration;
+use base64::{Engine, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::auth::hmacsha256;
+use sodiumoxide::randombytes::randombytes;
+use super::primitives::CryptoPrimitives;
+use super::identity::DeviceIdentity;
+use super::key_mgmt::KeyManager;
+use super::schema::{DeviceDoc, DeviceStatus, Platform};
+use super::trust::KeyPinning;
+use super::error::{CryptoError, CryptoResult};
+
+/// Default lifetime of a displayed QR code
+pub const DEFAULT_OFFER_TTL: Duration = Duration::from_secs(5 * 60);
+
+const QR_PREFIX: &str = "scap-pair:";
+const PROTOCOL_VERSION: u32 = 1;
+const SECRET_LEN: usize = 32;
+
+/// Contents of the QR code shown by the initiator
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingOffer {
+    pub v: u32,
+    pub device_id: String,
+    pub name: String,
+    pub platform: Platform,
+    pub pub_sign_key: String,
+    pub pub_box_key: String,
+    pub secret: String,  // base64, one-time
+    pub expires_at: i64,  // Unix seconds
+}
+
+/// Responder's keys, authenticated with the QR secret and its signing key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingResponse {
+    pub device_id: String,
+    pub name: String,
+    pub platform: Platform,
+    pub pub_sign_key: String,
+    pub pub_box_key: String,
+    pub mac: String,  // base64 HMAC-SHA256(secret, "response" || transcript)
+    pub signature: String,  // base64 Ed25519(transcript)
+}
+
+/// Initiator's proof that it holds the QR secret and its signing key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingConfirmation {
+    pub mac: String,  // base64 HMAC-SHA256(secret, "confirm" || transcript)
+    pub signature: String,  // base64 Ed25519("confirm" || transcript)
+}
+
+impl PairingOffer {
+    /// Encodes the offer as the QR code text
+    pub fn to_qr_payload(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Failed to serialize pairing offer");
+        format!("{}{}", QR_PREFIX, general_purpose::URL_SAFE_NO_PAD.encode(json))
+    }
+
+    /// Parses QR code text produced by `to_qr_payload`
+    pub fn from_qr_payload(payload: &str) -> CryptoResult<Self> {
+        let encoded = payload.trim().strip_prefix(QR_PREFIX)
+            .ok_or_else(|| CryptoError::PairingFailed("Not a pairing QR code".to_string()))?;
+        let json = general_purpose::URL_SAFE_NO_PAD.decode(encoded)
+            .map_err(|e| CryptoError::PairingFailed(format!("Invalid QR encoding: {}", e)))?;
+        let offer: PairingOffer = serde_json::from_slice(&json)
+            .map_err(|e| CryptoError::PairingFailed(format!("Invalid QR payload: {}", e)))?;
+
+        if offer.v != PROTOCOL_VERSION {
+            return Err(CryptoError::UnsupportedVersion(format!("Pairing protocol {}", offer.v)));
+        }
+        Ok(offer)
+    }
+
+    fn device_doc(&self) -> CryptoResult<DeviceDoc> {
+        peer_doc(&self.device_id, &self.name, self.platform, &self.pub_sign_key, &self.pub_box_key)
+    }
+
+    fn secret_bytes(&self) -> CryptoResult<Vec<u8>> {
+        let secret = general_purpose::STANDARD.decode(&self.secret)
+            .map_err(|e| CryptoError::PairingFailed(format!("Invalid pairing secret: {}", e)))?;
+        if secret.len() != SECRET_LEN {
+            return Err(CryptoError::PairingFailed("Invalid pairing secret length".to_string()));
+        }
+        Ok(secret)
+    }
+}
+
+impl PairingResponse {
+    fn device_doc(&self) -> CryptoResult<DeviceDoc> {
+        peer_doc(&self.device_id, &self.name, self.platform, &self.pub_sign_key, &self.pub_box_key)
+    }
+}
+
+/// Desktop side: shows the QR code and verifies the phone's response
+pub struct PairingInitiator {
+    key_manager: KeyManager,
+    offer: PairingOffer,
+}
+
+impl PairingInitiator {
+    /// Creates a fresh offer (new one-time secret) for this device
+    ///
+    /// # Arguments
+    /// * `key_manager` - This device's keys (provisioned if needed)
+    /// * `device_name` - Name shown on the phone
+    /// * `ttl` - How long the QR code stays valid
+    pub fn new(key_manager: KeyManager, device_name: &str, ttl: Duration) -> CryptoResult<Self> {
+        let own = DeviceIdentity::new(key_manager.clone()).registration_doc(device_name)?;
+
+        let offer = PairingOffer {
+            v: PROTOCOL_VERSION,
+            device_id: own.device_id,
+            name: own.name,
+            platform: own.platform,
+            pub_sign_key: own.pub_sign_key.unwrap_or_default(),
+            pub_box_key: own.pub_box_key.unwrap_or_default(),
+            secret: general_purpose::STANDARD.encode(randombytes(SECRET_LEN)),
+            expires_at: chrono::Utc::now().timestamp() + ttl.as_secs() as i64,
+        };
+
+        Ok(PairingInitiator { key_manager, offer })
+    }
+
+    pub fn offer(&self) -> &PairingOffer {
+        &self.offer
+    }
+
+    pub fn qr_payload(&self) -> String {
+        self.offer.to_qr_payload()
+    }
+
+    /// Verifies the phone's response and derives the SAS to display
+    pub fn accept_response(&self, response: &PairingResponse) -> CryptoResult<PendingPairing> {
+        check_not_expired(&self.offer)?;
+
+        let own = self.offer.device_doc()?;
+        let peer = response.device_doc()?;
+        let secret = self.offer.secret_bytes()?;
+        let transcript = transcript(&own, &peer)?;
+
+        if !verify_mac(&secret, b"response", &transcript, &response.mac)? {
+            return Err(CryptoError::PairingFailed(
+                "Response not authenticated with the QR secret".to_string(),
+            ));
+        }
+        verify_signature(&transcript, &response.signature, &peer)?;
+
+        let sign_sk = self.key_manager.get_sign_private_key()?;
+        let mut confirm_message = b"confirm".to_vec();
+        confirm_message.extend_from_slice(&transcript);
+        let confirmation = PairingConfirmation {
+            mac: general_purpose::STANDARD.encode(mac(&secret, b"confirm", &transcript)?),
+            signature: general_purpose::STANDARD.encode(CryptoPrimitives::sign(&confirm_message, &sign_sk)?),
+        };
+
+        Ok(PendingPairing {
+            peer,
+            sas: sas(&transcript),
+            confirmation: Some(confirmation),
+        })
+    }
+}
+
+/// Phone side: answers a scanned QR code
+pub struct PairingResponder;
+
+impl PairingResponder {
+    /// Builds the response to a scanned offer
+    ///
+    /// # Returns
+    /// Tuple of (response to send back, pending pairing showing the SAS)
+    pub fn respond(
+        key_manager: &KeyManager,
+        device_name: &str,
+        qr_payload: &str,
+    ) -> CryptoResult<(PairingResponse, ResponderPairing)> {
+        let offer = PairingOffer::from_qr_payload(qr_payload)?;
+        check_not_expired(&offer)?;
+
+        let own = DeviceIdentity::new(key_manager.clone()).registration_doc(device_name)?;
+        let peer = offer.device_doc()?;
+        let secret = offer.secret_bytes()?;
+        let transcript = transcript(&peer, &own)?;
+
+        let sign_sk = key_manager.get_sign_private_key()?;
+        let response = PairingResponse {
+            device_id: own.device_id.clone(),
+            name: own.name.clone(),
+            platform: own.platform,
+            pub_sign_key: own.pub_sign_key.clone().unwrap_or_default(),
+            pub_box_key: own.pub_box_key.clone().unwrap_or_default(),
+            mac: general_purpose::STANDARD.encode(mac(&secret, b"response", &transcript)?),
+            signature: general_purpose::STANDARD.encode(CryptoPrimitives::sign(&transcript, &sign_sk)?),
+        };
+
+        let pending = ResponderPairing {
+            pending: PendingPairing { peer, sas: sas(&transcript), confirmation: None },
+            secret,
+            transcript,
+        };
+
+        Ok((response, pending))
+    }
+}
+
+/// Verified peer awaiting the user's SAS comparison
+#[derive(Debug)]
+pub struct PendingPairing {
+    peer: DeviceDoc,
+    sas: String,
+    confirmation: Option<PairingConfirmation>,
+}
+
+impl PendingPairing {
+    /// 6-digit code both screens must show
+    pub fn sas(&self) -> &str {
+        &self.sas
+    }
+
+    pub fn peer(&self) -> &DeviceDoc {
+        &self.peer
+    }
+
+    /// User confirmed the SAS matches: pins the peer's keys
+    ///
+    /// # Returns
+    /// The confirmation to send to the responder (initiator side)
+    pub fn confirm(self, pinning: &KeyPinning) -> CryptoResult<Option<PairingConfirmation>> {
+        // Pairing is explicit user approval, so it replaces any earlier pin
+        pinning.approve_key_change(&self.peer)?;
+        Ok(self.confirmation)
+    }
+}
+
+/// Responder state until the initiator's confirmation arrives
+#[derive(Debug)]
+pub struct ResponderPairing {
+    pending: PendingPairing,
+    secret: Vec<u8>,
+    transcript: Vec<u8>,
+}
+
+impl ResponderPairing {
+    pub fn sas(&self) -> &str {
+        self.pending.sas()
+    }
+
+    pub fn peer(&self) -> &DeviceDoc {
+        self.pending.peer()
+    }
+
+    /// User confirmed the SAS and the initiator's confirmation verified: pins the initiator
+    pub fn finish(self, confirmation: &PairingConfirmation, pinning: &KeyPinning) -> CryptoResult<()> {
+        if !verify_mac(&self.secret, b"confirm", &self.transcript, &confirmation.mac)? {
+            return Err(CryptoError::PairingFailed(
+                "Confirmation not authenticated with the QR secret".to_string(),
+            ));
+        }
+
+        let mut confirm_message = b"confirm".to_vec();
+        confirm_message.extend_from_slice(&self.transcript);
+        verify_signature(&confirm_message, &confirmation.signature, &self.pending.peer)?;
+
+        self.pending.confirm(pinning)?;
+        Ok(())
+    }
+}
+
+fn peer_doc(
+    device_id: &str,
+    name: &str,
+    platform: Platform,
+    pub_sign_key: &str,
+    pub_box_key: &str,
+) -> CryptoResult<DeviceDoc> {
+    let doc = DeviceDoc {
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        platform,
+        created_at: None,
+        last_seen_at: None,
+        status: DeviceStatus::Active,
+        pub_sign_key: Some(pub_sign_key.to_string()),
+        pub_box_key: Some(pub_box_key.to_string()),
+        key_generation: None,
+        box_key_sig: None,
+        sign_key_chain: Vec::new(),
+        blob_versions: Vec::new(),
+    };
+    doc.validate()
+        .map_err(|e| CryptoError::PairingFailed(format!("Invalid peer keys: {}", e)))?;
+    Ok(doc)
+}
+
+fn check_not_expired(offer: &PairingOffer) -> CryptoResult<()> {
+    if chrono::Utc::now().timestamp() > offer.expires_at {
+        return Err(CryptoError::PairingFailed("Pairing code expired".to_string()));
+    }
+    Ok(())
+}
+
+/// SHA256 over both devices' IDs and keys (initiator first)
+fn transcript(initiator: &DeviceDoc, responder: &DeviceDoc) -> CryptoResult<Vec<u8>> {
+    let mut bytes = b"SCAP-PAIR-1".to_vec();
+    for doc in [initiator, responder] {
+        let id = doc.device_id.as_bytes();
+        bytes.extend_from_slice(&(id.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&doc.pub_sign_key_bytes()?);
+        bytes.extend_from_slice(&doc.pub_box_key_bytes()?);
+    }
+    Ok(CryptoPrimitives::sha256(&bytes))
+}
+
+/// Six decimal digits from SHA256("sas" || transcript)
+fn sas(transcript: &[u8]) -> String {
+    let mut input = b"sas".to_vec();
+    input.extend_from_slice(transcript);
+    let digest = CryptoPrimitives::sha256(&input);
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    format!("{:06}", value % 1_000_000)
+}
+
+fn mac(secret: &[u8], label: &[u8], transcript: &[u8]) -> CryptoResult<Vec<u8>> {
+    let key = hmacsha256::Key::from_slice(secret)
+        .ok_or_else(|| CryptoError::PairingFailed("Invalid pairing secret length".to_string()))?;
+    let mut message = label.to_vec();
+    message.extend_from_slice(transcript);
+    Ok(hmacsha256::authenticate(&message, &key).as_ref().to_vec())
+}
+
+fn verify_mac(secret: &[u8], label: &[u8], transcript: &[u8], mac_b64: &str) -> CryptoResult<bool> {
+    let key = hmacsha256::Key::from_slice(secret)
+        .ok_or_else(|| CryptoError::PairingFailed("Invalid pairing secret length".to_string()))?;
+    let tag_bytes = general_purpose::STANDARD.decode(mac_b64)
+        .map_err(|e| CryptoError::PairingFailed(format!("Invalid MAC encoding: {}", e)))?;
+    let Some(tag) = hmacsha256::Tag::from_slice(&tag_bytes) else {
+        return Ok(false);
+    };
+    let mut message = label.to_vec();
+    message.extend_from_slice(transcript);
+    Ok(hmacsha256::verify(&tag, &message, &key))
+}
+
+fn verify_signature(message: &[u8], signature_b64: &str, signer: &DeviceDoc) -> CryptoResult<()> {
+    let signature = general_purpose::STANDARD.decode(signature_b64)
+        .map_err(|e| CryptoError::PairingFailed(format!("Invalid signature encoding: {}", e)))?;
+    if !CryptoPrimitives::verify(message, &signature, &signer.pub_sign_key_bytes()?) {
+        return Err(CryptoError::InvalidSignature);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> (KeyManager, KeyPinning) {
+        CryptoPrimitives::init();
+        (KeyManager::in_memory(), KeyPinning::in_memory())
+    }
+
+    #[test]
+    fn test_ceremony_pins_both_sides() {
+        let (pc_keys, pc_pins) = device();
+        let (phone_keys, phone_pins) = device();
+
+        let initiator = PairingInitiator::new(pc_keys, "Desktop", DEFAULT_OFFER_TTL).unwrap();
+        let (response, phone_pending) =
+            PairingResponder::respond(&phone_keys, "Phone", &initiator.qr_payload()).unwrap();
+        let pc_pending = initiator.accept_response(&response).unwrap();
+
+        assert_eq!(pc_pending.sas(), phone_pending.sas());
+        assert_eq!(pc_pending.sas().len(), 6);
+
+        let confirmation = pc_pending.confirm(&pc_pins).unwrap().unwrap();
+        phone_pending.finish(&confirmation, &phone_pins).unwrap();
+
+        let phone_id = phone_keys.get_device_id().unwrap().unwrap();
+        let pc_id = initiator.offer().device_id.clone();
+        assert_eq!(pc_pins.pinned(&phone_id).unwrap().unwrap().pub_sign_key, response.pub_sign_key);
+        assert_eq!(phone_pins.pinned(&pc_id).unwrap().unwrap().pub_sign_key, initiator.offer().pub_sign_key);
+    }
+
+    #[test]
+    fn test_substituted_keys_without_secret_rejected() {
+        let (pc_keys, _) = device();
+        let (phone_keys, _) = device();
+        let (mitm_keys, _) = device();
+
+        let initiator = PairingInitiator::new(pc_keys, "Desktop", DEFAULT_OFFER_TTL).unwrap();
+        let (mut response, _) =
+            PairingResponder::respond(&phone_keys, "Phone", &initiator.qr_payload()).unwrap();
+
+        // Server swaps in its own keys but cannot recompute the MAC
+        let mitm = DeviceIdentity::new(mitm_keys).registration_doc("Phone").unwrap();
+        response.pub_sign_key = mitm.pub_sign_key.unwrap();
+        response.pub_box_key = mitm.pub_box_key.unwrap();
+
+        assert!(matches!(
+            initiator.accept_response(&response),
+            Err(CryptoError::PairingFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_forged_confirmation_rejected() {
+        let (pc_keys, pc_pins) = device();
+        let (phone_keys, phone_pins) = device();
+
+        let initiator = PairingInitiator::new(pc_keys, "Desktop", DEFAULT_OFFER_TTL).unwrap();
+        let (response, phone_pending) =
+            PairingResponder::respond(&phone_keys, "Phone", &initiator.qr_payload()).unwrap();
+        let mut confirmation = initiator.accept_response(&response).unwrap()
+            .confirm(&pc_pins).unwrap().unwrap();
+        confirmation.mac = general_purpose::STANDARD.encode([0u8; 32]);
+
+        assert!(phone_pending.finish(&confirmation, &phone_pins).is_err());
+        assert!(phone_pins.pinned(&initiator.offer().device_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_qr_payload_roundtrip_and_expiry() {
+        let (pc_keys, _) = device();
+        let (phone_keys, _) = device();
+
+        let initiator = PairingInitiator::new(pc_keys, "Desktop", DEFAULT_OFFER_TTL).unwrap();
+        let payload = initiator.qr_payload();
+        assert!(payload.starts_with("scap-pair:"));
+        assert_eq!(&PairingOffer::from_qr_payload(&payload).unwrap(), initiator.offer());
+
+        let mut expired = initiator.offer().clone();
+        expired.expires_at -= 3600;
+        assert!(matches!(
+            PairingResponder::respond(&phone_keys, "Phone", &expired.to_qr_payload()),
+            Err(CryptoError::PairingFailed(_))
+        ));
+    }
+}
@@ -1,14 +1,24 @@
-/// Low-level cryptographic primitives using sodiumoxide
-/// 
-/// Wraps libsodium calls for:
-/// - Ed25519 signing
-/// - X25519 key derivation (sealed box)
-/// - XChaCha20-Poly1305 AEAD
-/// - SHA256 hashing
-
-use sodiumoxide::crypto::{sign, box_, aead};
+//! Low-level cryptographic primitives using sodiumoxide
+//! 
+//! Wraps libsodium calls for:
+//! - Ed25519 signing
+//! - X25519 key derivation (sealed box)
+//! - XChaCha20-Poly1305 AEAD
+//! - Argon2id key derivation
+//! - SHA256 hashing
+//!
+//! Signature wire format: `sign` produces detached Ed25519 signatures, the
+//! 64 bytes `R || S` of RFC 8032 over the raw message (for messages, the 32
+//! metaHash bytes), base64-encoded in documents. This is libsodium
+//! `crypto_sign_detached`; Android must call lazysodium `cryptoSignDetached`,
+//! not `cryptoSign`, whose combined output (signature || message) `verify`
+//! rejects. `shared/remote-paste/signature_vectors.json` pins the format.
+
+use sodiumoxide::crypto::{sign, box_, aead, sealedbox};
+use sodiumoxide::crypto::pwhash::argon2id13;
 use sodiumoxide::randombytes;
 use sha2::{Sha256, Digest};
+use super::error::{CryptoError, CryptoResult};
 
 pub struct CryptoPrimitives;
 
@@ -46,18 +56,18 @@ impl CryptoPrimitives {
         randombytes::randombytes(24)
     }
 
-    /// Signs a message using Ed25519
+    /// Signs a message using Ed25519 (detached signature)
     /// 
     /// # Arguments
     /// * `message` - Data to sign (typically metaHash)
-    /// * `sk_bytes` - Secret key bytes (32 bytes)
+    /// * `sk_bytes` - Secret key bytes (64 bytes)
     /// 
     /// # Returns
     /// Signature (64 bytes)
-    pub fn sign(message: &[u8], sk_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    pub fn sign(message: &[u8], sk_bytes: &[u8]) -> CryptoResult<Vec<u8>> {
         let sk = sign::SecretKey::from_slice(sk_bytes)
-            .ok_or_else(|| "Invalid secret key".to_string())?;
-        Ok(sign::sign(message, &sk).as_ref().to_vec())
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid signing secret key".to_string()))?;
+        Ok(sign::sign_detached(message, &sk).to_bytes().to_vec())
     }
 
     /// Verifies an Ed25519 signature
@@ -74,11 +84,11 @@ impl CryptoPrimitives {
             Some(k) => k,
             None => return false,
         };
-        let sig = match sign::Signature::from_slice(signature) {
-            Some(s) => s,
-            None => return false,
+        let sig = match sign::Signature::from_bytes(signature) {
+            Ok(s) => s,
+            Err(_) => return false,
         };
-        sign::verify(&sig, message, &pk).is_ok()
+        sign::verify_detached(&sig, message, &pk)
     }
 
     /// Encrypts using XChaCha20-Poly1305 AEAD
@@ -96,12 +106,12 @@ impl CryptoPrimitives {
         nonce: &[u8],
         dek: &[u8],
         aad: &[u8],
-    ) -> Result<Vec<u8>, String> {
+    ) -> CryptoResult<Vec<u8>> {
         let key = aead::xchacha20poly1305_ietf::Key::from_slice(dek)
-            .ok_or_else(|| "Invalid DEK length".to_string())?;
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid DEK length".to_string()))?;
         
         let nonce_obj = aead::xchacha20poly1305_ietf::Nonce::from_slice(nonce)
-            .ok_or_else(|| "Invalid nonce length".to_string())?;
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid nonce length".to_string()))?;
 
         Ok(aead::xchacha20poly1305_ietf::seal(plaintext, Some(aad), &nonce_obj, &key))
     }
@@ -115,17 +125,22 @@ impl CryptoPrimitives {
     /// * `aad` - Additional Authenticated Data (must match encryption)
     /// 
     /// # Returns
-    /// Plaintext if decryption succeeds, None otherwise
+    /// Plaintext if decryption succeeds, `Tampered` if authentication fails
     pub fn decrypt_aead(
         ciphertext: &[u8],
         nonce: &[u8],
         dek: &[u8],
         aad: &[u8],
-    ) -> Option<Vec<u8>> {
-        let key = aead::xchacha20poly1305_ietf::Key::from_slice(dek)?;
-        let nonce_obj = aead::xchacha20poly1305_ietf::Nonce::from_slice(nonce)?;
+    ) -> CryptoResult<Vec<u8>> {
+        let key = aead::xchacha20poly1305_ietf::Key::from_slice(dek)
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid DEK length".to_string()))?;
+        let nonce_obj = aead::xchacha20poly1305_ietf::Nonce::from_slice(nonce)
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid nonce length".to_string()))?;
         
-        aead::xchacha20poly1305_ietf::open(ciphertext, Some(aad), &nonce_obj, &key).ok()
+        aead::xchacha20poly1305_ietf::open(ciphertext, Some(aad), &nonce_obj, &key)
+            .map_err(|_| CryptoError::Tampered(
+                "AEAD decryption failed (authentication failed or wrong key)".to_string(),
+            ))
     }
 
     /// Encrypts data using X25519 sealed box (for a specific recipient)
@@ -136,10 +151,10 @@ impl CryptoPrimitives {
     /// 
     /// # Returns
     /// Sealed box (48 bytes overhead + plaintext)
-    pub fn seal_box(plaintext: &[u8], pk_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    pub fn seal_box(plaintext: &[u8], pk_bytes: &[u8]) -> CryptoResult<Vec<u8>> {
         let pk = box_::PublicKey::from_slice(pk_bytes)
-            .ok_or_else(|| "Invalid public key".to_string())?;
-        Ok(box_::seal(plaintext, &pk))
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid box public key".to_string()))?;
+        Ok(sealedbox::seal(plaintext, &pk))
     }
 
     /// Decrypts data using X25519 sealed box
@@ -150,15 +165,55 @@ impl CryptoPrimitives {
     /// * `sk_bytes` - Recipient's secret key (32 bytes)
     /// 
     /// # Returns
-    /// Plaintext if decryption succeeds, None otherwise
+    /// Plaintext if decryption succeeds, `Tampered` if the box cannot be opened
     pub fn open_sealed_box(
         ciphertext: &[u8],
         pk_bytes: &[u8],
         sk_bytes: &[u8],
-    ) -> Option<Vec<u8>> {
-        let pk = box_::PublicKey::from_slice(pk_bytes)?;
-        let sk = box_::SecretKey::from_slice(sk_bytes)?;
-        box_::open_sealed(ciphertext, &pk, &sk).ok()
+    ) -> CryptoResult<Vec<u8>> {
+        let pk = box_::PublicKey::from_slice(pk_bytes)
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid box public key".to_string()))?;
+        let sk = box_::SecretKey::from_slice(sk_bytes)
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid box secret key".to_string()))?;
+        sealedbox::open(ciphertext, &pk, &sk)
+            .map_err(|_| CryptoError::Tampered("Failed to open sealed box".to_string()))
+    }
+
+    /// Generates random 16-byte salt for Argon2id
+    pub fn gen_salt() -> Vec<u8> {
+        randombytes::randombytes(argon2id13::SALTBYTES)
+    }
+
+    /// Derives a 32-byte key from a passphrase using Argon2id
+    /// 
+    /// # Arguments
+    /// * `passphrase` - User passphrase
+    /// * `salt` - 16-byte salt (stored alongside the derived-key consumer)
+    /// * `opslimit` - Argon2id iterations
+    /// * `memlimit` - Argon2id memory in bytes
+    /// 
+    /// # Returns
+    /// 32-byte key
+    pub fn derive_key_argon2id(
+        passphrase: &[u8],
+        salt: &[u8],
+        opslimit: usize,
+        memlimit: usize,
+    ) -> CryptoResult<Vec<u8>> {
+        let salt = argon2id13::Salt::from_slice(salt)
+            .ok_or_else(|| CryptoError::InvalidKey("Invalid Argon2id salt length".to_string()))?;
+
+        let mut key = vec![0u8; 32];
+        argon2id13::derive_key(
+            &mut key,
+            passphrase,
+            &salt,
+            argon2id13::OpsLimit(opslimit),
+            argon2id13::MemLimit(memlimit),
+        )
+        .map_err(|_| CryptoError::KeyStoreUnavailable("Argon2id key derivation failed".to_string()))?;
+
+        Ok(key)
     }
 
     /// Computes SHA256 hash
@@ -168,3 +223,36 @@ impl CryptoPrimitives {
         hasher.finalize().to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine, engine::general_purpose};
+    use serde_json::Value;
+
+    #[test]
+    fn test_signature_wire_format_vectors() {
+        CryptoPrimitives::init();
+        let fixture: Value = serde_json::from_str(include_str!(
+            "../../../../shared/remote-paste/signature_vectors.json"
+        ))
+        .expect("Valid vector file");
+        let decode = |value: &Value| general_purpose::STANDARD.decode(value.as_str().unwrap()).unwrap();
+
+        for vector in fixture["vectors"].as_array().expect("vectors array") {
+            let name = vector["name"].as_str().unwrap();
+            let seed = sign::Seed::from_slice(&decode(&vector["seed"])).unwrap();
+            let (pk, sk) = sign::keypair_from_seed(&seed);
+            let message = decode(&vector["message"]);
+            let expected = decode(&vector["signature"]);
+
+            assert_eq!(pk.as_ref(), decode(&vector["publicKey"]).as_slice(), "public key mismatch for {}", name);
+            assert_eq!(CryptoPrimitives::sign(&message, sk.as_ref()).unwrap(), expected, "signature mismatch for {}", name);
+            assert!(CryptoPrimitives::verify(&message, &expected, pk.as_ref()), "{}", name);
+
+            // Combined-mode output (signature || message) is not a signature
+            let combined = [expected.as_slice(), message.as_slice()].concat();
+            assert!(message.is_empty() || !CryptoPrimitives::verify(&message, &combined, pk.as_ref()), "{}", name);
+        }
+    }
+}
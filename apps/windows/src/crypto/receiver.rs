@@ -1,240 +1,345 @@
-/// Phase 2A E2EE Receiver for Windows (Tauri)
-/// 
-/// Implements full E2EE decryption pipeline:
-/// 1. Verify sender device status
-/// 2. Verify metaHash integrity
-/// 3. Verify Ed25519 signature
-/// 4. Decrypt DEK (from sealed box envelope)
-/// 5. Download and decrypt blob
-/// 6. Return plaintext
-
-use serde_json::Value;
+//! Phase 2A E2EE Receiver for Windows (Tauri)
+//! 
+//! Implements full E2EE decryption pipeline:
+//! 1. Verify sender device status (server and signed local revocations), that
+//!    it is the message's sender and that its keys match the pinned keys
+//!    (when KeyPinning is attached)
+//! 2. Verify metaHash integrity
+//! 3. Verify Ed25519 signature, then bind recipients and envelopes to the
+//!    signed recipient list
+//! 4. Check freshness + replay (when a ReplayGuard is attached)
+//! 5. Decrypt DEK (from sealed box envelope)
+//! 6. Decrypt blob (any supported blob version; see `blob.rs`)
+//! 7. Record messageId as seen, return plaintext
+//!
+//! `open_message` stops before step 7 so callers that persist messages can
+//! store first and `confirm_delivery` after; a failed store is then retried
+//! instead of being rejected as a replay.
+
 use base64::{Engine, engine::general_purpose};
 use super::primitives::CryptoPrimitives;
-use super::format::{BlobFormat, CanonicalMetadata};
+use super::blob::VersionedBlob;
+use super::format::CanonicalMetadata;
 use super::key_mgmt::KeyManager;
-use super::media::ImageValidator;
+use super::replay::ReplayGuard;
+use super::revocation::DeviceRevocations;
+use super::trust::{KeyPinning, PinStatus};
+use super::media::{FileSanitizer, ImageValidator};
+use super::schema::{DeviceDoc, DeviceStatus, MessageDoc, MessageType};
+use super::error::{CryptoError, CryptoResult};
 
 pub struct E2EEReceiver {
     key_manager: KeyManager,
+    replay_guard: Option<ReplayGuard>,
+    key_pinning: Option<KeyPinning>,
+    revocations: Option<DeviceRevocations>,
 }
 
 #[derive(Debug)]
 pub struct DecryptionResult {
     pub plaintext: Option<String>,  // For text messages (Phase 2A)
     pub image_bytes: Option<Vec<u8>>,  // For image messages (Phase 2B)
-    pub message_type: String,  // "text" or "image"
+    pub file_bytes: Option<Vec<u8>>,  // For file messages
+    pub filename: Option<String>,  // Sanitized media.filename (image/file)
+    pub mime: String,
+    pub message_type: MessageType,
     pub message_id: String,
     pub sender_device_id: String,
 }
 
-#[derive(Debug)]
-pub struct DecryptionError {
-    pub reason: String,
-}
-
-impl std::fmt::Display for DecryptionError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Decryption failed: {}", self.reason)
-    }
-}
-
 impl E2EEReceiver {
+    /// Metadata schema versions this client verifies. Blob formats evolve
+    /// independently through the self-describing blob header.
+    const SUPPORTED_VERSIONS: &'static [&'static str] = &["2A"];
+
     /// Creates receiver with default Windows key storage
-    pub fn new() -> Result<Self, DecryptionError> {
-        let key_manager = KeyManager::default_windows()
-            .map_err(|e| DecryptionError { reason: e })?;
+    pub fn new(passphrase: &str) -> CryptoResult<Self> {
+        let key_manager = KeyManager::default_windows(passphrase)?;
 
-        Ok(E2EEReceiver { key_manager })
+        Ok(E2EEReceiver { key_manager, replay_guard: None, key_pinning: None, revocations: None })
     }
 
     /// Creates receiver with custom key directory
-    pub fn with_key_dir(key_dir: &str) -> Result<Self, DecryptionError> {
-        let key_manager = KeyManager::new(key_dir)
-            .map_err(|e| DecryptionError { reason: e })?;
+    pub fn with_key_dir(key_dir: &str, passphrase: &str) -> CryptoResult<Self> {
+        let key_manager = KeyManager::with_passphrase(key_dir, passphrase)?;
+
+        Ok(E2EEReceiver { key_manager, replay_guard: None, key_pinning: None, revocations: None })
+    }
 
-        Ok(E2EEReceiver { key_manager })
+    /// Creates receiver over an existing KeyManager (any backend)
+    pub fn with_key_manager(key_manager: KeyManager) -> Self {
+        E2EEReceiver { key_manager, replay_guard: None, key_pinning: None, revocations: None }
     }
 
-    /// Main decryption pipeline
+    /// Enables freshness and replay checks (recommended for anything that
+    /// feeds the clipboard)
+    pub fn with_replay_guard(mut self, replay_guard: ReplayGuard) -> Self {
+        self.replay_guard = Some(replay_guard);
+        self
+    }
+
+    /// Enables trust-on-first-use pinning of sender keys
+    pub fn with_key_pinning(mut self, key_pinning: KeyPinning) -> Self {
+        self.key_pinning = Some(key_pinning);
+        self
+    }
+
+    /// Rejects senders revoked by a signed statement, whatever the server says
+    pub fn with_revocations(mut self, revocations: DeviceRevocations) -> Self {
+        self.revocations = Some(revocations);
+        self
+    }
+
+    /// Main decryption pipeline; records the messageId as seen on success
     /// 
     /// # Arguments
-    /// * `message_doc` - Firestore message document (as JSON)
+    /// * `message_doc` - Firestore message document
     /// * `this_device_id` - This device's UUID
     /// * `sender_device_doc` - Firestore device document for sender (with pubSignKey, status)
     /// * `blob` - Encrypted blob from Cloud Storage
     /// 
     /// # Returns
-    /// DecryptionResult with plaintext, or DecryptionError
+    /// DecryptionResult with plaintext, or CryptoError describing the failure class
     pub fn decrypt_message(
         &self,
-        message_doc: &Value,
+        message_doc: &MessageDoc,
+        this_device_id: &str,
+        sender_device_doc: &DeviceDoc,
+        blob: &[u8],
+    ) -> CryptoResult<DecryptionResult> {
+        let result = self.open_message(message_doc, this_device_id, sender_device_doc, blob)?;
+        self.confirm_delivery(&result)?;
+        Ok(result)
+    }
+
+    /// Verifies and decrypts like `decrypt_message` without recording the
+    /// messageId; call `confirm_delivery` once the result has been stored
+    pub fn open_message(
+        &self,
+        message_doc: &MessageDoc,
         this_device_id: &str,
-        sender_device_doc: &Value,
+        sender_device_doc: &DeviceDoc,
         blob: &[u8],
-    ) -> Result<DecryptionResult, DecryptionError> {
+    ) -> CryptoResult<DecryptionResult> {
         // Step 1: Hard checks (schema version, storage format)
         self.hard_checks(message_doc)?;
 
-        // Step 2: Verify sender device
-        self.verify_sender_device(sender_device_doc)?;
+        // Step 2: Verify sender device (status + identity binding)
+        self.verify_sender_device(message_doc, sender_device_doc)?;
+
+        // Step 3: Sender keys must match the pin (unknown senders are pinned once the signature verifies)
+        let pin_status = match &self.key_pinning {
+            Some(pinning) => Some(pinning.check(sender_device_doc)?),
+            None => None,
+        };
 
-        // Step 3: Verify metaHash integrity
+        // Step 4: Verify metaHash integrity
         let meta_hash = self.verify_meta_hash(message_doc)?;
 
-        // Step 4: Verify signature
-        let sender_pub_sign_key = sender_device_doc.get("pubSignKey")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing sender public signing key".to_string(),
-            })?;
+        // Step 5: Verify signature (messages signed just before a sign key
+        // rotation verify with the key the pin moved away from)
+        let sender_pub_sign_key = sender_device_doc.pub_sign_key_bytes()?;
+
+        match self.verify_signature(message_doc, &sender_pub_sign_key, &meta_hash) {
+            Err(CryptoError::InvalidSignature) => {
+                let retired_key = match (&self.key_pinning, Self::created_at_client_secs(message_doc)) {
+                    (Some(pinning), Some(created_at)) => pinning.retired_sign_key(&sender_device_doc.device_id, created_at)?,
+                    _ => None,
+                };
+                let key = retired_key.ok_or(CryptoError::InvalidSignature)?;
+                self.verify_signature(message_doc, &key, &meta_hash)?;
+            }
+            result => result?,
+        }
+
+        if let (Some(pinning), Some(PinStatus::FirstUse)) = (&self.key_pinning, pin_status) {
+            pinning.pin(sender_device_doc)?;
+        }
+
+        // Step 6: This device and the envelope set must match the signed recipients
+        self.verify_recipients(message_doc, this_device_id)?;
 
-        self.verify_signature(message_doc, sender_pub_sign_key, &meta_hash)?;
+        // Step 7: Freshness of the (now authenticated) createdAtClient
+        if let Some(guard) = &self.replay_guard {
+            let created_at_client = message_doc.created_at_client.as_deref()
+                .ok_or_else(|| CryptoError::MalformedDocument("Missing createdAtClient".to_string()))?;
+            guard.check_fresh(created_at_client)?;
+            guard.check_unseen(&message_doc.message_id)?;
+        }
 
-        // Step 5: Obtain envelope for this device
+        // Step 8: Obtain envelope for this device
         let dek = self.decrypt_dek(message_doc, this_device_id)?;
 
-        // Step 6: Parse blob
-        let (nonce, ciphertext) = BlobFormat::parse_blob(blob)
-            .map_err(|e| DecryptionError { reason: e })?;
+        // Step 9: Parse blob header and decrypt payload
+        let plaintext_bytes = VersionedBlob::open(blob, &dek, &meta_hash)?;
 
-        // Step 7: Decrypt payload
-        let plaintext_bytes = CryptoPrimitives::decrypt_aead(&ciphertext, &nonce, &dek, &meta_hash)
-            .ok_or_else(|| DecryptionError {
-                reason: "AEAD decryption failed (authentication failed or wrong key)".to_string(),
-            })?;
+        // Step 10: Validate according to message type
+        let message_type = message_doc.message_type;
 
-        // Step 8: Determine message type and validate accordingly
-        let message_type = message_doc.get("type")
-            .and_then(|v| v.as_str())
-            .unwrap_or("text")
-            .to_string();
+        let mut result = DecryptionResult {
+            plaintext: None,
+            image_bytes: None,
+            file_bytes: None,
+            filename: message_doc.media.as_ref()
+                .and_then(|m| m.filename.as_deref())
+                .map(FileSanitizer::sanitize_filename),
+            mime: message_doc.mime.clone(),
+            message_type,
+            message_id: message_doc.message_id.clone(),
+            sender_device_id: message_doc.sender_device_id.clone(),
+        };
 
-        let plaintext_opt = match message_type.as_str() {
-            "text" => {
+        match message_type {
+            MessageType::Text => {
                 // Phase 2A: Plain UTF-8 text
-                let plaintext = String::from_utf8(plaintext_bytes.clone())
-                    .map_err(|e| DecryptionError {
-                        reason: format!("Invalid UTF-8 in plaintext: {}", e),
-                    })?;
-                Some(plaintext)
+                let plaintext = String::from_utf8(plaintext_bytes)
+                    .map_err(|e| CryptoError::InvalidPayload(
+                        format!("Invalid UTF-8 in plaintext: {}", e),
+                    ))?;
+                result.plaintext = Some(plaintext);
             },
-            "image" => {
+            MessageType::Image => {
                 // Phase 2B: Image with magic byte validation
                 if !ImageValidator::validate_image_magic(&plaintext_bytes) {
-                    return Err(DecryptionError {
-                        reason: "Image magic bytes validation failed; payload corrupted or tampered".to_string(),
-                    });
+                    return Err(CryptoError::InvalidPayload(
+                        "Image magic bytes validation failed; payload corrupted or tampered".to_string(),
+                    ));
                 }
-                None  // Image bytes will be returned separately
+                result.image_bytes = Some(plaintext_bytes);
             },
-            _ => {
-                return Err(DecryptionError {
-                    reason: format!("Unsupported message type: {}", message_type),
-                });
-            }
-        };
+            MessageType::File => {
+                // Size is bound into the signed metadata
+                if message_doc.size_bytes_plain != Some(plaintext_bytes.len() as u64) {
+                    return Err(CryptoError::InvalidPayload(
+                        "File size does not match sizeBytesPlain".to_string(),
+                    ));
+                }
+                if result.filename.is_none() {
+                    result.filename = Some(FileSanitizer::sanitize_filename(""));
+                }
+                result.file_bytes = Some(plaintext_bytes);
+            },
+        }
 
-        let message_id = message_doc.get("messageId")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing messageId".to_string(),
-            })?
-            .to_string();
-
-        let sender_device_id = message_doc.get("senderDeviceId")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing senderDeviceId".to_string(),
-            })?
-            .to_string();
-
-        Ok(DecryptionResult {
-            plaintext: plaintext_opt,
-            image_bytes: if message_type == "image" { Some(plaintext_bytes) } else { None },
-            message_type,
-            message_id,
-            sender_device_id,
-        })
+        Ok(result)
+    }
+
+    /// Step 11: Records an opened message as seen; a second delivery of this
+    /// messageId fails from now on
+    pub fn confirm_delivery(&self, result: &DecryptionResult) -> CryptoResult<()> {
+        if let Some(guard) = &self.replay_guard {
+            guard.record(&result.message_id, &result.sender_device_id)?;
+        }
+        Ok(())
     }
 
     // Private helpers
 
-    fn hard_checks(&self, message_doc: &Value) -> Result<(), DecryptionError> {
+    fn hard_checks(&self, message_doc: &MessageDoc) -> CryptoResult<()> {
         // Check version
-        let version = message_doc.get("version")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
+        let version = message_doc.version.as_deref().unwrap_or("");
 
-        if !version.is_empty() && version != "2A" {
-            return Err(DecryptionError {
-                reason: format!("Only Phase 2A supported, got {}", version),
-            });
+        if !version.is_empty() && !Self::SUPPORTED_VERSIONS.contains(&version) {
+            return Err(CryptoError::UnsupportedVersion(version.to_string()));
         }
 
         // Check storage path ends with .bin
-        let storage_path = message_doc.get("storagePath")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing storagePath".to_string(),
-            })?;
-
-        if !storage_path.ends_with(".bin") {
-            return Err(DecryptionError {
-                reason: format!("Expected .bin storage, got {}", storage_path),
-            });
+        if !message_doc.storage_path.ends_with(".bin") {
+            return Err(CryptoError::MalformedDocument(
+                format!("Expected .bin storage, got {}", message_doc.storage_path),
+            ));
         }
 
         // Check required fields
-        for field in &["envelopes", "metaHash", "signature", "nonce"] {
-            if message_doc.get(field).is_none() {
-                return Err(DecryptionError {
-                    reason: format!("Missing required field: {}", field),
-                });
-            }
+        let missing = [
+            ("envelopes", message_doc.envelopes.is_none()),
+            ("metaHash", message_doc.meta_hash.is_none()),
+            ("signature", message_doc.signature.is_none()),
+            ("nonce", message_doc.nonce.is_none()),
+        ];
+        if let Some((field, _)) = missing.iter().find(|(_, is_missing)| *is_missing) {
+            return Err(CryptoError::MalformedDocument(
+                format!("Missing required field: {}", field),
+            ));
+        }
+
+        // Retention hints are covered by metaHash; a signed but unreadable expiry is still rejected
+        if let Some(expires_at) = &message_doc.expires_at {
+            chrono::DateTime::parse_from_rfc3339(expires_at)
+                .map_err(|e| CryptoError::MalformedDocument(format!("Invalid expiresAt: {}", e)))?;
         }
 
         Ok(())
     }
 
-    fn verify_sender_device(&self, sender_device_doc: &Value) -> Result<(), DecryptionError> {
-        let status = sender_device_doc.get("status")
-            .and_then(|v| v.as_str())
-            .unwrap_or("active");
-
-        if status == "revoked" {
-            return Err(DecryptionError {
-                reason: "Sender device is revoked".to_string(),
+    fn verify_sender_device(
+        &self,
+        message_doc: &MessageDoc,
+        sender_device_doc: &DeviceDoc,
+    ) -> CryptoResult<()> {
+        // The key we verify with must belong to the device the signed metadata names
+        if sender_device_doc.device_id != message_doc.sender_device_id {
+            return Err(CryptoError::SenderMismatch {
+                claimed: message_doc.sender_device_id.clone(),
+                document: sender_device_doc.device_id.clone(),
             });
         }
 
+        if sender_device_doc.status == DeviceStatus::Revoked {
+            return Err(CryptoError::SenderRevoked(sender_device_doc.device_id.clone()));
+        }
+
+        if let Some(revocations) = &self.revocations {
+            revocations.check(&sender_device_doc.device_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn verify_recipients(&self, message_doc: &MessageDoc, this_device_id: &str) -> CryptoResult<()> {
+        let recipients = message_doc.recipients.device_ids()
+            .ok_or_else(|| CryptoError::MalformedDocument(
+                "Recipients must be an explicit device list".to_string(),
+            ))?;
+
+        if !recipients.iter().any(|id| id == this_device_id) {
+            return Err(CryptoError::NotRecipient(this_device_id.to_string()));
+        }
+
+        let envelopes = message_doc.envelopes.as_ref()
+            .ok_or_else(|| CryptoError::MalformedDocument("Missing envelopes".to_string()))?;
+
+        // Envelopes are unsigned; their keys must be exactly the signed recipient set
+        let mut signed: Vec<&str> = recipients.iter().map(String::as_str).collect();
+        signed.sort_unstable();
+        signed.dedup();
+        let enveloped: Vec<&str> = envelopes.device_ids().collect();
+
+        if signed != enveloped {
+            return Err(CryptoError::Tampered(
+                "Envelope set does not match signed recipients".to_string(),
+            ));
+        }
+
         Ok(())
     }
 
-    fn verify_meta_hash(&self, message_doc: &Value) -> Result<Vec<u8>, DecryptionError> {
+    fn verify_meta_hash(&self, message_doc: &MessageDoc) -> CryptoResult<Vec<u8>> {
         // Reconstruct canonical metadata
-        let canonical_json = CanonicalMetadata::from_firestore_doc(message_doc)
-            .map_err(|e| DecryptionError { reason: e })?;
+        let canonical_json = CanonicalMetadata::from_message_doc(message_doc)?;
 
         // Compute candidate metaHash
         let meta_hash_candidate = CanonicalMetadata::compute_meta_hash(&canonical_json);
 
         // Compare with Firestore metaHash
-        let meta_hash_firestore_b64 = message_doc.get("metaHash")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing metaHash".to_string(),
-            })?;
+        let meta_hash_firestore_b64 = message_doc.meta_hash.as_deref()
+            .ok_or_else(|| CryptoError::MalformedDocument("Missing metaHash".to_string()))?;
 
         let meta_hash_firestore = general_purpose::STANDARD.decode(meta_hash_firestore_b64)
-            .map_err(|e| DecryptionError {
-                reason: format!("Failed to decode metaHash: {}", e),
-            })?;
+            .map_err(|e| CryptoError::MalformedDocument(format!("Failed to decode metaHash: {}", e)))?;
 
         if meta_hash_candidate != meta_hash_firestore {
-            return Err(DecryptionError {
-                reason: "metaHash mismatch; metadata tampered".to_string(),
-            });
+            return Err(CryptoError::Tampered("metaHash mismatch; metadata tampered".to_string()));
         }
 
         Ok(meta_hash_firestore)
@@ -242,94 +347,335 @@ impl E2EEReceiver {
 
     fn verify_signature(
         &self,
-        message_doc: &Value,
-        sender_pub_sign_key_b64: &str,
+        message_doc: &MessageDoc,
+        sender_pub_sign_key: &[u8],
         meta_hash: &[u8],
-    ) -> Result<(), DecryptionError> {
-        let signature_b64 = message_doc.get("signature")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing signature".to_string(),
-            })?;
+    ) -> CryptoResult<()> {
+        let signature_b64 = message_doc.signature.as_deref()
+            .ok_or_else(|| CryptoError::MalformedDocument("Missing signature".to_string()))?;
 
         let signature = general_purpose::STANDARD.decode(signature_b64)
-            .map_err(|e| DecryptionError {
-                reason: format!("Failed to decode signature: {}", e),
-            })?;
-
-        let pub_key = general_purpose::STANDARD.decode(sender_pub_sign_key_b64)
-            .map_err(|e| DecryptionError {
-                reason: format!("Failed to decode public key: {}", e),
-            })?;
-
-        if !CryptoPrimitives::verify(meta_hash, &signature, &pub_key) {
-            return Err(DecryptionError {
-                reason: "Signature verification failed".to_string(),
-            });
+            .map_err(|e| CryptoError::MalformedDocument(format!("Failed to decode signature: {}", e)))?;
+
+        if !CryptoPrimitives::verify(meta_hash, &signature, sender_pub_sign_key) {
+            return Err(CryptoError::InvalidSignature);
         }
 
         Ok(())
     }
 
+    fn created_at_client_secs(message_doc: &MessageDoc) -> Option<i64> {
+        message_doc.created_at_client.as_deref()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.timestamp())
+    }
+
     fn decrypt_dek(
         &self,
-        message_doc: &Value,
+        message_doc: &MessageDoc,
         this_device_id: &str,
-    ) -> Result<Vec<u8>, DecryptionError> {
+    ) -> CryptoResult<Vec<u8>> {
         // Get envelope for this device
-        let envelopes = message_doc.get("envelopes")
-            .and_then(|v| v.as_object())
-            .ok_or_else(|| DecryptionError {
-                reason: "Missing envelopes".to_string(),
-            })?;
+        let envelopes = message_doc.envelopes.as_ref()
+            .ok_or_else(|| CryptoError::MalformedDocument("Missing envelopes".to_string()))?;
 
         let envelope_b64 = envelopes.get(this_device_id)
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| DecryptionError {
-                reason: format!("No envelope for device {}; not a recipient", this_device_id),
-            })?;
+            .ok_or_else(|| CryptoError::NotRecipient(this_device_id.to_string()))?;
 
         let envelope = general_purpose::STANDARD.decode(envelope_b64)
-            .map_err(|e| DecryptionError {
-                reason: format!("Failed to decode envelope: {}", e),
-            })?;
-
-        // Retrieve this device's box keys
-        let box_pk = self.key_manager.get_box_public_key()
-            .map_err(|e| DecryptionError { reason: e })?;
+            .map_err(|e| CryptoError::MalformedDocument(format!("Failed to decode envelope: {}", e)))?;
 
-        let box_sk = self.key_manager.get_box_private_key()
-            .map_err(|e| DecryptionError { reason: e })?;
+        // Try the current box keys, then retired generations still in their grace period
+        for keys in self.key_manager.decryption_box_keys()? {
+            if let Ok(dek) = CryptoPrimitives::open_sealed_box(&envelope, &keys.public_key, &keys.private_key) {
+                return Ok(dek);
+            }
+        }
 
-        // Decrypt DEK from sealed box
-        CryptoPrimitives::open_sealed_box(&envelope, &box_pk, &box_sk)
-            .ok_or_else(|| DecryptionError {
-                reason: "Failed to decrypt DEK (sealed box open failed)".to_string(),
-            })
+        // A sealed box gives no hint which key it was sealed to, so this is
+        // reported as a missing key rather than tampering
+        Err(CryptoError::NoMatchingKey(
+            "Envelope does not open with any current or retired key".to_string(),
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::identity::DeviceIdentity;
+    use super::super::test_util::TestDevice;
+    use super::super::schema::RetentionHints;
 
     #[test]
     fn test_hard_checks_missing_version() {
-        let receiver = E2EEReceiver::new().expect("Failed to create receiver");
+        let receiver = E2EEReceiver::with_key_manager(KeyManager::in_memory());
         
-        let mut doc = serde_json::json!({
+        let mut doc = MessageDoc::from_value(&serde_json::json!({
+            "messageId": "msg-123",
+            "senderDeviceId": "dev-456",
+            "recipients": ["dev-789"],
             "storagePath": "users/uid/messages/msg-123.bin",
             "envelopes": {},
             "metaHash": "test",
             "signature": "test",
             "nonce": "test"
-        });
+        }))
+        .expect("Valid message document");
 
         // Should pass without version (backwards compatible)
         assert!(receiver.hard_checks(&doc).is_ok());
 
         // Add invalid version
-        doc["version"] = Value::String("1.0".to_string());
-        assert!(receiver.hard_checks(&doc).is_err());
+        doc.version = Some("1.0".to_string());
+        assert!(matches!(
+            receiver.hard_checks(&doc),
+            Err(CryptoError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_failure_classes_are_distinguishable() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let laptop = TestDevice::provision("dev-laptop");
+
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "secret")
+            .unwrap();
+        let receiver = phone.receiver();
+
+        // Not addressed to this device
+        assert!(matches!(
+            laptop.receiver().decrypt_message(&encrypted.message_doc, "dev-laptop", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::NotRecipient(_))
+        ));
+
+        // Signed by a different key than the sender document claims
+        let mut forged_sender = pc.device_doc();
+        forged_sender.pub_sign_key = Some(laptop.sign_pk.clone());
+        assert!(matches!(
+            receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &forged_sender, &encrypted.blob),
+            Err(CryptoError::InvalidSignature)
+        ));
+
+        // Metadata altered after signing
+        let mut tampered = encrypted.message_doc.clone();
+        tampered.size_bytes_plain = Some(999);
+        assert!(matches!(
+            receiver.decrypt_message(&tampered, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::Tampered(_))
+        ));
+
+        // Revoked sender
+        let mut revoked = pc.device_doc();
+        revoked.status = DeviceStatus::Revoked;
+        assert!(matches!(
+            receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &revoked, &encrypted.blob),
+            Err(CryptoError::SenderRevoked(_))
+        ));
+
+        // Truncated blob
+        assert!(matches!(
+            receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob[..10]),
+            Err(CryptoError::MalformedBlob(_))
+        ));
+
+        // Key store missing
+        let unprovisioned = E2EEReceiver::with_key_manager(KeyManager::in_memory());
+        assert!(matches!(
+            unprovisioned.decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::KeyStoreUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_decrypts_envelope_sealed_to_rotated_key() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+
+        // Sender still has the phone's pre-rotation box key
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "in flight")
+            .unwrap();
+        phone.key_manager().rotate_keys(std::time::Duration::from_secs(3600)).unwrap();
+
+        let result = phone.receiver()
+            .decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap();
+        assert_eq!(result.plaintext.as_deref(), Some("in flight"));
+    }
+
+    #[test]
+    fn test_envelope_for_expired_generation_is_no_matching_key() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "too late")
+            .unwrap();
+        // Zero grace: the retired generation has expired by the time it is needed
+        phone.key_manager().rotate_keys(std::time::Duration::ZERO).unwrap();
+
+        let err = phone.receiver()
+            .decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap_err();
+        assert!(matches!(err, CryptoError::NoMatchingKey(_)));
+        assert_eq!(err.code(), "no_matching_key");
+    }
+
+    #[test]
+    fn test_stripped_retention_hints_rejected() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let encrypted = pc.sender()
+            .with_retention(RetentionHints {
+                expires_at: Some("2026-01-28T16:50:00Z".to_string()),
+                ephemeral: Some(true),
+            })
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "hunter2")
+            .unwrap();
+
+        let result = phone.receiver()
+            .decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap();
+        assert_eq!(result.plaintext.as_deref(), Some("hunter2"));
+
+        let mut stripped = encrypted.message_doc.clone();
+        stripped.ephemeral = None;
+        assert!(matches!(
+            phone.receiver().decrypt_message(&stripped, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::Tampered(_))
+        ));
+
+        let mut extended = encrypted.message_doc.clone();
+        extended.expires_at = Some("2099-01-01T00:00:00Z".to_string());
+        assert!(matches!(
+            phone.receiver().decrypt_message(&extended, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::Tampered(_))
+        ));
+    }
+
+    #[test]
+    fn test_replayed_message_rejected() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let receiver = phone.receiver().with_replay_guard(ReplayGuard::in_memory());
+
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "paste me once")
+            .unwrap();
+
+        receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap();
+        assert!(matches!(
+            receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::Replayed(_))
+        ));
+    }
+
+    #[test]
+    fn test_sender_and_recipient_bindings_enforced() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let laptop = TestDevice::provision("dev-laptop");
+        let receiver = phone.receiver();
+
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "bound")
+            .unwrap();
+
+        // Server substitutes another device's document (with a valid key of its own)
+        assert!(matches!(
+            receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &laptop.device_doc(), &encrypted.blob),
+            Err(CryptoError::SenderMismatch { .. })
+        ));
+
+        // Server grafts an extra envelope for a device outside the signed recipients
+        let mut grafted = encrypted.message_doc.clone();
+        grafted.envelopes.as_mut().unwrap().insert("dev-laptop", "AAAA");
+        assert!(matches!(
+            receiver.decrypt_message(&grafted, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::Tampered(_))
+        ));
+        assert!(matches!(
+            laptop.receiver().decrypt_message(&grafted, "dev-laptop", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::NotRecipient(_))
+        ));
+    }
+
+    #[test]
+    fn test_message_signed_before_sender_rotation_still_verifies() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let pc_identity = DeviceIdentity::new(pc.key_manager());
+        let pinning = KeyPinning::in_memory();
+        pinning.pin(&pc_identity.registration_doc("PC").unwrap()).unwrap();
+
+        // Queued offline, delivered after the sender rotated its keys
+        let in_flight = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "queued")
+            .unwrap();
+        let rotated = pc_identity.rotate("PC", std::time::Duration::from_secs(3600)).unwrap();
+
+        let receiver = phone.receiver().with_key_pinning(pinning.clone());
+        let result = receiver
+            .decrypt_message(&in_flight.message_doc, "dev-phone", &rotated, &in_flight.blob)
+            .unwrap();
+        assert_eq!(result.plaintext.as_deref(), Some("queued"));
+        assert_eq!(pinning.pinned("dev-pc").unwrap().unwrap().pub_sign_key, rotated.pub_sign_key.unwrap());
+
+        // Without the pin there is no verified record of the old key
+        let unpinned = phone.receiver().with_key_pinning(KeyPinning::in_memory());
+        let rotated = pc_identity.registration_doc("PC").unwrap();
+        assert!(matches!(
+            unpinned.decrypt_message(&in_flight.message_doc, "dev-phone", &rotated, &in_flight.blob),
+            Err(CryptoError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_sender_key_substitution_after_pinning() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let pinning = KeyPinning::in_memory();
+        let receiver = phone.receiver().with_key_pinning(pinning.clone());
+
+        let first = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "first")
+            .unwrap();
+        receiver.decrypt_message(&first.message_doc, "dev-phone", &pc.device_doc(), &first.blob)
+            .unwrap();
+        assert!(pinning.pinned("dev-pc").unwrap().is_some());
+
+        // Backend swaps in its own key for dev-pc and signs with it
+        let impostor = TestDevice::provision("dev-pc");
+        let forged = impostor.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "forged")
+            .unwrap();
+        assert!(matches!(
+            receiver.decrypt_message(&forged.message_doc, "dev-phone", &impostor.device_doc(), &forged.blob),
+            Err(CryptoError::KeyChanged(_))
+        ));
+    }
+
+    #[test]
+    fn test_locally_revoked_sender_rejected_despite_active_status() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        phone.key_manager().store_device_id("dev-phone").unwrap();
+        let revocations = DeviceRevocations::in_memory(phone.key_manager(), KeyPinning::in_memory());
+        let receiver = phone.receiver().with_revocations(revocations.clone());
+
+        revocations.revoke("dev-pc", Some("stolen")).unwrap();
+
+        // Server still reports the device as active
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "hello")
+            .unwrap();
+        assert!(matches!(
+            receiver.decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::SenderRevoked(_))
+        ));
     }
 }
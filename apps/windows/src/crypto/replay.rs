@@ -0,0 +1,217 @@
+//! Replay and duplicate-delivery protection
+//!
+//! A message is accepted only if its signed `createdAtClient` falls inside the
+//! freshness window and its messageId has never been accepted before. Accepted
+//! IDs are recorded in a `SeenMessageLedger`. That is `db::SqliteSeenLedger`
+//! in the app, so the record survives restarts. IDs only need to be kept
+//! for as long as the window is open, because anything older fails the age
+//! check anyway.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use super::error::{CryptoError, CryptoResult};
+
+/// Persistent record of accepted message IDs
+pub trait SeenMessageLedger: Send + Sync {
+    /// Atomically records `message_id` if unseen
+    ///
+    /// # Returns
+    /// true if newly recorded, false if it was already present
+    fn record_if_new(&self, message_id: &str, sender_device_id: &str, seen_at: i64) -> CryptoResult<bool>;
+
+    /// Whether `message_id` was recorded (and not yet pruned)
+    fn is_seen(&self, message_id: &str) -> CryptoResult<bool>;
+
+    /// Forgets entries seen before `cutoff` (Unix seconds)
+    fn prune_before(&self, cutoff: i64) -> CryptoResult<()>;
+}
+
+/// In-memory ledger (tests, ephemeral sessions)
+#[derive(Default)]
+pub struct MemorySeenLedger {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl MemorySeenLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SeenMessageLedger for MemorySeenLedger {
+    fn record_if_new(&self, message_id: &str, _sender_device_id: &str, seen_at: i64) -> CryptoResult<bool> {
+        let mut seen = self.seen.lock()
+            .map_err(|_| CryptoError::LedgerUnavailable("Ledger lock poisoned".to_string()))?;
+        if seen.contains_key(message_id) {
+            return Ok(false);
+        }
+        seen.insert(message_id.to_string(), seen_at);
+        Ok(true)
+    }
+
+    fn is_seen(&self, message_id: &str) -> CryptoResult<bool> {
+        let seen = self.seen.lock()
+            .map_err(|_| CryptoError::LedgerUnavailable("Ledger lock poisoned".to_string()))?;
+        Ok(seen.contains_key(message_id))
+    }
+
+    fn prune_before(&self, cutoff: i64) -> CryptoResult<()> {
+        let mut seen = self.seen.lock()
+            .map_err(|_| CryptoError::LedgerUnavailable("Ledger lock poisoned".to_string()))?;
+        seen.retain(|_, seen_at| *seen_at >= cutoff);
+        Ok(())
+    }
+}
+
+/// Accepted range for `createdAtClient` relative to the local clock
+///
+/// `max_age` also bounds delivery on the sending side: a message is signed
+/// once, when it is copied, so one that waits in the outbox longer than
+/// `max_age` (3 days by default) is never uploaded (`OutboxFlusher` marks it
+/// failed) and needs to be copied again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreshnessWindow {
+    /// Oldest message still accepted
+    pub max_age: Duration,
+    /// How far in the future a sender's clock may run
+    pub max_clock_skew: Duration,
+}
+
+impl Default for FreshnessWindow {
+    fn default() -> Self {
+        FreshnessWindow {
+            max_age: Duration::from_secs(3 * 24 * 60 * 60),
+            max_clock_skew: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Freshness window plus seen-message ledger, consulted by `E2EEReceiver`
+#[derive(Clone)]
+pub struct ReplayGuard {
+    ledger: Arc<dyn SeenMessageLedger>,
+    window: FreshnessWindow,
+}
+
+impl ReplayGuard {
+    pub fn new(ledger: Arc<dyn SeenMessageLedger>, window: FreshnessWindow) -> Self {
+        ReplayGuard { ledger, window }
+    }
+
+    /// Guard over an in-memory ledger with the default window
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(MemorySeenLedger::new()), FreshnessWindow::default())
+    }
+
+    pub fn window(&self) -> FreshnessWindow {
+        self.window
+    }
+
+    /// Rejects messages whose signed creation time is outside the window
+    ///
+    /// # Arguments
+    /// * `created_at_client` - RFC 3339 timestamp from the signed metadata
+    pub fn check_fresh(&self, created_at_client: &str) -> CryptoResult<()> {
+        self.check_fresh_at(created_at_client, Utc::now())
+    }
+
+    /// Rejects a messageId that was already accepted (without recording it)
+    pub fn check_unseen(&self, message_id: &str) -> CryptoResult<()> {
+        if self.ledger.is_seen(message_id)? {
+            return Err(CryptoError::Replayed(message_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Records the message as accepted, failing if it was accepted before
+    ///
+    /// Call only after the message fully verified and decrypted (and, when it
+    /// is persisted, after it was stored), so forged or corrupt deliveries
+    /// cannot burn a legitimate messageId and a failed store can be retried.
+    pub fn record(&self, message_id: &str, sender_device_id: &str) -> CryptoResult<()> {
+        let now = Utc::now().timestamp();
+        if !self.ledger.record_if_new(message_id, sender_device_id, now)? {
+            return Err(CryptoError::Replayed(message_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Drops ledger entries that the age check alone now rejects
+    pub fn prune(&self) -> CryptoResult<()> {
+        let cutoff = Utc::now().timestamp() - self.window.max_age.as_secs() as i64
+            - self.window.max_clock_skew.as_secs() as i64;
+        self.ledger.prune_before(cutoff)
+    }
+
+    fn check_fresh_at(&self, created_at_client: &str, now: DateTime<Utc>) -> CryptoResult<()> {
+        let created_at = DateTime::parse_from_rfc3339(created_at_client)
+            .map_err(|e| CryptoError::MalformedDocument(
+                format!("Invalid createdAtClient {:?}: {}", created_at_client, e),
+            ))?
+            .with_timezone(&Utc);
+
+        let age = now.signed_duration_since(created_at);
+        let max_age = chrono::Duration::from_std(self.window.max_age)
+            .unwrap_or(chrono::Duration::MAX);
+        let max_skew = chrono::Duration::from_std(self.window.max_clock_skew)
+            .unwrap_or(chrono::Duration::MAX);
+
+        if age > max_age {
+            return Err(CryptoError::Stale(format!("created {}s ago", age.num_seconds())));
+        }
+        if -age > max_skew {
+            return Err(CryptoError::Stale(format!("created {}s in the future", -age.num_seconds())));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_freshness_window() {
+        let guard = ReplayGuard::new(
+            Arc::new(MemorySeenLedger::new()),
+            FreshnessWindow {
+                max_age: Duration::from_secs(3600),
+                max_clock_skew: Duration::from_secs(60),
+            },
+        );
+        let now = at("2026-01-28T12:00:00Z");
+
+        assert!(guard.check_fresh_at("2026-01-28T11:30:00.000Z", now).is_ok());
+        assert!(guard.check_fresh_at("2026-01-28T12:00:30Z", now).is_ok());
+        assert!(matches!(
+            guard.check_fresh_at("2026-01-28T10:59:59Z", now),
+            Err(CryptoError::Stale(_))
+        ));
+        assert!(matches!(
+            guard.check_fresh_at("2026-01-28T12:05:00Z", now),
+            Err(CryptoError::Stale(_))
+        ));
+        assert!(matches!(
+            guard.check_fresh_at("yesterday", now),
+            Err(CryptoError::MalformedDocument(_))
+        ));
+    }
+
+    #[test]
+    fn test_second_record_is_replay() {
+        let guard = ReplayGuard::in_memory();
+
+        guard.check_unseen("msg-1").unwrap();
+        guard.record("msg-1", "dev-a").unwrap();
+        assert!(matches!(guard.check_unseen("msg-1"), Err(CryptoError::Replayed(_))));
+        assert!(matches!(guard.record("msg-1", "dev-a"), Err(CryptoError::Replayed(_))));
+        guard.record("msg-2", "dev-a").unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//! Signed device revocations
+//!
+//! A revocation is a statement "device X is revoked" signed by another
+//! trusted device of the same user: this device or one whose keys are pinned.
+//! Verified statements are cached in a `RevocationStore`
+//! (`db::SqliteRevocationStore` in the app) and are never undone, so the
+//! server flipping `status` back to "active" does not reinstate a lost phone.
+//! Lists are exchanged between devices with `export` / `import`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use base64::{Engine, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use super::primitives::CryptoPrimitives;
+use super::key_mgmt::KeyManager;
+use super::trust::KeyPinning;
+use super::error::{CryptoError, CryptoResult};
+
+const EXPORT_PREFIX: &str = "scap-revocations:1:";
+
+/// "Device `revoked_device_id` is revoked", signed by `issuer_device_id`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevocationStatement {
+    pub revoked_device_id: String,
+    pub issuer_device_id: String,
+    pub issued_at: i64,  // Unix seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub signature: String,  // base64 Ed25519 over `signed_bytes`
+}
+
+impl RevocationStatement {
+    const DOMAIN: &'static [u8] = b"SCAP-REVOKE-1";
+
+    /// Domain tag, length-prefixed IDs, issuedAt (i64 BE), length-prefixed reason
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Self::DOMAIN.to_vec();
+        for field in [
+            self.revoked_device_id.as_str(),
+            self.issuer_device_id.as_str(),
+        ] {
+            bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(field.as_bytes());
+        }
+        bytes.extend_from_slice(&self.issued_at.to_be_bytes());
+        let reason = self.reason.as_deref().unwrap_or("");
+        bytes.extend_from_slice(&(reason.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(reason.as_bytes());
+        bytes
+    }
+
+    fn verify_with(&self, pub_sign_key: &[u8]) -> bool {
+        match general_purpose::STANDARD.decode(&self.signature) {
+            Ok(signature) => CryptoPrimitives::verify(&self.signed_bytes(), &signature, pub_sign_key),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Local cache of verified revocation statements
+pub trait RevocationStore: Send + Sync {
+    fn get_revocation(&self, device_id: &str) -> CryptoResult<Option<RevocationStatement>>;
+    /// Stores the statement unless the device is already revoked
+    fn put_revocation(&self, statement: &RevocationStatement) -> CryptoResult<()>;
+    fn list_revocations(&self) -> CryptoResult<Vec<RevocationStatement>>;
+}
+
+/// In-memory revocation store (tests, ephemeral sessions)
+#[derive(Default)]
+pub struct MemoryRevocationStore {
+    revocations: Mutex<HashMap<String, RevocationStatement>>,
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> CryptoResult<std::sync::MutexGuard<'_, HashMap<String, RevocationStatement>>> {
+        self.revocations.lock()
+            .map_err(|_| CryptoError::RevocationStoreUnavailable("Revocation store lock poisoned".to_string()))
+    }
+}
+
+impl RevocationStore for MemoryRevocationStore {
+    fn get_revocation(&self, device_id: &str) -> CryptoResult<Option<RevocationStatement>> {
+        Ok(self.lock()?.get(device_id).cloned())
+    }
+
+    fn put_revocation(&self, statement: &RevocationStatement) -> CryptoResult<()> {
+        self.lock()?
+            .entry(statement.revoked_device_id.clone())
+            .or_insert_with(|| statement.clone());
+        Ok(())
+    }
+
+    fn list_revocations(&self) -> CryptoResult<Vec<RevocationStatement>> {
+        let mut statements: Vec<_> = self.lock()?.values().cloned().collect();
+        statements.sort_by(|a, b| a.revoked_device_id.cmp(&b.revoked_device_id));
+        Ok(statements)
+    }
+}
+
+/// Issues, verifies and answers queries about revocations, consulted by `E2EEReceiver`
+#[derive(Clone)]
+pub struct DeviceRevocations {
+    key_manager: KeyManager,
+    pinning: KeyPinning,
+    store: Arc<dyn RevocationStore>,
+}
+
+impl DeviceRevocations {
+    /// # Arguments
+    /// * `key_manager` - This device's keys (device ID must be provisioned)
+    /// * `pinning` - Pinned peers; their statements are trusted
+    /// * `store` - Cache of accepted statements
+    pub fn new(key_manager: KeyManager, pinning: KeyPinning, store: Arc<dyn RevocationStore>) -> Self {
+        DeviceRevocations { key_manager, pinning, store }
+    }
+
+    pub fn in_memory(key_manager: KeyManager, pinning: KeyPinning) -> Self {
+        Self::new(key_manager, pinning, Arc::new(MemoryRevocationStore::new()))
+    }
+
+    /// Signs and records a revocation issued by this device
+    pub fn revoke(&self, device_id: &str, reason: Option<&str>) -> CryptoResult<RevocationStatement> {
+        let issuer_device_id = self.own_device_id()?;
+        if device_id == issuer_device_id {
+            return Err(CryptoError::InvalidRevocation("A device cannot revoke itself".to_string()));
+        }
+
+        let mut statement = RevocationStatement {
+            revoked_device_id: device_id.to_string(),
+            issuer_device_id,
+            issued_at: chrono::Utc::now().timestamp(),
+            reason: reason.map(str::to_string),
+            signature: String::new(),
+        };
+        let sign_sk = self.key_manager.get_sign_private_key()?;
+        statement.signature = general_purpose::STANDARD
+            .encode(CryptoPrimitives::sign(&statement.signed_bytes(), &sign_sk)?);
+
+        self.store.put_revocation(&statement)?;
+        Ok(statement)
+    }
+
+    /// Verifies a statement from another device and records it
+    ///
+    /// The issuer must be this device (any key generation) or a pinned
+    /// device that is not itself revoked.
+    pub fn accept(&self, statement: &RevocationStatement) -> CryptoResult<()> {
+        self.verify_issuer(statement)?;
+        self.store.put_revocation(statement)
+    }
+
+    /// Fails with `SenderRevoked` if a verified revocation exists for the device
+    pub fn check(&self, device_id: &str) -> CryptoResult<()> {
+        if self.is_revoked(device_id)? {
+            return Err(CryptoError::SenderRevoked(device_id.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, device_id: &str) -> CryptoResult<bool> {
+        Ok(self.store.get_revocation(device_id)?.is_some())
+    }
+
+    pub fn revocations(&self) -> CryptoResult<Vec<RevocationStatement>> {
+        self.store.list_revocations()
+    }
+
+    /// Encodes all cached statements as `scap-revocations:1:<base64url JSON>`
+    pub fn export(&self) -> CryptoResult<String> {
+        let json = serde_json::to_vec(&self.store.list_revocations()?)
+            .map_err(|e| CryptoError::InvalidRevocation(e.to_string()))?;
+        Ok(format!("{}{}", EXPORT_PREFIX, general_purpose::URL_SAFE_NO_PAD.encode(json)))
+    }
+
+    /// Verifies and records every statement in an exported list
+    ///
+    /// All statements are checked before any is stored, so a list with one
+    /// forged or untrusted entry is rejected as a whole. Statements issued by
+    /// a revoked device are ignored.
+    ///
+    /// # Returns
+    /// Number of devices newly revoked
+    pub fn import(&self, exported: &str) -> CryptoResult<usize> {
+        let encoded = exported.trim().strip_prefix(EXPORT_PREFIX)
+            .ok_or_else(|| CryptoError::InvalidRevocation("Not a revocation list".to_string()))?;
+        let json = general_purpose::URL_SAFE_NO_PAD.decode(encoded)
+            .map_err(|e| CryptoError::InvalidRevocation(format!("Invalid encoding: {}", e)))?;
+        let statements: Vec<RevocationStatement> = serde_json::from_slice(&json)
+            .map_err(|e| CryptoError::InvalidRevocation(format!("Invalid list: {}", e)))?;
+
+        // Statements from issuers revoked here or in the list itself are skipped;
+        // anything else that fails verification rejects the list
+        let mut trusted = Vec::new();
+        for statement in &statements {
+            let issuer = &statement.issuer_device_id;
+            if self.is_revoked(issuer)? || statements.iter().any(|s| &s.revoked_device_id == issuer) {
+                continue;
+            }
+            self.verify_issuer(statement)?;
+            trusted.push(statement);
+        }
+
+        let mut newly_revoked = 0;
+        for statement in trusted {
+            if !self.is_revoked(&statement.revoked_device_id)? {
+                newly_revoked += 1;
+            }
+            self.store.put_revocation(statement)?;
+        }
+        Ok(newly_revoked)
+    }
+
+    fn verify_issuer(&self, statement: &RevocationStatement) -> CryptoResult<()> {
+        if self.store.get_revocation(&statement.issuer_device_id)?.is_some() {
+            return Err(CryptoError::InvalidRevocation(format!(
+                "Issuer {} is revoked", statement.issuer_device_id
+            )));
+        }
+
+        let issuer_keys = self.issuer_sign_keys(&statement.issuer_device_id)?;
+        if issuer_keys.is_empty() {
+            return Err(CryptoError::InvalidRevocation(format!(
+                "Issuer {} is not a trusted device", statement.issuer_device_id
+            )));
+        }
+        if !issuer_keys.iter().any(|key| statement.verify_with(key)) {
+            return Err(CryptoError::InvalidRevocation(format!(
+                "Bad signature on revocation of {}", statement.revoked_device_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sign keys the issuer may have used: all of ours, or the pinned key of a peer
+    fn issuer_sign_keys(&self, issuer_device_id: &str) -> CryptoResult<Vec<Vec<u8>>> {
+        if self.key_manager.get_device_id()?.as_deref() == Some(issuer_device_id) {
+            return self.key_manager.key_generations()?
+                .iter()
+                .map(|generation| general_purpose::STANDARD.decode(&generation.sign_public)
+                    .map_err(|e| CryptoError::InvalidKey(e.to_string())))
+                .collect();
+        }
+
+        match self.pinning.pinned(issuer_device_id)? {
+            Some(pin) => {
+                let key = general_purpose::STANDARD.decode(&pin.pub_sign_key)
+                    .map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
+                Ok(vec![key])
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn own_device_id(&self) -> CryptoResult<String> {
+        self.key_manager.get_device_id()?
+            .ok_or_else(|| CryptoError::KeyStoreUnavailable("Device ID not provisioned".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::TestDevice;
+
+    /// Device with its ID stored, plus its own pins and revocation cache
+    fn device(device_id: &str) -> (TestDevice, KeyPinning, DeviceRevocations) {
+        let device = TestDevice::provision(device_id);
+        device.key_manager().store_device_id(device_id).unwrap();
+        let pinning = KeyPinning::in_memory();
+        let revocations = DeviceRevocations::in_memory(device.key_manager(), pinning.clone());
+        (device, pinning, revocations)
+    }
+
+    #[test]
+    fn test_revoke_export_import_between_trusted_devices() {
+        let (pc, _, pc_revocations) = device("dev-pc");
+        let (_laptop, laptop_pins, laptop_revocations) = device("dev-laptop");
+        laptop_pins.pin(&pc.device_doc()).unwrap();
+
+        pc_revocations.revoke("dev-lost-phone", Some("lost")).unwrap();
+        assert!(matches!(pc_revocations.check("dev-lost-phone"), Err(CryptoError::SenderRevoked(_))));
+
+        let exported = pc_revocations.export().unwrap();
+        assert!(exported.starts_with("scap-revocations:1:"));
+        assert_eq!(laptop_revocations.import(&exported).unwrap(), 1);
+        assert!(laptop_revocations.is_revoked("dev-lost-phone").unwrap());
+        assert_eq!(laptop_revocations.import(&exported).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_untrusted_or_tampered_statements_rejected() {
+        let (pc, _, pc_revocations) = device("dev-pc");
+        let (_laptop, laptop_pins, laptop_revocations) = device("dev-laptop");
+
+        let statement = pc_revocations.revoke("dev-phone", None).unwrap();
+
+        // Issuer not pinned on the laptop
+        assert!(matches!(laptop_revocations.accept(&statement), Err(CryptoError::InvalidRevocation(_))));
+
+        laptop_pins.pin(&pc.device_doc()).unwrap();
+        let mut retargeted = statement.clone();
+        retargeted.revoked_device_id = "dev-laptop-2".to_string();
+        assert!(matches!(laptop_revocations.accept(&retargeted), Err(CryptoError::InvalidRevocation(_))));
+
+        laptop_revocations.accept(&statement).unwrap();
+        assert!(laptop_revocations.is_revoked("dev-phone").unwrap());
+        assert!(!laptop_revocations.is_revoked("dev-laptop-2").unwrap());
+    }
+
+    #[test]
+    fn test_revoked_issuer_cannot_revoke() {
+        let (pc, _, pc_revocations) = device("dev-pc");
+        let (phone, _, phone_revocations) = device("dev-phone");
+        let (_laptop, laptop_pins, laptop_revocations) = device("dev-laptop");
+        laptop_pins.pin(&pc.device_doc()).unwrap();
+        laptop_pins.pin(&phone.device_doc()).unwrap();
+
+        let stolen_phone_says = phone_revocations.revoke("dev-pc", None).unwrap();
+        laptop_revocations.accept(&pc_revocations.revoke("dev-phone", None).unwrap()).unwrap();
+
+        assert!(matches!(
+            laptop_revocations.accept(&stolen_phone_says),
+            Err(CryptoError::InvalidRevocation(_))
+        ));
+        assert!(!laptop_revocations.is_revoked("dev-pc").unwrap());
+    }
+}
@@ -0,0 +1,512 @@
+//! Typed Firestore document models (Phase 1, 2A, 2B)
+//!
+//! Covers `users/{uid}/messages/{messageId}` and `users/{uid}/devices/{deviceId}`
+//! as described in docs/remote-paste/FIRESTORE_SCHEMA.md. Field names match the
+//! Firestore camelCase keys; optional fields are omitted when serializing.
+//!
+//! Use `MessageDoc::from_value` / `DeviceDoc::from_value` to deserialize and
+//! validate in one step.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use base64::{Engine, engine::general_purpose};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("Malformed document: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("Invalid field {field}: {reason}")]
+    InvalidField { field: &'static str, reason: String },
+    /// `type` names a payload this client does not know (e.g. from a newer sender)
+    #[error("Unsupported message type: {0}")]
+    UnsupportedType(String),
+}
+
+impl SchemaError {
+    fn invalid(field: &'static str, reason: impl Into<String>) -> Self {
+        SchemaError::InvalidField { field, reason: reason.into() }
+    }
+}
+
+/// Message payload type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageType {
+    #[default]
+    Text,
+    Image,
+    File,
+}
+
+impl MessageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageType::Text => "text",
+            MessageType::Image => "image",
+            MessageType::File => "file",
+        }
+    }
+}
+
+/// Message recipients: `"all"` (Phase 1) or an explicit device ID list (Phase 2A+)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawRecipients", into = "RawRecipients")]
+pub enum Recipients {
+    All,
+    Devices(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawRecipients {
+    Keyword(String),
+    Devices(Vec<String>),
+}
+
+impl TryFrom<RawRecipients> for Recipients {
+    type Error = String;
+
+    fn try_from(raw: RawRecipients) -> Result<Self, Self::Error> {
+        match raw {
+            RawRecipients::Keyword(k) if k == "all" => Ok(Recipients::All),
+            RawRecipients::Keyword(k) => Err(format!("expected \"all\" or an array, got \"{}\"", k)),
+            RawRecipients::Devices(ids) => Ok(Recipients::Devices(ids)),
+        }
+    }
+}
+
+impl From<Recipients> for RawRecipients {
+    fn from(recipients: Recipients) -> Self {
+        match recipients {
+            Recipients::All => RawRecipients::Keyword("all".to_string()),
+            Recipients::Devices(ids) => RawRecipients::Devices(ids),
+        }
+    }
+}
+
+impl Recipients {
+    /// Explicit device IDs, or None for `"all"`
+    pub fn device_ids(&self) -> Option<&[String]> {
+        match self {
+            Recipients::All => None,
+            Recipients::Devices(ids) => Some(ids),
+        }
+    }
+}
+
+/// Per-recipient DEK envelopes: `{ deviceId: base64(sealedBox(DEK)) }`
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Envelopes(BTreeMap<String, String>);
+
+impl Envelopes {
+    pub fn new() -> Self {
+        Envelopes(BTreeMap::new())
+    }
+
+    pub fn insert(&mut self, device_id: impl Into<String>, envelope_b64: impl Into<String>) {
+        self.0.insert(device_id.into(), envelope_b64.into());
+    }
+
+    /// Base64 envelope for a device, if present
+    pub fn get(&self, device_id: &str) -> Option<&str> {
+        self.0.get(device_id).map(String::as_str)
+    }
+
+    /// Device IDs with an envelope (sorted)
+    pub fn device_ids(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Phase 2B media metadata (`media` object)
+///
+/// Missing fields fall back to the Android defaults (`ext = "png"`, 0x0).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    #[serde(default = "MediaInfo::default_ext")]
+    pub ext: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub height: u32,
+    #[serde(default)]
+    pub width: u32,
+}
+
+impl MediaInfo {
+    fn default_ext() -> String {
+        "png".to_string()
+    }
+}
+
+/// Sender retention hints (`expiresAt` / `ephemeral`)
+///
+/// Bound into the signed canonical metadata, so relays cannot strip them;
+/// see `sensitive::Retention` for how they are chosen and applied.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RetentionHints {
+    /// RFC 3339
+    pub expires_at: Option<String>,
+    pub ephemeral: Option<bool>,
+}
+
+/// `users/{uid}/messages/{messageId}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDoc {
+    // Phase 1
+    pub message_id: String,
+    pub sender_device_id: String,
+    #[serde(rename = "type", default)]
+    pub message_type: MessageType,
+    /// Server timestamp (format depends on the transport; not signed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Value>,
+    pub recipients: Recipients,
+    pub storage_path: String,
+    #[serde(default = "MessageDoc::default_mime")]
+    pub mime: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+
+    // Phase 2A
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at_client: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelopes: Option<Envelopes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Algorithm descriptors (informational only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<Value>,
+
+    // Phase 2B
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes_plain: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaInfo>,
+
+    // Retention hints (signed when present; see `RetentionHints`)
+    /// RFC 3339; receivers delete the message from history after this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// Receivers keep the message out of their history list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ephemeral: Option<bool>,
+}
+
+impl MessageDoc {
+    fn default_mime() -> String {
+        "application/octet-stream".to_string()
+    }
+
+    /// Deserializes and validates a Firestore message document
+    pub fn from_value(value: &Value) -> Result<Self, SchemaError> {
+        if let Some(kind) = value.get("type").and_then(Value::as_str) {
+            if serde_json::from_value::<MessageType>(Value::from(kind)).is_err() {
+                return Err(SchemaError::UnsupportedType(kind.to_string()));
+            }
+        }
+        let doc: MessageDoc = serde_json::from_value(value.clone())?;
+        doc.validate()?;
+        Ok(doc)
+    }
+
+    /// Serializes back to the Firestore JSON shape
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).expect("MessageDoc is always serializable")
+    }
+
+    /// The document's retention hints
+    pub fn retention_hints(&self) -> RetentionHints {
+        RetentionHints {
+            expires_at: self.expires_at.clone(),
+            ephemeral: self.ephemeral,
+        }
+    }
+
+    /// Structural checks shared by all phases
+    pub fn validate(&self) -> Result<(), SchemaError> {
+        if self.message_id.is_empty() {
+            return Err(SchemaError::invalid("messageId", "must not be empty"));
+        }
+        if self.sender_device_id.is_empty() {
+            return Err(SchemaError::invalid("senderDeviceId", "must not be empty"));
+        }
+        if self.storage_path.is_empty() {
+            return Err(SchemaError::invalid("storagePath", "must not be empty"));
+        }
+
+        if let Recipients::Devices(ids) = &self.recipients {
+            if ids.is_empty() {
+                return Err(SchemaError::invalid("recipients", "must not be empty"));
+            }
+            if ids.iter().any(|id| id.is_empty()) {
+                return Err(SchemaError::invalid("recipients", "contains an empty device ID"));
+            }
+        }
+
+        if let Some(envelopes) = &self.envelopes {
+            if envelopes.device_ids().any(|id| id.is_empty()) {
+                return Err(SchemaError::invalid("envelopes", "contains an empty device ID"));
+            }
+        }
+
+        if let Some(media) = &self.media {
+            if media.ext.is_empty() {
+                return Err(SchemaError::invalid("media.ext", "must not be empty"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Device platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Android,
+    Windows,
+}
+
+/// Device trust status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    #[default]
+    Active,
+    Inactive,
+    Revoked,
+}
+
+/// One sign key rotation in a device document's `signKeyChain`
+///
+/// `sig` is made with the sign key being replaced (see `trust::SignKeyRotation`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignKeyLink {
+    /// Key generation that introduced `pubSignKey`
+    pub generation: u32,
+    /// New Ed25519 public key (base64)
+    pub pub_sign_key: String,
+    /// Unix seconds when the new key took over
+    pub rotated_at: i64,
+    /// Signature by the previous sign key (base64)
+    pub sig: String,
+}
+
+/// `users/{uid}/devices/{deviceId}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceDoc {
+    // Phase 1
+    pub device_id: String,
+    pub name: String,
+    pub platform: Platform,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<Value>,
+    #[serde(default)]
+    pub status: DeviceStatus,
+
+    // Phase 2A
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pub_sign_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pub_box_key: Option<String>,
+    /// Generation of `pubSignKey` / `pubBoxKey`; increases with every key
+    /// rotation (absent = 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_generation: Option<u32>,
+    /// `pubBoxKey` and `keyGeneration` signed with `pubSignKey` (see `trust::BoxKeyBinding`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub box_key_sig: Option<String>,
+    /// Sign key rotations, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sign_key_chain: Vec<SignKeyLink>,
+    /// Blob format versions this device can read (absent = "2A" only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blob_versions: Vec<String>,
+}
+
+impl DeviceDoc {
+    const PUBLIC_KEY_LEN: usize = 32;
+
+    /// Deserializes and validates a Firestore device document
+    pub fn from_value(value: &Value) -> Result<Self, SchemaError> {
+        let doc: DeviceDoc = serde_json::from_value(value.clone())?;
+        doc.validate()?;
+        Ok(doc)
+    }
+
+    /// Serializes back to the Firestore JSON shape
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).expect("DeviceDoc is always serializable")
+    }
+
+    /// Checks device ID and that any public keys are 32-byte base64
+    pub fn validate(&self) -> Result<(), SchemaError> {
+        if self.device_id.is_empty() {
+            return Err(SchemaError::invalid("deviceId", "must not be empty"));
+        }
+        if let Some(key) = &self.pub_sign_key {
+            Self::decode_key("pubSignKey", key)?;
+        }
+        if let Some(key) = &self.pub_box_key {
+            Self::decode_key("pubBoxKey", key)?;
+        }
+        Ok(())
+    }
+
+    /// `keyGeneration`, defaulting to 1 for documents published before rotation
+    pub fn key_generation(&self) -> u32 {
+        self.key_generation.unwrap_or(1)
+    }
+
+    /// Decoded Ed25519 public key
+    pub fn pub_sign_key_bytes(&self) -> Result<Vec<u8>, SchemaError> {
+        let key = self.pub_sign_key.as_deref()
+            .ok_or_else(|| SchemaError::invalid("pubSignKey", "missing"))?;
+        Self::decode_key("pubSignKey", key)
+    }
+
+    /// Decoded X25519 public key
+    pub fn pub_box_key_bytes(&self) -> Result<Vec<u8>, SchemaError> {
+        let key = self.pub_box_key.as_deref()
+            .ok_or_else(|| SchemaError::invalid("pubBoxKey", "missing"))?;
+        Self::decode_key("pubBoxKey", key)
+    }
+
+    fn decode_key(field: &'static str, key_b64: &str) -> Result<Vec<u8>, SchemaError> {
+        let bytes = general_purpose::STANDARD.decode(key_b64)
+            .map_err(|e| SchemaError::invalid(field, e.to_string()))?;
+        if bytes.len() != Self::PUBLIC_KEY_LEN {
+            return Err(SchemaError::invalid(
+                field,
+                format!("expected {} bytes, got {}", Self::PUBLIC_KEY_LEN, bytes.len()),
+            ));
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn phase2b_image_doc() -> Value {
+        json!({
+            "messageId": "770e8400-e29b-41d4-a716-446655440222",
+            "senderDeviceId": "550e8400-e29b-41d4-a716-446655440000",
+            "type": "image",
+            "createdAt": "2026-01-28T16:46:00Z",
+            "recipients": ["550e8400-e29b-41d4-a716-446655440000", "660e8400-e29b-41d4-a716-446655440222"],
+            "storagePath": "users/user-123/messages/770e8400-e29b-41d4-a716-446655440222.bin",
+            "mime": "image/png",
+            "sizeBytesPlain": 123456,
+            "nonce": "def456==",
+            "envelopes": {
+                "550e8400-e29b-41d4-a716-446655440000": "c2VhbGVkQQ==",
+                "660e8400-e29b-41d4-a716-446655440222": "c2VhbGVkQg=="
+            },
+            "metaHash": "aGFzaA==",
+            "signature": "c2ln",
+            "version": "2A",
+            "media": { "width": 1920, "height": 1080, "filename": "screenshot.png", "ext": "png" },
+            "alg": { "aead": "xchacha20poly1305", "wrap": "sealedbox-x25519", "sig": "ed25519" }
+        })
+    }
+
+    #[test]
+    fn test_phase1_doc_with_recipients_all() {
+        let doc = MessageDoc::from_value(&json!({
+            "messageId": "660e8400-e29b-41d4-a716-446655440111",
+            "senderDeviceId": "550e8400-e29b-41d4-a716-446655440000",
+            "type": "text",
+            "createdAt": "2026-01-28T16:45:00Z",
+            "recipients": "all",
+            "storagePath": "users/user-123/messages/660e8400-e29b-41d4-a716-446655440111.txt",
+            "mime": "text/plain",
+            "sizeBytes": 256
+        }))
+        .unwrap();
+
+        assert_eq!(doc.recipients, Recipients::All);
+        assert_eq!(doc.message_type, MessageType::Text);
+        assert!(doc.envelopes.is_none());
+        assert_eq!(doc.to_value()["recipients"], "all");
+    }
+
+    #[test]
+    fn test_phase2b_doc_roundtrip() {
+        let value = phase2b_image_doc();
+        let doc = MessageDoc::from_value(&value).unwrap();
+
+        assert_eq!(doc.message_type, MessageType::Image);
+        assert_eq!(doc.envelopes.as_ref().unwrap().len(), 2);
+        assert_eq!(doc.media.as_ref().unwrap().filename.as_deref(), Some("screenshot.png"));
+        assert_eq!(doc.to_value(), value);
+    }
+
+    #[test]
+    fn test_rejects_schema_drift() {
+        let mut bad_recipients = phase2b_image_doc();
+        bad_recipients["recipients"] = json!("everyone");
+        assert!(MessageDoc::from_value(&bad_recipients).is_err());
+
+        let mut bad_type = phase2b_image_doc();
+        bad_type["type"] = json!("video");
+        assert!(matches!(MessageDoc::from_value(&bad_type), Err(SchemaError::UnsupportedType(t)) if t == "video"));
+
+        let mut empty_recipients = phase2b_image_doc();
+        empty_recipients["recipients"] = json!([]);
+        assert!(matches!(
+            MessageDoc::from_value(&empty_recipients),
+            Err(SchemaError::InvalidField { field: "recipients", .. })
+        ));
+    }
+
+    #[test]
+    fn test_device_doc_key_validation() {
+        let key = general_purpose::STANDARD.encode([7u8; 32]);
+        let doc = DeviceDoc::from_value(&json!({
+            "deviceId": "550e8400-e29b-41d4-a716-446655440000",
+            "platform": "android",
+            "name": "My Pixel 8",
+            "pubSignKey": key,
+            "pubBoxKey": key
+        }))
+        .unwrap();
+
+        assert_eq!(doc.status, DeviceStatus::Active);
+        assert_eq!(doc.pub_sign_key_bytes().unwrap(), vec![7u8; 32]);
+
+        let short = DeviceDoc::from_value(&json!({
+            "deviceId": "550e8400-e29b-41d4-a716-446655440000",
+            "platform": "windows",
+            "name": "Laptop",
+            "pubSignKey": general_purpose::STANDARD.encode([7u8; 16])
+        }));
+        assert!(matches!(short, Err(SchemaError::InvalidField { field: "pubSignKey", .. })));
+    }
+}
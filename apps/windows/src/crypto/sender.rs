@@ -0,0 +1,465 @@
+//! Phase 2A E2EE Sender for Windows (Tauri)
+//!
+//! Mirrors the Android send pipeline so Windows can push clips to phones:
+//! 1. Generate DEK + nonce
+//! 2. Build canonical metadata and metaHash
+//! 3. Sign metaHash (Ed25519)
+//! 4. Encrypt payload (XChaCha20-Poly1305, AAD = metaHash) into the newest
+//!    blob version every recipient advertises
+//! 5. Wrap DEK per recipient (sealed box)
+//! 6. Return Firestore message document + `.bin` blob
+//!
+//! Uploading the blob and writing the document are left to the caller.
+
+use serde_json::json;
+use base64::{Engine, engine::general_purpose};
+use super::primitives::CryptoPrimitives;
+use super::blob::{BlobVersion, VersionedBlob};
+use super::format::CanonicalMetadata;
+use super::key_mgmt::KeyManager;
+use super::trust::KeyPinning;
+use super::media::{FileSanitizer, ImageValidator};
+use super::schema::{DeviceDoc, Envelopes, MediaInfo, MessageDoc, MessageType, Recipients, RetentionHints};
+use super::error::{CryptoError, CryptoResult};
+
+#[derive(Clone)]
+pub struct E2EESender {
+    key_manager: KeyManager,
+    key_pinning: Option<KeyPinning>,
+    retention: RetentionHints,
+}
+
+/// Recipient device as needed by the sender (from `users/{uid}/devices/{deviceId}`)
+#[derive(Debug, Clone)]
+pub struct RecipientDevice {
+    pub device_id: String,
+    pub pub_box_key: String,  // base64 X25519 public key
+    pub blob_versions: Vec<String>,  // from the device doc's blobVersions
+    /// Source document, to follow key rotation against the pins
+    pub device_doc: Option<DeviceDoc>,
+}
+
+impl RecipientDevice {
+    /// Builds a recipient from its Firestore device document
+    pub fn from_device_doc(doc: &DeviceDoc) -> CryptoResult<Self> {
+        let pub_box_key = doc.pub_box_key.clone()
+            .ok_or_else(|| CryptoError::MalformedDocument(
+                format!("Device {} has no pubBoxKey", doc.device_id),
+            ))?;
+
+        Ok(RecipientDevice {
+            device_id: doc.device_id.clone(),
+            pub_box_key,
+            blob_versions: doc.blob_versions.clone(),
+            device_doc: Some(doc.clone()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct EncryptedMessage {
+    pub message_id: String,
+    pub storage_path: String,
+    pub message_doc: MessageDoc,  // Firestore document for users/{uid}/messages/{messageId}
+    pub blob: Vec<u8>,  // Phase 2A blob for Cloud Storage
+}
+
+impl E2EESender {
+    /// Creates sender with default Windows key storage
+    pub fn new(passphrase: &str) -> CryptoResult<Self> {
+        let key_manager = KeyManager::default_windows(passphrase)?;
+
+        Ok(Self::with_key_manager(key_manager))
+    }
+
+    /// Creates sender with custom key directory
+    pub fn with_key_dir(key_dir: &str, passphrase: &str) -> CryptoResult<Self> {
+        let key_manager = KeyManager::with_passphrase(key_dir, passphrase)?;
+
+        Ok(Self::with_key_manager(key_manager))
+    }
+
+    /// Creates sender over an existing KeyManager (any backend)
+    pub fn with_key_manager(key_manager: KeyManager) -> Self {
+        E2EESender { key_manager, key_pinning: None, retention: RetentionHints::default() }
+    }
+
+    /// Refuses to wrap DEKs for recipients whose box key differs from the pin
+    pub fn with_key_pinning(mut self, key_pinning: KeyPinning) -> Self {
+        self.key_pinning = Some(key_pinning);
+        self
+    }
+
+    /// Signs `expiresAt` / `ephemeral` into the messages this sender encrypts
+    pub fn with_retention(mut self, retention: RetentionHints) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Encrypts a text clip (Phase 2A)
+    ///
+    /// # Arguments
+    /// * `uid` - Firebase user ID (for storage path)
+    /// * `sender_device_id` - This device's UUID
+    /// * `recipients` - Active recipient devices with their box public keys
+    /// * `text` - Plain UTF-8 text
+    ///
+    /// # Returns
+    /// EncryptedMessage with Firestore document and blob, or CryptoError
+    pub fn encrypt_text(
+        &self,
+        uid: &str,
+        sender_device_id: &str,
+        recipients: &[RecipientDevice],
+        text: &str,
+    ) -> CryptoResult<EncryptedMessage> {
+        self.encrypt_payload(
+            uid,
+            sender_device_id,
+            recipients,
+            text.as_bytes(),
+            MessageType::Text,
+            "application/octet-stream",
+            None,
+        )
+    }
+
+    /// Encrypts an image clip (Phase 2B)
+    ///
+    /// # Arguments
+    /// * `uid` - Firebase user ID (for storage path)
+    /// * `sender_device_id` - This device's UUID
+    /// * `recipients` - Active recipient devices with their box public keys
+    /// * `image_bytes` - Raw PNG or JPEG bytes
+    /// * `filename` - Original filename (optional, bound into metadata)
+    ///
+    /// # Returns
+    /// EncryptedMessage with Firestore document and blob, or CryptoError
+    pub fn encrypt_image(
+        &self,
+        uid: &str,
+        sender_device_id: &str,
+        recipients: &[RecipientDevice],
+        image_bytes: &[u8],
+        filename: Option<&str>,
+    ) -> CryptoResult<EncryptedMessage> {
+        if !ImageValidator::validate_image_magic(image_bytes) {
+            return Err(CryptoError::InvalidPayload(
+                "Image magic bytes validation failed; only PNG and JPEG are supported".to_string(),
+            ));
+        }
+
+        let (width, height) = ImageValidator::detect_dimensions(image_bytes).unwrap_or((0, 0));
+        let media = MediaInfo {
+            ext: ImageValidator::detect_ext(image_bytes),
+            filename: filename.map(String::from),
+            height,
+            width,
+        };
+
+        self.encrypt_payload(
+            uid,
+            sender_device_id,
+            recipients,
+            image_bytes,
+            MessageType::Image,
+            &ImageValidator::detect_mime(image_bytes),
+            Some(media),
+        )
+    }
+
+    /// Encrypts an arbitrary file (PDFs, logs, archives...)
+    ///
+    /// The filename is sanitized before it is bound into `media`; MIME, size
+    /// and filename are all covered by the signed metaHash.
+    ///
+    /// # Arguments
+    /// * `uid` - Firebase user ID (for storage path)
+    /// * `sender_device_id` - This device's UUID
+    /// * `recipients` - Active recipient devices with their box public keys
+    /// * `file_bytes` - Raw file contents
+    /// * `filename` - Original filename
+    /// * `mime` - MIME type (guessed from the extension if None)
+    ///
+    /// # Returns
+    /// EncryptedMessage with Firestore document and blob, or CryptoError
+    pub fn encrypt_file(
+        &self,
+        uid: &str,
+        sender_device_id: &str,
+        recipients: &[RecipientDevice],
+        file_bytes: &[u8],
+        filename: &str,
+        mime: Option<&str>,
+    ) -> CryptoResult<EncryptedMessage> {
+        let filename = FileSanitizer::sanitize_filename(filename);
+        let ext = FileSanitizer::ext_from_filename(&filename);
+        let mime = mime
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| FileSanitizer::mime_for_ext(&ext))
+            .to_string();
+
+        let media = MediaInfo {
+            ext,
+            filename: Some(filename),
+            height: 0,
+            width: 0,
+        };
+
+        self.encrypt_payload(
+            uid,
+            sender_device_id,
+            recipients,
+            file_bytes,
+            MessageType::File,
+            &mime,
+            Some(media),
+        )
+    }
+
+    // Private helpers
+
+    #[allow(clippy::too_many_arguments)]
+    fn encrypt_payload(
+        &self,
+        uid: &str,
+        sender_device_id: &str,
+        recipients: &[RecipientDevice],
+        plaintext: &[u8],
+        message_type: MessageType,
+        mime: &str,
+        media: Option<MediaInfo>,
+    ) -> CryptoResult<EncryptedMessage> {
+        if recipients.is_empty() {
+            return Err(CryptoError::NoRecipients);
+        }
+
+        if let Some(pinning) = &self.key_pinning {
+            for recipient in recipients {
+                pinning.check_box_key(
+                    &recipient.device_id,
+                    &recipient.pub_box_key,
+                    recipient.device_doc.as_ref(),
+                )?;
+            }
+        }
+
+        // Step 1: Generate DEK + nonce
+        let dek = CryptoPrimitives::gen_dek();
+        let nonce = CryptoPrimitives::gen_nonce();
+
+        // Step 2: Build canonical metadata (recipients sorted for stability)
+        let message_id = uuid::Uuid::new_v4().to_string();
+        let storage_path = format!("users/{}/messages/{}.bin", uid, message_id);
+        let created_at_client = chrono::Utc::now()
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+
+        let mut recipient_ids: Vec<String> = recipients.iter()
+            .map(|r| r.device_id.clone())
+            .collect();
+        recipient_ids.sort();
+        recipient_ids.dedup();
+
+        let canonical_json = CanonicalMetadata::create_canonical_json_typed(
+            &message_id,
+            sender_device_id,
+            &recipient_ids,
+            &storage_path,
+            plaintext.len(),
+            &created_at_client,
+            message_type.as_str(),
+            mime,
+            media.as_ref(),
+            &self.retention,
+        );
+
+        // Step 3: Compute metaHash
+        let meta_hash = CanonicalMetadata::compute_meta_hash(&canonical_json);
+
+        // Step 4: Sign metaHash
+        let sign_sk = self.key_manager.get_sign_private_key()?;
+        let signature = CryptoPrimitives::sign(&meta_hash, &sign_sk)?;
+
+        // Step 5: Encrypt payload into the blob version all recipients can read
+        let blob_version = BlobVersion::negotiate(
+            recipients.iter().map(|r| r.blob_versions.as_slice()),
+        );
+        let blob = VersionedBlob::seal(blob_version, plaintext, &nonce, &dek, &meta_hash)?;
+
+        // Step 6: Wrap DEK per recipient
+        let envelopes = Self::build_envelopes(recipients, &dek)?;
+
+        // Step 7: Build Firestore document (createdAt is set by the server)
+        let message_doc = MessageDoc {
+            message_id: message_id.clone(),
+            sender_device_id: sender_device_id.to_string(),
+            message_type,
+            created_at: None,
+            recipients: Recipients::Devices(recipient_ids),
+            storage_path: storage_path.clone(),
+            mime: mime.to_string(),
+            size_bytes: Some(blob.len() as u64),
+            created_at_client: Some(created_at_client),
+            nonce: Some(general_purpose::STANDARD.encode(&nonce)),
+            envelopes: Some(envelopes),
+            meta_hash: Some(general_purpose::STANDARD.encode(&meta_hash)),
+            signature: Some(general_purpose::STANDARD.encode(&signature)),
+            version: Some("2A".to_string()),
+            alg: Some(json!({
+                "aead": "xchacha20poly1305",
+                "wrap": "sealedbox-x25519",
+                "sig": "ed25519"
+            })),
+            size_bytes_plain: Some(plaintext.len() as u64),
+            media,
+            expires_at: self.retention.expires_at.clone(),
+            ephemeral: self.retention.ephemeral,
+        };
+
+        Ok(EncryptedMessage {
+            message_id,
+            storage_path,
+            message_doc,
+            blob,
+        })
+    }
+
+    fn build_envelopes(
+        recipients: &[RecipientDevice],
+        dek: &[u8],
+    ) -> CryptoResult<Envelopes> {
+        let mut envelopes = Envelopes::new();
+
+        for recipient in recipients {
+            let pub_box_key = general_purpose::STANDARD.decode(&recipient.pub_box_key)
+                .map_err(|e| CryptoError::InvalidKey(
+                    format!("Failed to decode public box key for {}: {}", recipient.device_id, e),
+                ))?;
+
+            let envelope = CryptoPrimitives::seal_box(dek, &pub_box_key)?;
+
+            envelopes.insert(
+                recipient.device_id.clone(),
+                general_purpose::STANDARD.encode(&envelope),
+            );
+        }
+
+        Ok(envelopes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::TestDevice;
+
+    #[test]
+    fn test_text_roundtrip_through_receiver() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "hello from windows")
+            .unwrap();
+        assert!(encrypted.storage_path.ends_with(".bin"));
+
+        // Document survives a Firestore JSON roundtrip unchanged
+        let reparsed = MessageDoc::from_value(&encrypted.message_doc.to_value()).unwrap();
+        assert_eq!(reparsed, encrypted.message_doc);
+
+        let result = phone.receiver()
+            .decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap();
+
+        assert_eq!(result.plaintext.as_deref(), Some("hello from windows"));
+        assert_eq!(result.message_id, encrypted.message_id);
+        assert_eq!(result.sender_device_id, "dev-pc");
+    }
+
+    #[test]
+    fn test_image_roundtrip_through_receiver() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let png_bytes = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x01];
+
+        let encrypted = pc.sender()
+            .encrypt_image("uid-1", "dev-pc", &[phone.recipient()], &png_bytes, Some("shot.png"))
+            .unwrap();
+        let doc_json = encrypted.message_doc.to_value();
+        assert_eq!(doc_json["mime"], "image/png");
+        assert_eq!(doc_json["media"]["filename"], "shot.png");
+        assert_eq!(doc_json["media"]["ext"], "png");
+
+        let result = phone.receiver()
+            .decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap();
+
+        assert_eq!(result.message_type, MessageType::Image);
+        assert_eq!(result.image_bytes, Some(png_bytes));
+    }
+
+    #[test]
+    fn test_rejects_empty_recipients() {
+        let pc = TestDevice::provision("dev-pc");
+
+        assert!(matches!(
+            pc.sender().encrypt_text("uid-1", "dev-pc", &[], "hello"),
+            Err(CryptoError::NoRecipients)
+        ));
+    }
+
+    #[test]
+    fn test_blob_version_negotiated_per_recipient_set() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let mut old_phone = TestDevice::provision("dev-old").recipient();
+        old_phone.blob_versions.clear();
+
+        let modern = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "hi")
+            .unwrap();
+        assert!(modern.blob.starts_with(b"SCAP\x03"));
+
+        // One recipient without blobVersions forces the legacy format
+        let mixed = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient(), old_phone], "hi")
+            .unwrap();
+        assert!(mixed.blob.starts_with(b"SCAP2A"));
+
+        let result = phone.receiver()
+            .decrypt_message(&mixed.message_doc, "dev-phone", &pc.device_doc(), &mixed.blob)
+            .unwrap();
+        assert_eq!(result.plaintext.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn test_file_roundtrip_binds_sanitized_metadata() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let pdf_bytes = b"%PDF-1.7 fake body".to_vec();
+
+        let encrypted = pc.sender()
+            .encrypt_file("uid-1", "dev-pc", &[phone.recipient()], &pdf_bytes, "..\\Q3: report.PDF", None)
+            .unwrap();
+        let doc_json = encrypted.message_doc.to_value();
+        assert_eq!(doc_json["type"], "file");
+        assert_eq!(doc_json["mime"], "application/pdf");
+        assert_eq!(doc_json["media"]["filename"], "Q3_ report.PDF");
+        assert_eq!(doc_json["media"]["ext"], "pdf");
+
+        let result = phone.receiver()
+            .decrypt_message(&encrypted.message_doc, "dev-phone", &pc.device_doc(), &encrypted.blob)
+            .unwrap();
+        assert_eq!(result.message_type, MessageType::File);
+        assert_eq!(result.file_bytes, Some(pdf_bytes));
+        assert_eq!(result.filename.as_deref(), Some("Q3_ report.PDF"));
+
+        // Renaming the file after signing breaks the metaHash
+        let mut renamed = encrypted.message_doc.clone();
+        renamed.media.as_mut().unwrap().filename = Some("invoice.exe".to_string());
+        assert!(matches!(
+            phone.receiver().decrypt_message(&renamed, "dev-phone", &pc.device_doc(), &encrypted.blob),
+            Err(CryptoError::Tampered(_))
+        ));
+    }
+}
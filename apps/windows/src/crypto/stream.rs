@@ -0,0 +1,401 @@
+//! Streaming blob format for large payloads (screen recordings, files)
+//!
+//! `SCAP2A` blobs hold the whole payload as a single AEAD message. `SCAP2S`
+//! blobs instead use libsodium secretstream (XChaCha20-Poly1305), so payloads
+//! of any size are encrypted and decrypted with one chunk in memory.
+//!
+//! Format: [Magic "SCAP2S" (6 bytes)] + [Chunk size, u32 LE (4 bytes)]
+//!       + [Secretstream header (24 bytes)] + [Chunk]*
+//!
+//! Every chunk is `chunk size + 17` bytes, except the last one. The last chunk
+//! may be shorter or even empty, and it carries the `Final` tag. Each chunk
+//! authenticates the metaHash as AAD. A stream that ends without the `Final`
+//! tag is reported as truncated, and data after that tag is rejected.
+
+use std::io::{self, Read, Write};
+use sodiumoxide::crypto::secretstream::xchacha20poly1305::{
+    Header, Key, Pull, Push, Stream, Tag, ABYTES, HEADERBYTES, KEYBYTES,
+};
+use super::error::{CryptoError, CryptoResult};
+
+pub struct StreamBlobFormat;
+
+impl StreamBlobFormat {
+    pub const MAGIC: &'static [u8] = b"SCAP2S";
+    pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+    /// Upper bound on a chunk size read from a blob header (bounds memory)
+    pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+    const CHUNK_SIZE_LEN: usize = 4;
+
+    /// Length of the fixed blob prefix before the first chunk
+    pub fn header_len() -> usize {
+        Self::MAGIC.len() + Self::CHUNK_SIZE_LEN + HEADERBYTES
+    }
+
+    /// Encrypts everything from `reader` into a SCAP2S blob written to `writer`
+    ///
+    /// # Arguments
+    /// * `dek` - 32-byte data encryption key
+    /// * `aad` - Additional authenticated data (metaHash)
+    ///
+    /// # Returns
+    /// Number of plaintext bytes encrypted
+    pub fn encrypt<R: Read, W: Write>(
+        reader: &mut R,
+        writer: W,
+        dek: &[u8],
+        aad: &[u8],
+    ) -> CryptoResult<u64> {
+        let mut encryptor = StreamEncryptor::new(writer, dek, aad, Self::DEFAULT_CHUNK_SIZE)?;
+        let copied = io::copy(reader, &mut encryptor).map_err(from_io_error)?;
+        encryptor.finish()?;
+
+        Ok(copied)
+    }
+
+    /// Decrypts a SCAP2S blob from `reader` into `writer`
+    ///
+    /// Plaintext is written as each chunk authenticates. If the stream turns out
+    /// to be truncated or tampered, an error is returned and the partial output
+    /// must be discarded.
+    ///
+    /// # Returns
+    /// Number of plaintext bytes written
+    pub fn decrypt<R: Read, W: Write>(
+        reader: R,
+        writer: &mut W,
+        dek: &[u8],
+        aad: &[u8],
+    ) -> CryptoResult<u64> {
+        let mut decryptor = StreamDecryptor::new(reader, dek, aad)?;
+        io::copy(&mut decryptor, writer).map_err(from_io_error)
+    }
+}
+
+/// `Write` adapter producing a SCAP2S blob
+///
+/// `finish` must be called to write the final chunk. Dropping the encryptor
+/// without it leaves a blob that decrypts as truncated.
+pub struct StreamEncryptor<W: Write> {
+    inner: W,
+    stream: Stream<Push>,
+    aad: Vec<u8>,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+    /// Writes the blob header and returns an encryptor ready for payload bytes
+    pub fn new(inner: W, dek: &[u8], aad: &[u8], chunk_size: usize) -> CryptoResult<Self> {
+        let (mut encryptor, header) = Self::without_prefix(inner, dek, aad, chunk_size)?;
+
+        encryptor.inner.write_all(StreamBlobFormat::MAGIC).map_err(from_io_error)?;
+        encryptor.inner.write_all(&(chunk_size as u32).to_le_bytes()).map_err(from_io_error)?;
+        encryptor.inner.write_all(&header).map_err(from_io_error)?;
+
+        Ok(encryptor)
+    }
+
+    /// Starts a stream without writing any prefix
+    ///
+    /// Used by container formats that carry the chunk size and secretstream
+    /// header themselves.
+    ///
+    /// # Returns
+    /// Tuple of (encryptor, 24-byte secretstream header)
+    pub(crate) fn without_prefix(
+        inner: W,
+        dek: &[u8],
+        aad: &[u8],
+        chunk_size: usize,
+    ) -> CryptoResult<(Self, Vec<u8>)> {
+        if chunk_size == 0 || chunk_size > StreamBlobFormat::MAX_CHUNK_SIZE {
+            return Err(CryptoError::InvalidKey(format!("Invalid chunk size: {}", chunk_size)));
+        }
+
+        let key = stream_key(dek)?;
+        let (stream, header) = Stream::init_push(&key)
+            .map_err(|_| CryptoError::InvalidKey("Failed to initialize stream".to_string()))?;
+
+        let encryptor = StreamEncryptor {
+            inner,
+            stream,
+            aad: aad.to_vec(),
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+        };
+
+        Ok((encryptor, header.as_ref().to_vec()))
+    }
+
+    /// Mutable access to the inner writer (e.g. to emit a container header first)
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Encrypts buffered bytes as the final chunk and returns the inner writer
+    pub fn finish(mut self) -> CryptoResult<W> {
+        let chunk = self.stream.push(&self.buffer, Some(&self.aad), Tag::Final)
+            .map_err(|_| CryptoError::InvalidPayload("Failed to encrypt final chunk".to_string()))?;
+        self.inner.write_all(&chunk).map_err(from_io_error)?;
+        self.inner.flush().map_err(from_io_error)?;
+
+        Ok(self.inner)
+    }
+
+    fn push_chunk(&mut self) -> io::Result<()> {
+        let chunk = self.stream.push(&self.buffer, Some(&self.aad), Tag::Message)
+            .map_err(|_| to_io_error(CryptoError::InvalidPayload("Failed to encrypt chunk".to_string())))?;
+        self.buffer.clear();
+        self.inner.write_all(&chunk)
+    }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let take = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..take]);
+
+        if self.buffer.len() == self.chunk_size {
+            self.push_chunk()?;
+        }
+
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Partial chunks stay buffered; only whole chunks reach the writer
+        self.inner.flush()
+    }
+}
+
+/// `Read` adapter decrypting a SCAP2S blob
+///
+/// Yields plaintext one authenticated chunk at a time. Tampering, truncation
+/// and trailing data surface as `io::Error`s wrapping a `CryptoError`.
+pub struct StreamDecryptor<R: Read> {
+    inner: R,
+    stream: Stream<Pull>,
+    aad: Vec<u8>,
+    frame: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    /// Reads and validates the blob header
+    pub fn new(mut inner: R, dek: &[u8], aad: &[u8]) -> CryptoResult<Self> {
+        let mut prefix = vec![0u8; StreamBlobFormat::header_len()];
+        inner.read_exact(&mut prefix)
+            .map_err(|_| CryptoError::MalformedBlob("Stream blob header too short".to_string()))?;
+
+        let (magic, rest) = prefix.split_at(StreamBlobFormat::MAGIC.len());
+        if magic != StreamBlobFormat::MAGIC {
+            return Err(CryptoError::MalformedBlob("Invalid stream blob magic".to_string()));
+        }
+
+        let (chunk_size_bytes, header_bytes) = rest.split_at(StreamBlobFormat::CHUNK_SIZE_LEN);
+        let chunk_size = u32::from_le_bytes(chunk_size_bytes.try_into().expect("4-byte slice")) as usize;
+
+        Self::without_prefix(inner, header_bytes, chunk_size, dek, aad)
+    }
+
+    /// Resumes a stream whose prefix was already consumed by a container format
+    ///
+    /// # Arguments
+    /// * `header_bytes` - 24-byte secretstream header
+    /// * `chunk_size` - Plaintext chunk size the stream was written with
+    pub(crate) fn without_prefix(
+        inner: R,
+        header_bytes: &[u8],
+        chunk_size: usize,
+        dek: &[u8],
+        aad: &[u8],
+    ) -> CryptoResult<Self> {
+        if chunk_size == 0 || chunk_size > StreamBlobFormat::MAX_CHUNK_SIZE {
+            return Err(CryptoError::MalformedBlob(format!("Invalid chunk size: {}", chunk_size)));
+        }
+
+        let header = Header::from_slice(header_bytes)
+            .ok_or_else(|| CryptoError::MalformedBlob("Invalid stream header".to_string()))?;
+        let key = stream_key(dek)?;
+        let stream = Stream::init_pull(&header, &key)
+            .map_err(|_| CryptoError::MalformedBlob("Failed to initialize stream".to_string()))?;
+
+        Ok(StreamDecryptor {
+            inner,
+            stream,
+            aad: aad.to_vec(),
+            frame: vec![0u8; chunk_size + ABYTES],
+            plaintext: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Reads, authenticates and decrypts the next chunk into `plaintext`
+    fn pull_chunk(&mut self) -> io::Result<()> {
+        let frame_len = read_full(&mut self.inner, &mut self.frame)?;
+        if frame_len < ABYTES {
+            return Err(to_io_error(CryptoError::Tampered("Stream truncated".to_string())));
+        }
+
+        let tag = self.stream.pull_to_vec(&self.frame[..frame_len], Some(&self.aad), &mut self.plaintext)
+            .map_err(|_| to_io_error(CryptoError::Tampered("Stream chunk authentication failed".to_string())))?;
+        self.position = 0;
+
+        if tag == Tag::Final {
+            let mut probe = [0u8; 1];
+            if read_full(&mut self.inner, &mut probe)? != 0 {
+                return Err(to_io_error(CryptoError::MalformedBlob(
+                    "Trailing data after final chunk".to_string(),
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Empty chunks are legal (e.g. an empty final chunk), so keep pulling
+        while self.position == self.plaintext.len() {
+            if self.stream.is_finalized() {
+                return Ok(0);
+            }
+            self.pull_chunk()?;
+        }
+
+        let available = &self.plaintext[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+
+        Ok(n)
+    }
+}
+
+fn stream_key(dek: &[u8]) -> CryptoResult<Key> {
+    Key::from_slice(dek)
+        .ok_or_else(|| CryptoError::InvalidKey(format!("DEK must be {} bytes", KEYBYTES)))
+}
+
+/// Reads until `buf` is full or EOF, returning the number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+pub(crate) fn to_io_error(e: CryptoError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Recovers a `CryptoError` passed through the `io` adapters
+pub(crate) fn from_io_error(e: io::Error) -> CryptoError {
+    if e.get_ref().is_some_and(|inner| inner.is::<CryptoError>()) {
+        return *e.into_inner()
+            .expect("checked above")
+            .downcast::<CryptoError>()
+            .expect("checked above");
+    }
+    CryptoError::MalformedBlob(format!("Stream I/O error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::primitives::CryptoPrimitives;
+
+    fn encrypt_with_chunk_size(plaintext: &[u8], dek: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut encryptor = StreamEncryptor::new(Vec::new(), dek, b"meta", chunk_size).unwrap();
+        encryptor.write_all(plaintext).unwrap();
+        encryptor.finish().unwrap()
+    }
+
+    #[test]
+    fn test_stream_roundtrip_across_chunk_boundaries() {
+        CryptoPrimitives::init();
+        let dek = CryptoPrimitives::gen_dek();
+
+        for len in [0usize, 1, 15, 16, 17, 48, 100] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let blob = encrypt_with_chunk_size(&plaintext, &dek, 16);
+
+            let mut decrypted = Vec::new();
+            StreamBlobFormat::decrypt(&blob[..], &mut decrypted, &dek, b"meta").unwrap();
+            assert_eq!(decrypted, plaintext, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_one_shot_helpers() {
+        CryptoPrimitives::init();
+        let dek = CryptoPrimitives::gen_dek();
+        let plaintext = vec![7u8; StreamBlobFormat::DEFAULT_CHUNK_SIZE * 2 + 5];
+
+        let mut blob = Vec::new();
+        let written = StreamBlobFormat::encrypt(&mut &plaintext[..], &mut blob, &dek, b"meta").unwrap();
+        assert_eq!(written, plaintext.len() as u64);
+        assert!(blob.starts_with(StreamBlobFormat::MAGIC));
+
+        let mut decrypted = Vec::new();
+        StreamBlobFormat::decrypt(&blob[..], &mut decrypted, &dek, b"meta").unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_truncation_and_tampering_detected() {
+        CryptoPrimitives::init();
+        let dek = CryptoPrimitives::gen_dek();
+        let blob = encrypt_with_chunk_size(&[1u8; 40], &dek, 16);
+        let frame = 16 + ABYTES;
+        let body = StreamBlobFormat::header_len();
+
+        // Dropped final chunk (cut exactly on a chunk boundary)
+        let truncated = &blob[..body + 2 * frame];
+        assert!(matches!(
+            StreamBlobFormat::decrypt(truncated, &mut Vec::new(), &dek, b"meta"),
+            Err(CryptoError::Tampered(_))
+        ));
+
+        // Flipped ciphertext byte
+        let mut flipped = blob.clone();
+        flipped[body + 3] ^= 0x01;
+        assert!(matches!(
+            StreamBlobFormat::decrypt(&flipped[..], &mut Vec::new(), &dek, b"meta"),
+            Err(CryptoError::Tampered(_))
+        ));
+
+        // Wrong AAD
+        assert!(matches!(
+            StreamBlobFormat::decrypt(&blob[..], &mut Vec::new(), &dek, b"other"),
+            Err(CryptoError::Tampered(_))
+        ));
+
+        // Data appended after the final chunk
+        let mut extended = blob.clone();
+        extended.push(0);
+        assert!(StreamBlobFormat::decrypt(&extended[..], &mut Vec::new(), &dek, b"meta").is_err());
+    }
+
+    #[test]
+    fn test_rejects_legacy_magic() {
+        CryptoPrimitives::init();
+        let dek = CryptoPrimitives::gen_dek();
+        let mut blob = encrypt_with_chunk_size(b"hello", &dek, 16);
+        blob[..6].copy_from_slice(b"SCAP2A");
+
+        assert!(matches!(
+            StreamDecryptor::new(&blob[..], &dek, b"meta"),
+            Err(CryptoError::MalformedBlob(_))
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+//! Shared fixtures for crypto tests
+//!
+//! `TestDevice` provisions a fresh keypair set in an in-memory key store and
+//! hands out the sender/receiver/document views other tests need.
+
+use base64::{Engine, engine::general_purpose};
+use super::blob::BlobVersion;
+use super::key_mgmt::KeyManager;
+use super::primitives::CryptoPrimitives;
+use super::receiver::E2EEReceiver;
+use super::schema::{DeviceDoc, DeviceStatus, Platform};
+use super::sender::{E2EESender, RecipientDevice};
+
+pub struct TestDevice {
+    pub device_id: String,
+    pub sign_pk: String,  // base64
+    pub box_pk: String,  // base64
+    key_manager: KeyManager,
+}
+
+impl TestDevice {
+    /// Generates sign + box keypairs into a fresh key store
+    pub fn provision(device_id: &str) -> Self {
+        CryptoPrimitives::init();
+        let manager = KeyManager::in_memory();
+
+        let (sign_sk, sign_pk) = CryptoPrimitives::gen_sign_keypair();
+        let (box_sk, box_pk) = CryptoPrimitives::gen_box_keypair();
+        manager.store_sign_keys(&sign_sk, &sign_pk).unwrap();
+        manager.store_box_keys(&box_sk, &box_pk).unwrap();
+        manager.store_device_id(device_id).unwrap();
+
+        TestDevice {
+            device_id: device_id.to_string(),
+            sign_pk: general_purpose::STANDARD.encode(&sign_pk),
+            box_pk: general_purpose::STANDARD.encode(&box_pk),
+            key_manager: manager,
+        }
+    }
+
+    pub fn key_manager(&self) -> KeyManager {
+        self.key_manager.clone()
+    }
+
+    pub fn sender(&self) -> E2EESender {
+        E2EESender::with_key_manager(self.key_manager())
+    }
+
+    pub fn receiver(&self) -> E2EEReceiver {
+        E2EEReceiver::with_key_manager(self.key_manager())
+    }
+
+    pub fn recipient(&self) -> RecipientDevice {
+        RecipientDevice {
+            device_id: self.device_id.clone(),
+            pub_box_key: self.box_pk.clone(),
+            blob_versions: BlobVersion::advertised(),
+            device_doc: None,
+        }
+    }
+
+    /// Firestore device document as the server would return it
+    pub fn device_doc(&self) -> DeviceDoc {
+        DeviceDoc {
+            device_id: self.device_id.clone(),
+            name: format!("Test {}", self.device_id),
+            platform: Platform::Windows,
+            created_at: None,
+            last_seen_at: None,
+            status: DeviceStatus::Active,
+            pub_sign_key: Some(self.sign_pk.clone()),
+            pub_box_key: Some(self.box_pk.clone()),
+            key_generation: None,
+            box_key_sig: None,
+            sign_key_chain: Vec::new(),
+            blob_versions: BlobVersion::advertised(),
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! Trust-on-first-use pinning of peer device keys
+//!
+//! The first time a peer device is seen, its sign and box public keys are
+//! pinned locally (`db::SqlitePinStore` in the app). From then on a device
+//! document with different keys fails with `CryptoError::KeyChanged` until
+//! the user approves the change, ideally after comparing the safety number
+//! on both screens.
+//!
+//! Key rotation reaches peers without approval: a document with a higher
+//! `keyGeneration` is accepted when its `signKeyChain` leads from the pinned
+//! sign key to the new one (`SignKeyRotation`) and its `boxKeySig` verifies
+//! with that key (`BoxKeyBinding`). Both signatures cover the generation, and
+//! the pin only moves forward, so replaying an older document fails.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use base64::{Engine, engine::general_purpose};
+use sodiumoxide::crypto::hash::sha512;
+use super::primitives::CryptoPrimitives;
+use super::schema::{DeviceDoc, SignKeyLink};
+use super::error::{CryptoError, CryptoResult};
+
+/// Keys pinned for a peer device (base64, as in the device document)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedKeys {
+    pub device_id: String,
+    pub pub_sign_key: String,
+    pub pub_box_key: String,
+    /// `keyGeneration` of the pinned keys
+    pub key_generation: u32,
+    /// Sign key replaced by the latest followed rotation (base64)
+    pub previous_sign_key: Option<String>,
+    /// When `previous_sign_key` stopped signing (Unix seconds)
+    pub sign_key_rotated_at: Option<i64>,
+    pub pinned_at: i64,
+}
+
+/// Local storage for pinned peer keys
+pub trait PinStore: Send + Sync {
+    fn get_pin(&self, device_id: &str) -> CryptoResult<Option<PinnedKeys>>;
+    /// Inserts or replaces the pin for `keys.device_id`
+    fn put_pin(&self, keys: &PinnedKeys) -> CryptoResult<()>;
+    fn remove_pin(&self, device_id: &str) -> CryptoResult<()>;
+}
+
+/// In-memory pin store (tests, ephemeral sessions)
+#[derive(Default)]
+pub struct MemoryPinStore {
+    pins: Mutex<HashMap<String, PinnedKeys>>,
+}
+
+impl MemoryPinStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> CryptoResult<std::sync::MutexGuard<'_, HashMap<String, PinnedKeys>>> {
+        self.pins.lock()
+            .map_err(|_| CryptoError::PinStoreUnavailable("Pin store lock poisoned".to_string()))
+    }
+}
+
+impl PinStore for MemoryPinStore {
+    fn get_pin(&self, device_id: &str) -> CryptoResult<Option<PinnedKeys>> {
+        Ok(self.lock()?.get(device_id).cloned())
+    }
+
+    fn put_pin(&self, keys: &PinnedKeys) -> CryptoResult<()> {
+        self.lock()?.insert(keys.device_id.clone(), keys.clone());
+        Ok(())
+    }
+
+    fn remove_pin(&self, device_id: &str) -> CryptoResult<()> {
+        self.lock()?.remove(device_id);
+        Ok(())
+    }
+}
+
+/// Outcome of checking a device document against the pins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinStatus {
+    /// Keys match the pinned keys
+    Match,
+    /// Device never seen before; `pin` records it once it proves itself
+    FirstUse,
+}
+
+/// TOFU policy over a `PinStore`, consulted by `E2EEReceiver` and `E2EESender`
+#[derive(Clone)]
+pub struct KeyPinning {
+    store: Arc<dyn PinStore>,
+}
+
+impl KeyPinning {
+    pub fn new(store: Arc<dyn PinStore>) -> Self {
+        KeyPinning { store }
+    }
+
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(MemoryPinStore::new()))
+    }
+
+    /// Compares a peer's device document with its pinned keys
+    ///
+    /// Keys from a later, properly signed rotation replace the pin and count
+    /// as a match.
+    ///
+    /// # Returns
+    /// PinStatus, or `KeyChanged` if the keys differ from the pin and do not
+    /// follow from it by rotation
+    pub fn check(&self, doc: &DeviceDoc) -> CryptoResult<PinStatus> {
+        let (sign_key, box_key) = Self::doc_keys(doc)?;
+
+        match self.store.get_pin(&doc.device_id)? {
+            None => Ok(PinStatus::FirstUse),
+            Some(pin) if pin.pub_sign_key == sign_key && pin.pub_box_key == box_key => Ok(PinStatus::Match),
+            Some(pin) => {
+                self.follow_rotation(pin, doc)?;
+                Ok(PinStatus::Match)
+            }
+        }
+    }
+
+    /// Checks a recipient's box key before wrapping a DEK for it
+    ///
+    /// Unpinned recipients pass; only the sign key proves a device, so they
+    /// are pinned when their first message verifies or on pairing. A rotated
+    /// box key is accepted if `device_doc` publishes it and passes `check`.
+    ///
+    /// # Arguments
+    /// * `device_id` - Recipient device
+    /// * `pub_box_key` - Recipient's published box key (base64)
+    /// * `device_doc` - Recipient's device document, if known
+    pub fn check_box_key(&self, device_id: &str, pub_box_key: &str, device_doc: Option<&DeviceDoc>) -> CryptoResult<()> {
+        match self.store.get_pin(device_id)? {
+            Some(pin) if pin.pub_box_key != pub_box_key => match device_doc {
+                Some(doc) if doc.device_id == device_id && doc.pub_box_key.as_deref() == Some(pub_box_key) => {
+                    self.check(doc).map(|_| ())
+                }
+                _ => Err(CryptoError::KeyChanged(device_id.to_string())),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Sign key a pinned device used before its latest rotation, for
+    /// messages it signed before rotating
+    ///
+    /// Only the key the pin itself moved away from is returned, and only for
+    /// a signed `createdAtClient` up to the rotation; the freshness window
+    /// bounds how long such messages are accepted.
+    pub fn retired_sign_key(&self, device_id: &str, created_at_client: i64) -> CryptoResult<Option<Vec<u8>>> {
+        let Some(pin) = self.store.get_pin(device_id)? else {
+            return Ok(None);
+        };
+        match (pin.previous_sign_key, pin.sign_key_rotated_at) {
+            (Some(key), Some(rotated_at)) if created_at_client <= rotated_at => general_purpose::STANDARD
+                .decode(key)
+                .map(Some)
+                .map_err(|e| CryptoError::InvalidKey(e.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Pins a device seen for the first time
+    ///
+    /// Does nothing if the device is already pinned with the same keys and
+    /// fails with `KeyChanged` if it is pinned with different ones.
+    pub fn pin(&self, doc: &DeviceDoc) -> CryptoResult<()> {
+        if self.check(doc)? == PinStatus::FirstUse {
+            self.store_pin(doc)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the pinned keys after the user explicitly approved a key change
+    pub fn approve_key_change(&self, doc: &DeviceDoc) -> CryptoResult<()> {
+        self.store_pin(doc)
+    }
+
+    /// Forgets a device (e.g. after it was removed from the account)
+    pub fn forget(&self, device_id: &str) -> CryptoResult<()> {
+        self.store.remove_pin(device_id)
+    }
+
+    pub fn pinned(&self, device_id: &str) -> CryptoResult<Option<PinnedKeys>> {
+        self.store.get_pin(device_id)
+    }
+
+    /// Re-pins the keys of a later generation if they follow from the pin
+    ///
+    /// Walks the `signKeyChain` links after the pinned generation, each
+    /// signed by the key before it, then checks `boxKeySig` with the key
+    /// reached. Anything else (older or equal generation, broken chain,
+    /// unsigned box key) is a key change.
+    fn follow_rotation(&self, pin: PinnedKeys, doc: &DeviceDoc) -> CryptoResult<()> {
+        let changed = || CryptoError::KeyChanged(doc.device_id.clone());
+        let generation = doc.key_generation();
+        if generation <= pin.key_generation {
+            return Err(changed());
+        }
+
+        let mut links: Vec<&SignKeyLink> = doc.sign_key_chain.iter()
+            .filter(|link| link.generation > pin.key_generation && link.generation <= generation)
+            .collect();
+        links.sort_by_key(|link| link.generation);
+
+        let mut sign_key = Self::decode_key(&pin.pub_sign_key)?;
+        let mut retired = None;
+        for link in links {
+            if !SignKeyRotation::verify(&doc.device_id, link, &sign_key) {
+                return Err(changed());
+            }
+            retired = Some((general_purpose::STANDARD.encode(&sign_key), link.rotated_at));
+            sign_key = Self::decode_key(&link.pub_sign_key)?;
+        }
+
+        let (doc_sign_key, box_key) = Self::doc_keys(doc)?;
+        let box_key_bytes = doc.pub_box_key_bytes()?;
+        let box_key_signed = doc.box_key_sig.as_deref().is_some_and(|signature| {
+            BoxKeyBinding::verify(&doc.device_id, generation, &box_key_bytes, signature, &sign_key)
+        });
+        if general_purpose::STANDARD.encode(&sign_key) != doc_sign_key || !box_key_signed {
+            return Err(changed());
+        }
+
+        let (previous_sign_key, sign_key_rotated_at) = match retired {
+            Some((key, rotated_at)) => (Some(key), Some(rotated_at)),
+            None => (pin.previous_sign_key, pin.sign_key_rotated_at),
+        };
+        self.store.put_pin(&PinnedKeys {
+            device_id: pin.device_id,
+            pub_sign_key: doc_sign_key,
+            pub_box_key: box_key,
+            key_generation: generation,
+            previous_sign_key,
+            sign_key_rotated_at,
+            pinned_at: chrono::Utc::now().timestamp(),
+        })
+    }
+
+    fn store_pin(&self, doc: &DeviceDoc) -> CryptoResult<()> {
+        let (sign_key, box_key) = Self::doc_keys(doc)?;
+        self.store.put_pin(&PinnedKeys {
+            device_id: doc.device_id.clone(),
+            pub_sign_key: sign_key,
+            pub_box_key: box_key,
+            key_generation: doc.key_generation(),
+            previous_sign_key: None,
+            sign_key_rotated_at: None,
+            pinned_at: chrono::Utc::now().timestamp(),
+        })
+    }
+
+    fn decode_key(key_b64: &str) -> CryptoResult<Vec<u8>> {
+        general_purpose::STANDARD.decode(key_b64)
+            .map_err(|e| CryptoError::InvalidKey(e.to_string()))
+    }
+
+    fn doc_keys(doc: &DeviceDoc) -> CryptoResult<(String, String)> {
+        // Normalize through the decoded bytes so encoding quirks don't look like key changes
+        let sign_key = general_purpose::STANDARD.encode(doc.pub_sign_key_bytes()?);
+        let box_key = general_purpose::STANDARD.encode(doc.pub_box_key_bytes()?);
+        Ok((sign_key, box_key))
+    }
+}
+
+/// Signature binding a device's box key and key generation to its sign key
+///
+/// Published as `boxKeySig` in the device document so peers can follow key
+/// rotation without re-approval.
+pub struct BoxKeyBinding;
+
+impl BoxKeyBinding {
+    const DOMAIN: &'static [u8] = b"SCAP-BOXKEY-2";
+
+    /// Signs `box_key` of `generation` for `device_id`
+    ///
+    /// # Returns
+    /// base64 Ed25519 signature, as stored in `boxKeySig`
+    pub fn sign(device_id: &str, generation: u32, box_key: &[u8], sign_private_key: &[u8]) -> CryptoResult<String> {
+        let signature = CryptoPrimitives::sign(&Self::signed_bytes(device_id, generation, box_key), sign_private_key)?;
+        Ok(general_purpose::STANDARD.encode(signature))
+    }
+
+    /// Checks a base64 `boxKeySig` against the device's sign key
+    pub fn verify(device_id: &str, generation: u32, box_key: &[u8], signature: &str, pub_sign_key: &[u8]) -> bool {
+        match general_purpose::STANDARD.decode(signature) {
+            Ok(signature) => CryptoPrimitives::verify(
+                &Self::signed_bytes(device_id, generation, box_key),
+                &signature,
+                pub_sign_key,
+            ),
+            Err(_) => false,
+        }
+    }
+
+    /// Domain tag, length-prefixed device ID, generation (u32 BE), raw box key
+    fn signed_bytes(device_id: &str, generation: u32, box_key: &[u8]) -> Vec<u8> {
+        let mut bytes = Self::DOMAIN.to_vec();
+        bytes.extend_from_slice(&(device_id.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(device_id.as_bytes());
+        bytes.extend_from_slice(&generation.to_be_bytes());
+        bytes.extend_from_slice(box_key);
+        bytes
+    }
+}
+
+/// Cross-signature handing a device's identity over to a new sign key
+///
+/// Made with the outgoing sign key and published in `signKeyChain`, so peers
+/// that pinned any earlier key can follow the chain to the current one.
+pub struct SignKeyRotation;
+
+impl SignKeyRotation {
+    const DOMAIN: &'static [u8] = b"SCAP-SIGNKEY-1";
+
+    /// Signs the hand-over to `new_sign_key` at `generation`
+    pub fn sign(
+        device_id: &str,
+        generation: u32,
+        new_sign_key: &[u8],
+        rotated_at: i64,
+        old_sign_private_key: &[u8],
+    ) -> CryptoResult<SignKeyLink> {
+        let pub_sign_key = general_purpose::STANDARD.encode(new_sign_key);
+        let bytes = Self::signed_bytes(device_id, generation, new_sign_key, rotated_at);
+        let signature = CryptoPrimitives::sign(&bytes, old_sign_private_key)?;
+        Ok(SignKeyLink {
+            generation,
+            pub_sign_key,
+            rotated_at,
+            sig: general_purpose::STANDARD.encode(signature),
+        })
+    }
+
+    /// Checks `link` against the sign key it replaces
+    pub fn verify(device_id: &str, link: &SignKeyLink, old_pub_sign_key: &[u8]) -> bool {
+        let (Ok(new_sign_key), Ok(signature)) = (
+            general_purpose::STANDARD.decode(&link.pub_sign_key),
+            general_purpose::STANDARD.decode(&link.sig),
+        ) else {
+            return false;
+        };
+        let bytes = Self::signed_bytes(device_id, link.generation, &new_sign_key, link.rotated_at);
+        CryptoPrimitives::verify(&bytes, &signature, old_pub_sign_key)
+    }
+
+    /// Domain tag, length-prefixed device ID, generation (u32 BE),
+    /// rotation time (i64 BE), raw new sign key
+    fn signed_bytes(device_id: &str, generation: u32, new_sign_key: &[u8], rotated_at: i64) -> Vec<u8> {
+        let mut bytes = Self::DOMAIN.to_vec();
+        bytes.extend_from_slice(&(device_id.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(device_id.as_bytes());
+        bytes.extend_from_slice(&generation.to_be_bytes());
+        bytes.extend_from_slice(&rotated_at.to_be_bytes());
+        bytes.extend_from_slice(new_sign_key);
+        bytes
+    }
+}
+
+/// Safety number for out-of-band comparison of two devices' keys
+pub struct SafetyNumber;
+
+impl SafetyNumber {
+    const VERSION: &'static [u8] = b"SCAP-SAFETY-1";
+    const GROUPS: usize = 12;
+
+    /// Computes the 60-digit safety number for a pair of devices
+    ///
+    /// Symmetric: both devices get the same digits regardless of which side
+    /// is "local". Output is twelve space-separated groups of five digits.
+    pub fn compute(a: &DeviceDoc, b: &DeviceDoc) -> CryptoResult<String> {
+        let mut parties = [Self::party_bytes(a)?, Self::party_bytes(b)?];
+        parties.sort();
+
+        let mut input = Self::VERSION.to_vec();
+        for party in &parties {
+            input.extend_from_slice(party);
+        }
+        let digest = sha512::hash(&input);
+
+        let groups: Vec<String> = digest.as_ref()
+            .chunks(5)
+            .take(Self::GROUPS)
+            .map(|chunk| {
+                let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                format!("{:05}", value % 100_000)
+            })
+            .collect();
+
+        Ok(groups.join(" "))
+    }
+
+    /// Length-prefixed device ID followed by the raw sign and box keys
+    fn party_bytes(doc: &DeviceDoc) -> CryptoResult<Vec<u8>> {
+        let id = doc.device_id.as_bytes();
+        let mut bytes = (id.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&doc.pub_sign_key_bytes()?);
+        bytes.extend_from_slice(&doc.pub_box_key_bytes()?);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::TestDevice;
+
+    #[test]
+    fn test_first_use_then_match_then_change() {
+        let pinning = KeyPinning::in_memory();
+        let phone = TestDevice::provision("dev-phone");
+        let doc = phone.device_doc();
+
+        assert_eq!(pinning.check(&doc).unwrap(), PinStatus::FirstUse);
+        pinning.pin(&doc).unwrap();
+        assert_eq!(pinning.check(&doc).unwrap(), PinStatus::Match);
+
+        // Same device ID, different keys (server substitution or reinstall)
+        let mut swapped = TestDevice::provision("dev-phone").device_doc();
+        swapped.name = doc.name.clone();
+        assert!(matches!(pinning.check(&swapped), Err(CryptoError::KeyChanged(_))));
+        assert!(matches!(pinning.pin(&swapped), Err(CryptoError::KeyChanged(_))));
+        assert!(matches!(
+            pinning.check_box_key("dev-phone", swapped.pub_box_key.as_deref().unwrap(), None),
+            Err(CryptoError::KeyChanged(_))
+        ));
+
+        pinning.approve_key_change(&swapped).unwrap();
+        assert_eq!(pinning.check(&swapped).unwrap(), PinStatus::Match);
+    }
+
+    #[test]
+    fn test_rotation_needs_signatures_and_a_higher_generation() {
+        let pinning = KeyPinning::in_memory();
+        let phone = TestDevice::provision("dev-phone");
+        let original = phone.device_doc();
+        pinning.pin(&original).unwrap();
+        let phone_sk = phone.key_manager().get_sign_private_key().unwrap();
+
+        // New box key under the pinned sign key
+        let impostor = TestDevice::provision("dev-phone");
+        let new_box_key = impostor.device_doc().pub_box_key_bytes().unwrap();
+        let mut rotated = original.clone();
+        rotated.pub_box_key = impostor.device_doc().pub_box_key;
+        rotated.key_generation = Some(2);
+
+        // Signed by someone else, or for a generation that is not newer
+        let impostor_sk = impostor.key_manager().get_sign_private_key().unwrap();
+        rotated.box_key_sig = Some(BoxKeyBinding::sign("dev-phone", 2, &new_box_key, &impostor_sk).unwrap());
+        assert!(matches!(pinning.check(&rotated), Err(CryptoError::KeyChanged(_))));
+        rotated.key_generation = Some(1);
+        rotated.box_key_sig = Some(BoxKeyBinding::sign("dev-phone", 1, &new_box_key, &phone_sk).unwrap());
+        assert!(matches!(pinning.check(&rotated), Err(CryptoError::KeyChanged(_))));
+
+        rotated.key_generation = Some(2);
+        rotated.box_key_sig = Some(BoxKeyBinding::sign("dev-phone", 2, &new_box_key, &phone_sk).unwrap());
+        pinning.check_box_key("dev-phone", rotated.pub_box_key.as_deref().unwrap(), Some(&rotated)).unwrap();
+        assert_eq!(pinning.check(&rotated).unwrap(), PinStatus::Match);
+        assert_eq!(pinning.pinned("dev-phone").unwrap().unwrap().key_generation, 2);
+
+        // Replaying the original document cannot roll the pin back
+        assert!(matches!(pinning.check(&original), Err(CryptoError::KeyChanged(_))));
+        assert!(matches!(
+            pinning.check_box_key("dev-phone", original.pub_box_key.as_deref().unwrap(), Some(&original)),
+            Err(CryptoError::KeyChanged(_))
+        ));
+
+        // A new sign key must be handed over by the pinned one
+        let mut forged = impostor.device_doc();
+        forged.key_generation = Some(3);
+        forged.sign_key_chain = vec![SignKeyRotation::sign(
+            "dev-phone", 3, &forged.pub_sign_key_bytes().unwrap(), 0, &impostor_sk,
+        ).unwrap()];
+        forged.box_key_sig = Some(BoxKeyBinding::sign(
+            "dev-phone", 3, &forged.pub_box_key_bytes().unwrap(), &impostor_sk,
+        ).unwrap());
+        assert!(matches!(pinning.check(&forged), Err(CryptoError::KeyChanged(_))));
+
+        forged.sign_key_chain = vec![SignKeyRotation::sign(
+            "dev-phone", 3, &forged.pub_sign_key_bytes().unwrap(), 1_000, &phone_sk,
+        ).unwrap()];
+        assert_eq!(pinning.check(&forged).unwrap(), PinStatus::Match);
+        let pin = pinning.pinned("dev-phone").unwrap().unwrap();
+        assert_eq!(pin.previous_sign_key, original.pub_sign_key);
+        assert!(pinning.retired_sign_key("dev-phone", 1_000).unwrap().is_some());
+        assert!(pinning.retired_sign_key("dev-phone", 1_001).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_safety_number_is_symmetric_and_key_bound() {
+        let pc = TestDevice::provision("dev-pc").device_doc();
+        let phone = TestDevice::provision("dev-phone").device_doc();
+
+        let from_pc = SafetyNumber::compute(&pc, &phone).unwrap();
+        let from_phone = SafetyNumber::compute(&phone, &pc).unwrap();
+        assert_eq!(from_pc, from_phone);
+        assert_eq!(from_pc.split(' ').count(), 12);
+        assert!(from_pc.split(' ').all(|g| g.len() == 5 && g.chars().all(|c| c.is_ascii_digit())));
+
+        let impostor = TestDevice::provision("dev-phone").device_doc();
+        assert_ne!(SafetyNumber::compute(&pc, &impostor).unwrap(), from_pc);
+    }
+}
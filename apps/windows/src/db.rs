@@ -2,83 +2,1294 @@
 // src/db.rs
 
 use rusqlite::{Connection, Result as SqliteResult};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use crate::crypto::error::{CryptoError, CryptoResult};
+use crate::crypto::history::{HistoryCipher, SealedValue};
+use crate::crypto::key_mgmt::KeyManager;
+use crate::crypto::replay::SeenMessageLedger;
+use crate::crypto::revocation::{RevocationStatement, RevocationStore};
+use crate::crypto::trust::{PinStore, PinnedKeys};
+use crate::crypto::schema::{MessageDoc, MessageType};
+use crate::outbox::{OutboxError, OutboxItem, OutboxResult, OutboxStatus, OutboxStore};
+use crate::sensitive::{Retention, SensitiveKind};
 
+const SEEN_MESSAGES_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS seen_messages (
+        message_id TEXT PRIMARY KEY,
+        sender_device_id TEXT NOT NULL,
+        seen_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_seen_messages_seen_at ON seen_messages(seen_at);
+";
+
+const REVOCATIONS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS revocations (
+        revoked_device_id TEXT PRIMARY KEY,
+        issuer_device_id TEXT NOT NULL,
+        issued_at INTEGER NOT NULL,
+        reason TEXT,
+        signature TEXT NOT NULL
+    );
+";
+
+const SYNC_STATE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sync_state (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+const OUTBOX_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS outbox (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        message_id TEXT NOT NULL UNIQUE,
+        uid TEXT NOT NULL,
+        message_doc TEXT NOT NULL,
+        blob BLOB NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        queued_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_outbox_status ON outbox(status, seq);
+";
+
+/// Creates `devices` and adds the key-pinning columns to older databases
+fn ensure_devices_table(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS devices (
+            id TEXT PRIMARY KEY,
+            device_id TEXT,
+            platform TEXT,
+            name TEXT,
+            created_at INTEGER,
+            last_seen_at INTEGER
+        );
+        ",
+    )?;
+
+    let existing: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('devices')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    for (column, decl) in [
+        ("pub_sign_key", "TEXT"),
+        ("pub_box_key", "TEXT"),
+        ("pinned_at", "INTEGER"),
+        ("key_generation", "INTEGER"),
+        ("previous_sign_key", "TEXT"),
+        ("sign_key_rotated_at", "INTEGER"),
+    ] {
+        if !existing.iter().any(|c| c == column) {
+            conn.execute_batch(&format!("ALTER TABLE devices ADD COLUMN {} {};", column, decl))?;
+        }
+    }
+    Ok(())
+}
+
+/// One schema step; runs inside a transaction
+type Migration = fn(&Connection) -> SqliteResult<()>;
+
+/// Schema history, applied in order; `PRAGMA user_version` = steps applied.
+/// Append new steps, never edit released ones.
+const MIGRATIONS: &[Migration] = &[
+    migrate_v1_baseline,
+    migrate_v2_typed_messages,
+    migrate_v3_sealed_content,
+    migrate_v4_retention,
+    migrate_v5_pin_generations,
+];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// v1: every table as created before versioning (no-op on those databases)
+fn migrate_v1_baseline(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS messages (
+            id TEXT PRIMARY KEY,
+            message_id TEXT NOT NULL,
+            sender_device_id TEXT,
+            type TEXT DEFAULT 'text',
+            content TEXT,
+            created_at INTEGER,
+            downloaded_at INTEGER,
+            is_favorite BOOLEAN DEFAULT 0,
+            is_last BOOLEAN DEFAULT 0
+        );
+        ",
+    )?;
+    ensure_devices_table(conn)?;
+    conn.execute_batch(SEEN_MESSAGES_SCHEMA)?;
+    conn.execute_batch(REVOCATIONS_SCHEMA)?;
+    conn.execute_batch(SYNC_STATE_SCHEMA)?;
+    conn.execute_batch(OUTBOX_SCHEMA)
+}
+
+/// v2: typed message metadata, one row per messageId, at most one "last" row
+fn migrate_v2_typed_messages(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE messages ADD COLUMN mime TEXT;
+        ALTER TABLE messages ADD COLUMN size_bytes INTEGER;
+        ALTER TABLE messages ADD COLUMN filename TEXT;
+
+        DELETE FROM messages
+        WHERE rowid NOT IN (SELECT MAX(rowid) FROM messages GROUP BY message_id);
+        CREATE UNIQUE INDEX idx_messages_message_id ON messages(message_id);
+
+        UPDATE messages SET is_last = 0;
+        UPDATE messages SET is_last = 1
+        WHERE rowid = (SELECT rowid FROM messages ORDER BY downloaded_at DESC, rowid DESC LIMIT 1);
+        CREATE UNIQUE INDEX idx_messages_last ON messages(is_last) WHERE is_last = 1;
+
+        CREATE INDEX idx_messages_downloaded_at ON messages(downloaded_at);
+        CREATE INDEX idx_messages_sender ON messages(sender_device_id, downloaded_at);
+        ",
+    )
+}
+
+/// v3: sealed history content
+///
+/// Sealing needs the history key, so existing plaintext rows are sealed by
+/// `Database::new` right after migrating.
+fn migrate_v3_sealed_content(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE messages ADD COLUMN sealed_content BLOB;
+        ALTER TABLE messages ADD COLUMN key_generation INTEGER;
+        ",
+    )
+}
+
+/// v4: sensitive-content retention (see `sensitive`)
+fn migrate_v4_retention(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE messages ADD COLUMN expires_at INTEGER;
+        ALTER TABLE messages ADD COLUMN ephemeral BOOLEAN DEFAULT 0;
+        ALTER TABLE messages ADD COLUMN sensitive TEXT;
+        CREATE INDEX idx_messages_expires_at ON messages(expires_at) WHERE expires_at IS NOT NULL;
+        ",
+    )
+}
+
+/// v5: key generation and retired sign key of pinned devices (see `trust`)
+fn migrate_v5_pin_generations(conn: &Connection) -> SqliteResult<()> {
+    ensure_devices_table(conn)
+}
+
+/// Applies pending migrations, one transaction per step
+fn migrate(conn: &mut Connection) -> SqliteResult<()> {
+    let current: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if current > SCHEMA_VERSION {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+            Some(format!(
+                "Database schema v{} is newer than this build (v{})",
+                current, SCHEMA_VERSION
+            )),
+        ));
+    }
+
+    for (index, step) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let tx = conn.transaction()?;
+        step(&tx)?;
+        tx.pragma_update(None, "user_version", index as u32 + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum DbError {
+    #[error("Local database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("History encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+}
+
+impl DbError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DbError::Sqlite(_) => "storage",
+            DbError::Crypto(e) => e.code(),
+        }
+    }
+}
+
+pub type DbResult<T> = Result<T, DbError>;
+
+/// A stored history entry
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageRecord {
+    pub id: String,
+    pub message_id: String,
+    pub sender_device_id: Option<String>,
+    pub message_type: MessageType,
+    pub mime: Option<String>,
+    pub size_bytes: Option<u64>,
+    pub filename: Option<String>,
+    /// Text, or base64 payload for image/file (decrypted on read)
+    pub content: Option<String>,
+    /// Sender's createdAtClient (Unix seconds)
+    pub created_at: Option<i64>,
+    /// When this device stored it (Unix seconds)
+    pub downloaded_at: i64,
+    pub is_favorite: bool,
+    pub is_last: bool,
+    /// Deleted after this (Unix seconds), even if a favorite
+    pub expires_at: Option<i64>,
+    /// Not listed in history; deleted once it stops being "last"
+    pub ephemeral: bool,
+    /// Secrets detected when it was received
+    pub sensitive: Vec<SensitiveKind>,
+}
+
+impl MessageRecord {
+    const COLUMNS: &'static str = "id, message_id, sender_device_id, type, mime, size_bytes, filename, \
+                                   sealed_content, key_generation, created_at, downloaded_at, \
+                                   is_favorite, is_last, expires_at, ephemeral, sensitive";
+
+    /// Record without content, plus the sealed content to open
+    fn from_row(row: &rusqlite::Row) -> SqliteResult<(Self, Option<SealedValue>)> {
+        let message_type = match row.get::<_, Option<String>>(3)?.as_deref() {
+            Some("image") => MessageType::Image,
+            Some("file") => MessageType::File,
+            _ => MessageType::Text,
+        };
+
+        let sealed = match (row.get::<_, Option<Vec<u8>>>(7)?, row.get::<_, Option<u32>>(8)?) {
+            (Some(bytes), Some(generation)) => Some(SealedValue { generation, bytes }),
+            _ => None,
+        };
+
+        let record = MessageRecord {
+            id: row.get(0)?,
+            message_id: row.get(1)?,
+            sender_device_id: row.get(2)?,
+            message_type,
+            mime: row.get(4)?,
+            size_bytes: row.get::<_, Option<i64>>(5)?.map(|n| n as u64),
+            filename: row.get(6)?,
+            content: None,
+            created_at: row.get(9)?,
+            downloaded_at: row.get::<_, Option<i64>>(10)?.unwrap_or(0),
+            is_favorite: row.get::<_, Option<bool>>(11)?.unwrap_or(false),
+            is_last: row.get::<_, Option<bool>>(12)?.unwrap_or(false),
+            expires_at: row.get(13)?,
+            ephemeral: row.get::<_, Option<bool>>(14)?.unwrap_or(false),
+            sensitive: row.get::<_, Option<String>>(15)?
+                .map(|kinds| kinds.split(',').filter_map(SensitiveKind::parse).collect())
+                .unwrap_or_default(),
+        };
+        Ok((record, sealed))
+    }
+}
+
+/// A received message to store
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewMessage {
+    pub message_id: String,
+    pub sender_device_id: String,
+    pub message_type: MessageType,
+    pub mime: Option<String>,
+    pub size_bytes: Option<u64>,
+    pub filename: Option<String>,
+    pub content: String,
+    pub created_at: Option<i64>,
+    pub retention: Retention,
+    pub sensitive: Vec<SensitiveKind>,
+}
+
+/// History filter and page (newest first)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryQuery {
+    pub sender_device_id: Option<String>,
+    pub message_type: Option<MessageType>,
+    pub favorites_only: bool,
+    /// Page size (default 50, at most 500)
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl HistoryQuery {
+    pub const DEFAULT_LIMIT: usize = 50;
+    pub const MAX_LIMIT: usize = 500;
+}
+
+/// Message history; content is sealed with the local history key
 pub struct Database {
     conn: Connection,
+    cipher: HistoryCipher,
 }
 
+/// The one open `Database` that sync and history share
+///
+/// Sealing reads the current history key generation before writing, so
+/// inserts and `rekey_history` must go through the same lock; a second
+/// connection could commit rows sealed under a generation just retired.
+pub type SharedDatabase = Arc<Mutex<Database>>;
+
 impl Database {
-    pub fn new(path: &PathBuf) -> SqliteResult<Self> {
-        let conn = Connection::open(path)?;
-        conn.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS messages (
-                id TEXT PRIMARY KEY,
-                message_id TEXT NOT NULL,
-                sender_device_id TEXT,
-                type TEXT DEFAULT 'text',
-                content TEXT,
-                created_at INTEGER,
-                downloaded_at INTEGER,
-                is_favorite BOOLEAN DEFAULT 0,
-                is_last BOOLEAN DEFAULT 0
-            );
-
-            CREATE TABLE IF NOT EXISTS devices (
-                id TEXT PRIMARY KEY,
-                device_id TEXT,
-                platform TEXT,
-                name TEXT,
-                created_at INTEGER,
-                last_seen_at INTEGER
-            );
-            ",
-        )?;
-        Ok(Database { conn })
+    /// `%APPDATA%/ScingOS/spectrocap.db` (next to the key directory)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("ScingOS").join("spectrocap.db"))
+    }
+
+    /// Opens the database and brings its schema up to `SCHEMA_VERSION`
+    ///
+    /// Plaintext content left by earlier versions is sealed and the file is
+    /// vacuumed so the old pages do not linger.
+    ///
+    /// # Arguments
+    /// * `key_manager` - Holds the history data key (created on first use)
+    pub fn new(path: &PathBuf, key_manager: &KeyManager) -> DbResult<Self> {
+        let mut conn = Connection::open(path)?;
+        // Zero freed pages so replaced content is not recoverable from the file
+        conn.pragma_update(None, "secure_delete", true)?;
+        migrate(&mut conn)?;
+
+        let db = Database { conn, cipher: HistoryCipher::new(key_manager.clone()) };
+        db.purge_expired(chrono::Utc::now().timestamp())?;
+        if db.seal_plaintext_rows()? > 0 {
+            db.conn.execute_batch("VACUUM")?;
+        }
+        Ok(db)
+    }
+
+    pub fn into_shared(self) -> SharedDatabase {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn schema_version(&self) -> DbResult<u32> {
+        Ok(self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
     }
 
+    /// Stores a text message (see `insert_message`)
     pub fn add_message(
         &self,
         message_id: &str,
         content: &str,
         sender_device_id: &str,
-    ) -> SqliteResult<()> {
-        self.conn.execute(
-            "INSERT INTO messages (id, message_id, content, sender_device_id, downloaded_at, is_last)
-             VALUES (?, ?, ?, ?, ?, ?)",
+    ) -> DbResult<()> {
+        self.insert_message(&NewMessage {
+            message_id: message_id.to_string(),
+            sender_device_id: sender_device_id.to_string(),
+            message_type: MessageType::Text,
+            mime: Some("text/plain".to_string()),
+            size_bytes: Some(content.len() as u64),
+            filename: None,
+            content: content.to_string(),
+            created_at: None,
+            retention: Retention::default(),
+            sensitive: Vec::new(),
+        })?;
+        Ok(())
+    }
+
+    /// Stores a received message and makes it the "last" message
+    ///
+    /// A messageId that is already stored is left untouched (and does not
+    /// move the "last" pointer). An ephemeral "last" message it replaces is
+    /// deleted.
+    pub fn insert_message(&self, message: &NewMessage) -> DbResult<MessageRecord> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let sealed = self.cipher.seal(&id, message.content.as_bytes())?;
+        let sensitive = (!message.sensitive.is_empty()).then(|| {
+            message.sensitive.iter().map(|kind| kind.as_str()).collect::<Vec<_>>().join(",")
+        });
+
+        let tx = self.conn.unchecked_transaction()?;
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO messages
+                 (id, message_id, sender_device_id, type, mime, size_bytes, filename,
+                  sealed_content, key_generation, created_at, downloaded_at, is_favorite, is_last,
+                  expires_at, ephemeral, sensitive)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 0, 0, ?, ?, ?)",
             rusqlite::params![
-                uuid::Uuid::new_v4().to_string(),
-                message_id,
-                content,
-                sender_device_id,
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-                true
+                id,
+                message.message_id,
+                message.sender_device_id,
+                message.message_type.as_str(),
+                message.mime,
+                message.size_bytes.map(|n| n as i64),
+                message.filename,
+                sealed.bytes,
+                sealed.generation,
+                message.created_at,
+                chrono::Utc::now().timestamp(),
+                message.retention.expires_at,
+                message.retention.ephemeral,
+                sensitive,
             ],
         )?;
+        if inserted == 1 {
+            Self::move_last_pointer(&tx, &message.message_id)?;
+        }
+        tx.commit()?;
+
+        self.get_message(&message.message_id)?
+            .ok_or(DbError::Sqlite(rusqlite::Error::QueryReturnedNoRows))
+    }
+
+    pub fn get_message(&self, message_id: &str) -> DbResult<Option<MessageRecord>> {
+        let sql = format!("SELECT {} FROM messages WHERE message_id = ?", MessageRecord::COLUMNS);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query_map([message_id], MessageRecord::from_row)?;
+        rows.next().transpose()?.map(|row| self.open_record(row)).transpose()
+    }
+
+    /// One page of history, newest first (ephemeral messages are not listed)
+    pub fn list_messages(&self, query: &HistoryQuery) -> DbResult<Vec<MessageRecord>> {
+        let mut filters = vec!["ephemeral = 0"];
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+        if let Some(sender) = &query.sender_device_id {
+            filters.push("sender_device_id = ?");
+            params.push(Box::new(sender.clone()));
+        }
+        if let Some(message_type) = query.message_type {
+            filters.push("type = ?");
+            params.push(Box::new(message_type.as_str()));
+        }
+        if query.favorites_only {
+            filters.push("is_favorite = 1");
+        }
+
+        let limit = query.limit.unwrap_or(HistoryQuery::DEFAULT_LIMIT).min(HistoryQuery::MAX_LIMIT);
+        params.push(Box::new(limit as i64));
+        params.push(Box::new(query.offset as i64));
+
+        let sql = format!(
+            "SELECT {} FROM messages WHERE {} ORDER BY downloaded_at DESC, rowid DESC LIMIT ? OFFSET ?",
+            MessageRecord::COLUMNS,
+            filters.join(" AND "),
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), MessageRecord::from_row)?;
+        rows.map(|row| self.open_record(row?)).collect()
+    }
+
+    pub fn has_message(&self, message_id: &str) -> DbResult<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE message_id = ?)",
+            [message_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// # Returns
+    /// false if no such message is stored
+    pub fn set_favorite(&self, message_id: &str, favorite: bool) -> DbResult<bool> {
+        let updated = self.conn.execute(
+            "UPDATE messages SET is_favorite = ? WHERE message_id = ?",
+            rusqlite::params![favorite, message_id],
+        )?;
+        Ok(updated == 1)
+    }
+
+    /// Points "last" (what Paste Last pastes) at a stored message
+    ///
+    /// # Returns
+    /// false if no such message is stored (the pointer is unchanged)
+    pub fn set_last(&self, message_id: &str) -> DbResult<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let moved = Self::move_last_pointer(&tx, message_id)?;
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    /// Deletes messages whose retention has run out
+    ///
+    /// # Arguments
+    /// * `now` - Unix seconds
+    ///
+    /// # Returns
+    /// Number of messages deleted
+    pub fn purge_expired(&self, now: i64) -> DbResult<usize> {
+        Ok(self.conn.execute("DELETE FROM messages WHERE expires_at <= ?", [now])?)
+    }
+
+    pub fn get_last_message(&self) -> DbResult<Option<MessageRecord>> {
+        let sql = format!("SELECT {} FROM messages WHERE is_last = 1", MessageRecord::COLUMNS);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query_map([], MessageRecord::from_row)?;
+        rows.next().transpose()?.map(|row| self.open_record(row)).transpose()
+    }
+
+    /// Re-seals all history under a new data key, then retires old keys
+    ///
+    /// Runs in one transaction: on any failure the rows keep their previous
+    /// sealing and the previous keys are kept.
+    ///
+    /// # Returns
+    /// Number of rows re-sealed
+    pub fn rekey_history(&self) -> DbResult<usize> {
+        let new_key = self.cipher.rotate()?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        let rows: Vec<(String, SealedValue)> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, sealed_content, key_generation FROM messages
+                 WHERE sealed_content IS NOT NULL AND key_generation != ?",
+            )?;
+            let rows = stmt.query_map([new_key.generation], |row| {
+                Ok((row.get(0)?, SealedValue { bytes: row.get(1)?, generation: row.get(2)? }))
+            })?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+
+        for (id, sealed) in &rows {
+            let plaintext = self.cipher.open(id, sealed)?;
+            let resealed = HistoryCipher::seal_with(&new_key, id, &plaintext)?;
+            tx.execute(
+                "UPDATE messages SET sealed_content = ?, key_generation = ? WHERE id = ?",
+                rusqlite::params![resealed.bytes, resealed.generation, id],
+            )?;
+        }
+        tx.commit()?;
+
+        self.cipher.retire_keys(&self.key_generations_in_use()?)?;
+        Ok(rows.len())
+    }
+
+    /// Opaque inbox sync cursor for an account (see `sync::InboxSync`)
+    pub fn get_sync_cursor(&self, uid: &str) -> DbResult<Option<String>> {
+        let mut stmt = self.conn.prepare("SELECT value FROM sync_state WHERE key = ?")?;
+        let mut rows = stmt.query_map([format!("cursor:{}", uid)], |row| row.get(0))?;
+        Ok(rows.next().transpose()?)
+    }
+
+    pub fn set_sync_cursor(&self, uid: &str, cursor: &str) -> DbResult<()> {
+        self.conn.execute(
+            "INSERT INTO sync_state (key, value) VALUES (?, ?)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            rusqlite::params![format!("cursor:{}", uid), cursor],
+        )?;
+        Ok(())
+    }
+
+    fn open_record(&self, (mut record, sealed): (MessageRecord, Option<SealedValue>)) -> DbResult<MessageRecord> {
+        if let Some(sealed) = sealed {
+            let plaintext = self.cipher.open(&record.id, &sealed)?;
+            record.content = Some(String::from_utf8(plaintext).map_err(|_| {
+                CryptoError::InvalidPayload(format!("History content of {} is not UTF-8", record.id))
+            })?);
+        }
+        Ok(record)
+    }
+
+    /// Seals `content` left in plaintext by pre-v3 versions
+    fn seal_plaintext_rows(&self) -> DbResult<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let rows: Vec<(String, String)> = {
+            let mut stmt = tx.prepare("SELECT id, content FROM messages WHERE content IS NOT NULL")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let key = self.cipher.current_key()?;
+        for (id, content) in &rows {
+            let sealed = HistoryCipher::seal_with(&key, id, content.as_bytes())?;
+            tx.execute(
+                "UPDATE messages SET sealed_content = ?, key_generation = ?, content = NULL WHERE id = ?",
+                rusqlite::params![sealed.bytes, sealed.generation, id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(rows.len())
+    }
+
+    fn key_generations_in_use(&self) -> DbResult<Vec<u32>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT key_generation FROM messages WHERE key_generation IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<SqliteResult<_>>()?)
+    }
+
+    /// Clears the old "last" row (deleting it if ephemeral) and sets the new
+    /// one (caller holds a transaction)
+    fn move_last_pointer(conn: &Connection, message_id: &str) -> DbResult<bool> {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE message_id = ?)",
+            [message_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "DELETE FROM messages WHERE is_last = 1 AND ephemeral = 1 AND message_id != ?",
+            [message_id],
+        )?;
+        conn.execute("UPDATE messages SET is_last = 0 WHERE is_last = 1", [])?;
+        conn.execute("UPDATE messages SET is_last = 1 WHERE message_id = ?", [message_id])?;
+        Ok(true)
+    }
+}
+
+/// Replay ledger stored in the `seen_messages` table of the app database
+///
+/// Opens its own connection to the same file as `Database` so it can be
+/// shared with the receiver across threads.
+pub struct SqliteSeenLedger {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteSeenLedger {
+    pub fn open(path: &Path) -> SqliteResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SEEN_MESSAGES_SCHEMA)?;
+        Ok(SqliteSeenLedger { conn: Mutex::new(conn) })
+    }
+
+    fn lock(&self) -> CryptoResult<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock()
+            .map_err(|_| CryptoError::LedgerUnavailable("Ledger lock poisoned".to_string()))
+    }
+}
+
+impl SeenMessageLedger for SqliteSeenLedger {
+    fn record_if_new(&self, message_id: &str, sender_device_id: &str, seen_at: i64) -> CryptoResult<bool> {
+        let inserted = self.lock()?
+            .execute(
+                "INSERT OR IGNORE INTO seen_messages (message_id, sender_device_id, seen_at)
+                 VALUES (?, ?, ?)",
+                rusqlite::params![message_id, sender_device_id, seen_at],
+            )
+            .map_err(|e| CryptoError::LedgerUnavailable(e.to_string()))?;
+        Ok(inserted == 1)
+    }
+
+    fn is_seen(&self, message_id: &str) -> CryptoResult<bool> {
+        self.lock()?
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM seen_messages WHERE message_id = ?)",
+                [message_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| CryptoError::LedgerUnavailable(e.to_string()))
+    }
+
+    fn prune_before(&self, cutoff: i64) -> CryptoResult<()> {
+        self.lock()?
+            .execute("DELETE FROM seen_messages WHERE seen_at < ?", [cutoff])
+            .map_err(|e| CryptoError::LedgerUnavailable(e.to_string()))?;
         Ok(())
     }
+}
+
+/// Pinned peer keys stored in the `devices` table of the app database
+///
+/// Rows are keyed by device ID (`id = device_id`).
+pub struct SqlitePinStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqlitePinStore {
+    pub fn open(path: &Path) -> SqliteResult<Self> {
+        let conn = Connection::open(path)?;
+        ensure_devices_table(&conn)?;
+        Ok(SqlitePinStore { conn: Mutex::new(conn) })
+    }
+
+    fn lock(&self) -> CryptoResult<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock()
+            .map_err(|_| CryptoError::PinStoreUnavailable("Pin store lock poisoned".to_string()))
+    }
+}
+
+impl PinStore for SqlitePinStore {
+    fn get_pin(&self, device_id: &str) -> CryptoResult<Option<PinnedKeys>> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT device_id, pub_sign_key, pub_box_key, key_generation, previous_sign_key,
+                        sign_key_rotated_at, pinned_at FROM devices
+                 WHERE id = ? AND pub_sign_key IS NOT NULL AND pub_box_key IS NOT NULL",
+            )
+            .map_err(|e| CryptoError::PinStoreUnavailable(e.to_string()))?;
+
+        let mut rows = stmt
+            .query_map([device_id], |row| {
+                Ok(PinnedKeys {
+                    device_id: row.get(0)?,
+                    pub_sign_key: row.get(1)?,
+                    pub_box_key: row.get(2)?,
+                    key_generation: row.get::<_, Option<u32>>(3)?.unwrap_or(1),
+                    previous_sign_key: row.get(4)?,
+                    sign_key_rotated_at: row.get(5)?,
+                    pinned_at: row.get::<_, Option<i64>>(6)?.unwrap_or(0),
+                })
+            })
+            .map_err(|e| CryptoError::PinStoreUnavailable(e.to_string()))?;
+
+        rows.next()
+            .transpose()
+            .map_err(|e| CryptoError::PinStoreUnavailable(e.to_string()))
+    }
+
+    fn put_pin(&self, keys: &PinnedKeys) -> CryptoResult<()> {
+        self.lock()?
+            .execute(
+                "INSERT INTO devices (id, device_id, pub_sign_key, pub_box_key, key_generation,
+                                      previous_sign_key, sign_key_rotated_at, pinned_at)
+                 VALUES (?1, ?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(id) DO UPDATE SET
+                     pub_sign_key = excluded.pub_sign_key,
+                     pub_box_key = excluded.pub_box_key,
+                     key_generation = excluded.key_generation,
+                     previous_sign_key = excluded.previous_sign_key,
+                     sign_key_rotated_at = excluded.sign_key_rotated_at,
+                     pinned_at = excluded.pinned_at",
+                rusqlite::params![
+                    keys.device_id,
+                    keys.pub_sign_key,
+                    keys.pub_box_key,
+                    keys.key_generation,
+                    keys.previous_sign_key,
+                    keys.sign_key_rotated_at,
+                    keys.pinned_at,
+                ],
+            )
+            .map_err(|e| CryptoError::PinStoreUnavailable(e.to_string()))?;
+        Ok(())
+    }
+
+    fn remove_pin(&self, device_id: &str) -> CryptoResult<()> {
+        self.lock()?
+            .execute(
+                "UPDATE devices SET pub_sign_key = NULL, pub_box_key = NULL, key_generation = NULL,
+                     previous_sign_key = NULL, sign_key_rotated_at = NULL, pinned_at = NULL
+                 WHERE id = ?",
+                [device_id],
+            )
+            .map_err(|e| CryptoError::PinStoreUnavailable(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Verified revocation statements stored in the `revocations` table
+///
+/// The first statement for a device wins; revocations are never removed.
+pub struct SqliteRevocationStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteRevocationStore {
+    pub fn open(path: &Path) -> SqliteResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(REVOCATIONS_SCHEMA)?;
+        Ok(SqliteRevocationStore { conn: Mutex::new(conn) })
+    }
+
+    fn lock(&self) -> CryptoResult<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock()
+            .map_err(|_| CryptoError::RevocationStoreUnavailable("Revocation store lock poisoned".to_string()))
+    }
+
+    fn query(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> CryptoResult<Vec<RevocationStatement>> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare(sql)
+            .map_err(|e| CryptoError::RevocationStoreUnavailable(e.to_string()))?;
+
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(RevocationStatement {
+                    revoked_device_id: row.get(0)?,
+                    issuer_device_id: row.get(1)?,
+                    issued_at: row.get(2)?,
+                    reason: row.get(3)?,
+                    signature: row.get(4)?,
+                })
+            })
+            .map_err(|e| CryptoError::RevocationStoreUnavailable(e.to_string()))?;
+
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| CryptoError::RevocationStoreUnavailable(e.to_string()))
+    }
+}
+
+impl RevocationStore for SqliteRevocationStore {
+    fn get_revocation(&self, device_id: &str) -> CryptoResult<Option<RevocationStatement>> {
+        Ok(self
+            .query(
+                "SELECT revoked_device_id, issuer_device_id, issued_at, reason, signature
+                 FROM revocations WHERE revoked_device_id = ?",
+                &[&device_id],
+            )?
+            .into_iter()
+            .next())
+    }
+
+    fn put_revocation(&self, statement: &RevocationStatement) -> CryptoResult<()> {
+        self.lock()?
+            .execute(
+                "INSERT OR IGNORE INTO revocations
+                     (revoked_device_id, issuer_device_id, issued_at, reason, signature)
+                 VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    statement.revoked_device_id,
+                    statement.issuer_device_id,
+                    statement.issued_at,
+                    statement.reason,
+                    statement.signature
+                ],
+            )
+            .map_err(|e| CryptoError::RevocationStoreUnavailable(e.to_string()))?;
+        Ok(())
+    }
+
+    fn list_revocations(&self) -> CryptoResult<Vec<RevocationStatement>> {
+        self.query(
+            "SELECT revoked_device_id, issuer_device_id, issued_at, reason, signature
+             FROM revocations ORDER BY revoked_device_id",
+            &[],
+        )
+    }
+}
+
+/// Outgoing message queue stored in the `outbox` table
+pub struct SqliteOutbox {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteOutbox {
+    const COLUMNS: &'static str = "seq, uid, message_doc, blob, status, attempts, last_error, queued_at";
+
+    pub fn open(path: &Path) -> SqliteResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(OUTBOX_SCHEMA)?;
+        Ok(SqliteOutbox { conn: Mutex::new(conn) })
+    }
+
+    fn lock(&self) -> OutboxResult<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock()
+            .map_err(|_| OutboxError::Storage("Outbox lock poisoned".to_string()))
+    }
+
+    fn query(&self, filter: &str, params: &[&dyn rusqlite::ToSql]) -> OutboxResult<Vec<OutboxItem>> {
+        let conn = self.lock()?;
+        let sql = format!("SELECT {} FROM outbox {} ORDER BY seq", Self::COLUMNS, filter);
+        let mut stmt = conn.prepare(&sql).map_err(|e| OutboxError::Storage(e.to_string()))?;
+
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, i64>(7)?,
+                ))
+            })
+            .map_err(|e| OutboxError::Storage(e.to_string()))?;
+
+        rows.map(|row| {
+            let (seq, uid, doc_json, blob, status, attempts, last_error, queued_at) =
+                row.map_err(|e| OutboxError::Storage(e.to_string()))?;
+            let message_doc: MessageDoc = serde_json::from_str(&doc_json)
+                .map_err(|e| OutboxError::Storage(format!("Corrupt outbox item {}: {}", seq, e)))?;
+            let status = OutboxStatus::parse(&status)
+                .ok_or_else(|| OutboxError::Storage(format!("Unknown outbox status {:?}", status)))?;
+            Ok(OutboxItem { seq, uid, message_doc, blob, status, attempts, last_error, queued_at })
+        })
+        .collect()
+    }
+}
+
+impl OutboxStore for SqliteOutbox {
+    fn enqueue(&self, uid: &str, message_doc: &MessageDoc, blob: &[u8]) -> OutboxResult<OutboxItem> {
+        let doc_json = serde_json::to_string(message_doc)
+            .map_err(|e| OutboxError::Storage(e.to_string()))?;
+        self.lock()?
+            .execute(
+                "INSERT OR IGNORE INTO outbox (message_id, uid, message_doc, blob, queued_at)
+                 VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    message_doc.message_id,
+                    uid,
+                    doc_json,
+                    blob,
+                    chrono::Utc::now().timestamp()
+                ],
+            )
+            .map_err(|e| OutboxError::Storage(e.to_string()))?;
+
+        self.query("WHERE message_id = ?", &[&message_doc.message_id])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| OutboxError::Storage("Queued item vanished".to_string()))
+    }
+
+    fn pending(&self) -> OutboxResult<Vec<OutboxItem>> {
+        self.query("WHERE status = ?", &[&OutboxStatus::Pending.as_str()])
+    }
+
+    fn items(&self) -> OutboxResult<Vec<OutboxItem>> {
+        self.query("", &[])
+    }
+
+    fn record_attempt(&self, message_id: &str, status: OutboxStatus, error: Option<&str>) -> OutboxResult<()> {
+        self.lock()?
+            .execute(
+                "UPDATE outbox SET status = ?, attempts = attempts + 1, last_error = ? WHERE message_id = ?",
+                rusqlite::params![status.as_str(), error, message_id],
+            )
+            .map_err(|e| OutboxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn purge_sent(&self) -> OutboxResult<()> {
+        self.lock()?
+            .execute("DELETE FROM outbox WHERE status = ?", [OutboxStatus::Sent.as_str()])
+            .map_err(|e| OutboxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(path: &PathBuf) -> Database {
+        Database::new(path, &KeyManager::in_memory()).unwrap()
+    }
+
+    fn text(message_id: &str, sender: &str) -> NewMessage {
+        NewMessage {
+            message_id: message_id.to_string(),
+            sender_device_id: sender.to_string(),
+            message_type: MessageType::Text,
+            mime: Some("text/plain".to_string()),
+            size_bytes: Some(5),
+            filename: None,
+            content: "hello".to_string(),
+            created_at: Some(1_700_000_000),
+            retention: Retention::default(),
+            sensitive: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_migrates_unversioned_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+
+        // Pre-migration layout: duplicate rows and every row flagged last
+        Connection::open(&path).unwrap()
+            .execute_batch(
+                "CREATE TABLE messages (id TEXT PRIMARY KEY, message_id TEXT NOT NULL,
+                 sender_device_id TEXT, type TEXT DEFAULT 'text', content TEXT, created_at INTEGER,
+                 downloaded_at INTEGER, is_favorite BOOLEAN DEFAULT 0, is_last BOOLEAN DEFAULT 0);
+                 INSERT INTO messages VALUES ('r1', 'm-1', 'dev-a', 'text', 'legacy secret', NULL, 10, 0, 1);
+                 INSERT INTO messages VALUES ('r2', 'm-1', 'dev-a', 'text', 'legacy secret', NULL, 11, 0, 1);
+                 INSERT INTO messages VALUES ('r3', 'm-2', 'dev-b', 'image', 'aGk=', NULL, 20, 1, 1);",
+            )
+            .unwrap();
+
+        let key_manager = KeyManager::in_memory();
+        let db = Database::new(&path, &key_manager).unwrap();
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+
+        let all = db.list_messages(&HistoryQuery::default()).unwrap();
+        assert_eq!(all.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["r3", "r2"]);
+        assert_eq!(all[0].message_type, MessageType::Image);
+        assert!(all[0].is_favorite);
+        assert_eq!(db.get_last_message().unwrap().unwrap().message_id, "m-2");
+        assert_eq!(all[1].content.as_deref(), Some("legacy secret"));
+        drop(db);
+
+        // Legacy plaintext was sealed and vacuumed out of the file
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!raw.windows(b"legacy secret".len()).any(|w| w == b"legacy secret"));
+
+        // Reopening is a no-op
+        let db = Database::new(&path, &key_manager).unwrap();
+        assert_eq!(db.list_messages(&HistoryQuery::default()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_refuses_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+        Connection::open(&path).unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+
+        assert!(Database::new(&path, &KeyManager::in_memory()).is_err());
+    }
+
+    #[test]
+    fn test_last_pointer_moves_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir.path().join("spectrocap.db"));
+
+        db.insert_message(&text("m-1", "dev-a")).unwrap();
+        let second = db.insert_message(&text("m-2", "dev-a")).unwrap();
+        assert!(second.is_last);
+        assert_eq!(second.size_bytes, Some(5));
+
+        // Duplicate delivery neither adds a row nor moves the pointer
+        db.set_last("m-1").unwrap();
+        db.insert_message(&text("m-2", "dev-a")).unwrap();
+        assert_eq!(db.get_last_message().unwrap().unwrap().message_id, "m-1");
+
+        assert!(!db.set_last("missing").unwrap());
+        assert_eq!(db.get_last_message().unwrap().unwrap().message_id, "m-1");
+
+        let flagged: i64 = db.conn
+            .query_row("SELECT COUNT(*) FROM messages WHERE is_last = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(flagged, 1);
+    }
+
+    #[test]
+    fn test_history_paging_filters_and_favorites() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir.path().join("spectrocap.db"));
+
+        for i in 0..5 {
+            db.insert_message(&text(&format!("a-{}", i), "dev-a")).unwrap();
+        }
+        let mut image = text("b-0", "dev-b");
+        image.message_type = MessageType::Image;
+        image.mime = Some("image/png".to_string());
+        db.insert_message(&image).unwrap();
+
+        let page = db.list_messages(&HistoryQuery { limit: Some(2), offset: 1, ..Default::default() }).unwrap();
+        assert_eq!(page.iter().map(|m| m.message_id.as_str()).collect::<Vec<_>>(), ["a-4", "a-3"]);
+
+        let from_a = HistoryQuery { sender_device_id: Some("dev-a".to_string()), ..Default::default() };
+        assert_eq!(db.list_messages(&from_a).unwrap().len(), 5);
+
+        let images = HistoryQuery { message_type: Some(MessageType::Image), ..Default::default() };
+        let images = db.list_messages(&images).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].mime.as_deref(), Some("image/png"));
+
+        assert!(db.set_favorite("a-2", true).unwrap());
+        assert!(!db.set_favorite("missing", true).unwrap());
+        let favorites = db.list_messages(&HistoryQuery { favorites_only: true, ..Default::default() }).unwrap();
+        assert_eq!(favorites.iter().map(|m| m.message_id.as_str()).collect::<Vec<_>>(), ["a-2"]);
+    }
+
+    #[test]
+    fn test_expiring_and_ephemeral_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir.path().join("spectrocap.db"));
+        let now = chrono::Utc::now().timestamp();
+
+        let mut card = text("m-card", "dev-a");
+        card.retention = Retention { expires_at: Some(now + 300), ephemeral: false };
+        card.sensitive = vec![SensitiveKind::CardNumber];
+        db.insert_message(&card).unwrap();
+        db.set_favorite("m-card", true).unwrap();
+
+        let mut token = text("m-token", "dev-a");
+        token.retention = Retention { expires_at: Some(now + 300), ephemeral: true };
+        token.sensitive = vec![SensitiveKind::PasswordManager, SensitiveKind::GithubToken];
+        let stored = db.insert_message(&token).unwrap();
+        assert!(stored.is_last && stored.ephemeral);
+        assert_eq!(stored.sensitive, token.sensitive);
+
+        // Ephemeral: "last" only, never listed
+        let listed = db.list_messages(&HistoryQuery::default()).unwrap();
+        assert_eq!(listed.iter().map(|m| m.message_id.as_str()).collect::<Vec<_>>(), ["m-card"]);
+        assert_eq!(listed[0].expires_at, Some(now + 300));
+        assert_eq!(db.get_last_message().unwrap().unwrap().message_id, "m-token");
+
+        // ...and gone once something else becomes "last"
+        db.insert_message(&text("m-plain", "dev-a")).unwrap();
+        assert!(!db.has_message("m-token").unwrap());
+
+        // Expiry wins over favorites
+        assert_eq!(db.purge_expired(now).unwrap(), 0);
+        assert_eq!(db.purge_expired(now + 300).unwrap(), 1);
+        assert!(!db.has_message("m-card").unwrap());
+        assert!(db.has_message("m-plain").unwrap());
+    }
+
+    #[test]
+    fn test_history_sealed_at_rest_and_rekeyed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+        let key_manager = KeyManager::in_memory();
+        let db = Database::new(&path, &key_manager).unwrap();
+
+        let mut secret = text("m-1", "dev-a");
+        secret.content = "correct horse battery staple".to_string();
+        let stored = db.insert_message(&secret).unwrap();
+        db.insert_message(&text("m-2", "dev-a")).unwrap();
+        assert_eq!(stored.content.as_deref(), Some("correct horse battery staple"));
+
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!raw.windows(secret.content.len()).any(|w| w == secret.content.as_bytes()));
+
+        assert_eq!(db.rekey_history().unwrap(), 2);
+        assert_eq!(db.get_message("m-1").unwrap().unwrap().content, stored.content);
+        assert!(key_manager.history_key_for(1).is_err());
+
+        // Another key store cannot read the file
+        drop(db);
+        let stranger = Database::new(&path, &KeyManager::in_memory()).unwrap();
+        assert!(matches!(stranger.get_message("m-1"), Err(DbError::Crypto(_))));
+    }
+
+    #[test]
+    fn test_sealed_content_bound_to_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir.path().join("spectrocap.db"));
+        db.insert_message(&text("m-1", "dev-a")).unwrap();
+        db.insert_message(&text("m-2", "dev-a")).unwrap();
+
+        db.conn
+            .execute(
+                "UPDATE messages SET sealed_content =
+                 (SELECT sealed_content FROM messages WHERE message_id = 'm-1')
+                 WHERE message_id = 'm-2'",
+                [],
+            )
+            .unwrap();
+
+        assert!(db.get_message("m-1").unwrap().is_some());
+        assert!(matches!(
+            db.get_message("m-2"),
+            Err(DbError::Crypto(CryptoError::Tampered(_)))
+        ));
+    }
+
+    #[test]
+    fn test_seen_ledger_persists_across_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+        let _db = open(&path);
+
+        let ledger = SqliteSeenLedger::open(&path).unwrap();
+        assert!(ledger.record_if_new("msg-1", "dev-a", 100).unwrap());
+        assert!(!ledger.record_if_new("msg-1", "dev-a", 200).unwrap());
+        assert!(ledger.is_seen("msg-1").unwrap());
+        assert!(!ledger.is_seen("msg-2").unwrap());
+        drop(ledger);
+
+        let reopened = SqliteSeenLedger::open(&path).unwrap();
+        assert!(!reopened.record_if_new("msg-1", "dev-a", 300).unwrap());
+
+        reopened.prune_before(150).unwrap();
+        assert!(reopened.record_if_new("msg-1", "dev-a", 400).unwrap());
+    }
+
+    #[test]
+    fn test_pin_store_upgrades_old_devices_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+
+        // Database created before key pinning existed
+        Connection::open(&path).unwrap()
+            .execute_batch(
+                "CREATE TABLE devices (id TEXT PRIMARY KEY, device_id TEXT, platform TEXT,
+                 name TEXT, created_at INTEGER, last_seen_at INTEGER);",
+            )
+            .unwrap();
+        let _db = open(&path);
+
+        let store = SqlitePinStore::open(&path).unwrap();
+        assert_eq!(store.get_pin("dev-phone").unwrap(), None);
+
+        let pin = PinnedKeys {
+            device_id: "dev-phone".to_string(),
+            pub_sign_key: "sign".to_string(),
+            pub_box_key: "box".to_string(),
+            key_generation: 3,
+            previous_sign_key: Some("old-sign".to_string()),
+            sign_key_rotated_at: Some(40),
+            pinned_at: 42,
+        };
+        store.put_pin(&pin).unwrap();
+        assert_eq!(store.get_pin("dev-phone").unwrap(), Some(pin));
+
+        store.remove_pin("dev-phone").unwrap();
+        assert_eq!(store.get_pin("dev-phone").unwrap(), None);
+    }
+
+    #[test]
+    fn test_revocation_store_keeps_first_statement() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+        let _db = open(&path);
+
+        let store = SqliteRevocationStore::open(&path).unwrap();
+        let statement = RevocationStatement {
+            revoked_device_id: "dev-phone".to_string(),
+            issuer_device_id: "dev-pc".to_string(),
+            issued_at: 100,
+            reason: Some("lost".to_string()),
+            signature: "sig".to_string(),
+        };
+        store.put_revocation(&statement).unwrap();
+        store.put_revocation(&RevocationStatement { issued_at: 200, ..statement.clone() }).unwrap();
+        drop(store);
+
+        let reopened = SqliteRevocationStore::open(&path).unwrap();
+        assert_eq!(reopened.get_revocation("dev-phone").unwrap(), Some(statement.clone()));
+        assert_eq!(reopened.get_revocation("dev-pc").unwrap(), None);
+        assert_eq!(reopened.list_revocations().unwrap(), vec![statement]);
+    }
+
+    #[test]
+    fn test_outbox_survives_restart_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spectrocap.db");
+        let _db = open(&path);
+
+        let doc = |id: &str| MessageDoc::from_value(&serde_json::json!({
+            "messageId": id,
+            "senderDeviceId": "dev-pc",
+            "recipients": ["dev-phone"],
+            "storagePath": format!("users/uid-1/messages/{}.bin", id),
+        }))
+        .unwrap();
+
+        let outbox = SqliteOutbox::open(&path).unwrap();
+        let first = outbox.enqueue("uid-1", &doc("m-1"), b"blob-1").unwrap();
+        outbox.enqueue("uid-1", &doc("m-2"), b"blob-2").unwrap();
+        assert_eq!(outbox.enqueue("uid-1", &doc("m-1"), b"other").unwrap(), first);
+        outbox.record_attempt("m-1", OutboxStatus::Pending, Some("offline")).unwrap();
+        drop(outbox);
+
+        let reopened = SqliteOutbox::open(&path).unwrap();
+        let pending = reopened.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].message_doc, doc("m-1"));
+        assert_eq!(pending[0].blob, b"blob-1");
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(pending[0].last_error.as_deref(), Some("offline"));
+
+        reopened.record_attempt("m-1", OutboxStatus::Sent, None).unwrap();
+        reopened.record_attempt("m-2", OutboxStatus::Failed, Some("forbidden")).unwrap();
+        assert!(reopened.pending().unwrap().is_empty());
 
-    pub fn get_messages(&self) -> SqliteResult<Vec<String>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT message_id, content FROM messages ORDER BY downloaded_at DESC LIMIT 100")?;
-        let messages = stmt
-            .query_map([], |row| {
-                Ok(format!("{}: {}", row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(messages)
-    }
-
-    pub fn get_last_message(&self) -> SqliteResult<Option<String>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT content FROM messages WHERE is_last = true ORDER BY downloaded_at DESC LIMIT 1")?;
-        let result = stmt.query_row([], |row| row.get(0)).ok();
-        Ok(result)
+        reopened.purge_sent().unwrap();
+        let items = reopened.items().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].status, OutboxStatus::Failed);
     }
 }
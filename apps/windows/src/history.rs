@@ -0,0 +1,315 @@
+// Clipboard history service
+// src/history.rs
+//
+// Backs the `get_last_message`, `get_history` and `paste_last` commands and
+// the tray's "Paste Last" item: reads decrypted entries from the `Database`
+// shared with sync and pastes them through a `ClipboardBackend` (set
+// clipboard, then Ctrl+V).
+// Pasted clips are recorded in `RecentClips` so the clipboard watcher does
+// not send them back to the phone. Expired messages are purged before every
+// read, and messages flagged by `sensitive` are written as concealed clips
+// so they stay out of the OS clipboard history.
+
+use std::sync::Arc;
+use base64::{Engine, engine::general_purpose};
+use serde::Serialize;
+use thiserror::Error;
+use crate::clipboard::{ClipData, ClipboardBackend, ClipboardError};
+use crate::crypto::schema::MessageType;
+use crate::db::{Database, DbError, HistoryQuery, MessageRecord, SharedDatabase};
+use crate::sensitive::SensitiveKind;
+use crate::watcher::{ClipFingerprint, RecentClips};
+
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error(transparent)]
+    Storage(#[from] DbError),
+    #[error(transparent)]
+    Clipboard(#[from] ClipboardError),
+    #[error("History is empty")]
+    Empty,
+    #[error("Message {0} is not in history")]
+    NotFound(String),
+    #[error("{0} messages cannot be pasted")]
+    NotPastable(&'static str),
+    #[error("Stored payload of {0} is corrupt")]
+    Corrupt(String),
+}
+
+impl HistoryError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            HistoryError::Storage(e) => e.code(),
+            HistoryError::Clipboard(_) => "clipboard",
+            HistoryError::Empty => "history_empty",
+            HistoryError::NotFound(_) => "not_found",
+            HistoryError::NotPastable(_) => "not_pastable",
+            HistoryError::Corrupt(_) => "corrupt",
+        }
+    }
+}
+
+/// History entry as sent to the webview
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub message_id: String,
+    pub sender_device_id: Option<String>,
+    #[serde(rename = "type")]
+    pub message_type: MessageType,
+    pub mime: Option<String>,
+    pub size_bytes: Option<u64>,
+    pub filename: Option<String>,
+    /// Text messages only
+    pub text: Option<String>,
+    /// Image messages only
+    pub image_base64: Option<String>,
+    pub created_at: Option<i64>,
+    pub downloaded_at: i64,
+    pub is_favorite: bool,
+    pub is_last: bool,
+    /// Unix seconds; the entry disappears after this
+    pub expires_at: Option<i64>,
+    /// Secrets detected on receipt (the UI warns before pasting)
+    pub sensitive: Vec<SensitiveKind>,
+}
+
+impl From<MessageRecord> for HistoryEntry {
+    fn from(record: MessageRecord) -> Self {
+        let (text, image_base64) = match record.message_type {
+            MessageType::Text => (record.content, None),
+            MessageType::Image => (None, record.content),
+            MessageType::File => (None, None),
+        };
+
+        HistoryEntry {
+            message_id: record.message_id,
+            sender_device_id: record.sender_device_id,
+            message_type: record.message_type,
+            mime: record.mime,
+            size_bytes: record.size_bytes,
+            filename: record.filename,
+            text,
+            image_base64,
+            created_at: record.created_at,
+            downloaded_at: record.downloaded_at,
+            is_favorite: record.is_favorite,
+            is_last: record.is_last,
+            expires_at: record.expires_at,
+            sensitive: record.sensitive,
+        }
+    }
+}
+
+pub struct History {
+    db: SharedDatabase,
+    clipboard: Arc<dyn ClipboardBackend>,
+    recent: Option<Arc<RecentClips>>,
+}
+
+impl History {
+    pub fn new(db: SharedDatabase, clipboard: Arc<dyn ClipboardBackend>) -> Self {
+        History { db, clipboard, recent: None }
+    }
+
+    /// Records pasted clips for echo suppression
+    pub fn with_recent_clips(mut self, recent: Arc<RecentClips>) -> Self {
+        self.recent = Some(recent);
+        self
+    }
+
+    pub fn last(&self) -> Result<Option<HistoryEntry>, HistoryError> {
+        Ok(self.current_db()?.get_last_message()?.map(HistoryEntry::from))
+    }
+
+    pub fn list(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, HistoryError> {
+        let records = self.current_db()?.list_messages(query)?;
+        Ok(records.into_iter().map(HistoryEntry::from).collect())
+    }
+
+    /// Pastes the "last" message into the focused window
+    pub fn paste_last(&self) -> Result<HistoryEntry, HistoryError> {
+        let record = self.current_db()?.get_last_message()?.ok_or(HistoryError::Empty)?;
+        self.paste_record(record)
+    }
+
+    /// Pastes a history entry and makes it the "last" message ("Paste From...")
+    pub fn paste(&self, message_id: &str) -> Result<HistoryEntry, HistoryError> {
+        let record = {
+            let db = self.current_db()?;
+            if !db.set_last(message_id)? {
+                return Err(HistoryError::NotFound(message_id.to_string()));
+            }
+            db.get_message(message_id)?
+                .ok_or_else(|| HistoryError::NotFound(message_id.to_string()))?
+        };
+        self.paste_record(record)
+    }
+
+    /// Re-seals history under a new data key (see `Database::rekey_history`)
+    ///
+    /// # Returns
+    /// Number of entries re-sealed
+    pub fn rekey(&self) -> Result<usize, HistoryError> {
+        Ok(self.current_db()?.rekey_history()?)
+    }
+
+    fn paste_record(&self, record: MessageRecord) -> Result<HistoryEntry, HistoryError> {
+        let content = record.content.as_deref().unwrap_or_default();
+        let (mut clip, fingerprint) = match record.message_type {
+            MessageType::Text => (ClipData::text(content), ClipFingerprint::of_text(content)),
+            MessageType::Image => {
+                let bytes = general_purpose::STANDARD.decode(content)
+                    .map_err(|_| HistoryError::Corrupt(record.message_id.clone()))?;
+                let fingerprint = ClipFingerprint::of_image(&bytes);
+                (ClipData::image(record.mime.as_deref().unwrap_or("image/png"), bytes), fingerprint)
+            }
+            MessageType::File => return Err(HistoryError::NotPastable("File")),
+        };
+        clip.concealed = !record.sensitive.is_empty() || record.ephemeral || record.expires_at.is_some();
+        // Before the write, so the watcher can never see the clip unrecorded
+        if let Some(recent) = &self.recent {
+            recent.remember(fingerprint);
+        }
+        self.clipboard.write(&clip)?;
+
+        match self.clipboard.paste() {
+            // Left on the clipboard for the user to paste
+            Err(ClipboardError::Unsupported(what)) => log::info!("Copied {} ({} unavailable)", record.message_id, what),
+            result => result?,
+        }
+        Ok(record.into())
+    }
+
+    /// Database with expired messages already purged
+    fn current_db(&self) -> Result<std::sync::MutexGuard<'_, Database>, HistoryError> {
+        let db = self.db.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        db.purge_expired(chrono::Utc::now().timestamp())?;
+        Ok(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard::MemoryClipboard;
+    use crate::crypto::key_mgmt::KeyManager;
+    use crate::db::NewMessage;
+    use crate::sensitive::Retention;
+
+    fn message(message_id: &str, message_type: MessageType, content: &str) -> NewMessage {
+        NewMessage {
+            message_id: message_id.to_string(),
+            sender_device_id: "dev-phone".to_string(),
+            message_type,
+            mime: None,
+            size_bytes: None,
+            filename: None,
+            content: content.to_string(),
+            created_at: None,
+            retention: Retention::default(),
+            sensitive: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_paste_last_and_paste_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&dir.path().join("spectrocap.db"), &KeyManager::in_memory()).unwrap();
+        db.insert_message(&message("m-1", MessageType::Text, "hello")).unwrap();
+        db.insert_message(&message("m-2", MessageType::Image, "iVBORw0KGgo=")).unwrap();
+        db.insert_message(&message("m-3", MessageType::File, "AAAA")).unwrap();
+
+        let clipboard = Arc::new(MemoryClipboard::new());
+        let recent = Arc::new(RecentClips::default());
+        let history = History::new(db.into_shared(), clipboard.clone()).with_recent_clips(recent.clone());
+
+        assert!(matches!(history.paste_last(), Err(HistoryError::NotPastable(_))));
+        assert_eq!(clipboard.paste_count(), 0);
+
+        let pasted = history.paste("m-2").unwrap();
+        assert!(pasted.is_last);
+        assert_eq!(clipboard.contents(), ClipData::image("image/png", b"\x89PNG\r\n\x1a\n".to_vec()));
+
+        history.paste("m-1").unwrap();
+        history.paste_last().unwrap();
+        assert_eq!(clipboard.contents(), ClipData::text("hello"));
+        assert_eq!(clipboard.paste_count(), 3);
+        assert!(recent.contains(&ClipFingerprint::of_text("hello")));
+        assert!(matches!(history.paste("missing"), Err(HistoryError::NotFound(_))));
+
+        let entries = history.list(&HistoryQuery::default()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].image_base64.as_deref(), Some("iVBORw0KGgo="));
+        let json = serde_json::to_value(&entries[2]).unwrap();
+        assert_eq!(json["type"], "text");
+        assert_eq!(json["text"], "hello");
+        assert_eq!(json["isLast"], true);
+    }
+
+    #[test]
+    fn test_sensitive_entries_pasted_concealed_until_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&dir.path().join("spectrocap.db"), &KeyManager::in_memory()).unwrap();
+        let now = chrono::Utc::now().timestamp();
+
+        let mut card = message("m-card", MessageType::Text, "4111 1111 1111 1111");
+        card.retention = Retention { expires_at: Some(now + 300), ephemeral: false };
+        card.sensitive = vec![SensitiveKind::CardNumber];
+        db.insert_message(&card).unwrap();
+        let mut expired = message("m-old", MessageType::Text, "5555 5555 5555 4444");
+        expired.retention = Retention { expires_at: Some(now - 1), ephemeral: false };
+        db.insert_message(&expired).unwrap();
+
+        let clipboard = Arc::new(MemoryClipboard::new());
+        let history = History::new(db.into_shared(), clipboard.clone());
+        assert!(matches!(history.paste("m-old"), Err(HistoryError::NotFound(_))));
+
+        let pasted = history.paste("m-card").unwrap();
+        assert_eq!(pasted.sensitive, [SensitiveKind::CardNumber]);
+        assert!(clipboard.contents().concealed);
+        let json = serde_json::to_value(&pasted).unwrap();
+        assert_eq!(json["sensitive"], serde_json::json!(["cardNumber"]));
+        assert_eq!(json["expiresAt"], now + 300);
+
+        history.paste_last().unwrap();
+        assert_eq!(history.list(&HistoryQuery::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rekey_while_sync_inserts() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&dir.path().join("spectrocap.db"), &KeyManager::in_memory())
+            .unwrap()
+            .into_shared();
+        let history = History::new(db.clone(), Arc::new(MemoryClipboard::new()));
+
+        // Inserts the way sync does, through the shared lock
+        let inserter = std::thread::spawn(move || {
+            for i in 0..40 {
+                let message = message(&format!("m-{}", i), MessageType::Text, "hello");
+                db.lock().unwrap().insert_message(&message).unwrap();
+            }
+        });
+        for _ in 0..5 {
+            history.rekey().unwrap();
+        }
+        inserter.join().unwrap();
+        history.rekey().unwrap();
+
+        let query = HistoryQuery { limit: Some(100), ..HistoryQuery::default() };
+        let entries = history.list(&query).unwrap();
+        assert_eq!(entries.len(), 40);
+        assert!(entries.iter().all(|entry| entry.text.as_deref() == Some("hello")));
+    }
+
+    #[test]
+    fn test_empty_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&dir.path().join("spectrocap.db"), &KeyManager::in_memory()).unwrap();
+        let history = History::new(db.into_shared(), Arc::new(MemoryClipboard::new()));
+
+        assert_eq!(history.last().unwrap(), None);
+        assert!(matches!(history.paste_last(), Err(HistoryError::Empty)));
+    }
+}
@@ -6,6 +6,12 @@ pub mod db;
 pub mod hotkey;
 pub mod commands;
 pub mod crypto;  // Phase 2A: E2EE cryptography module
+pub mod transport;
+pub mod sync;
+pub mod outbox;
+pub mod history;
+pub mod watcher;
+pub mod sensitive;
 
 #[cfg(test)]
 mod tests {
@@ -16,24 +16,17 @@ mod clipboard;
 mod db;
 mod hotkey;
 mod crypto;
+mod transport;
+mod sync;
+mod outbox;
+mod history;
+mod watcher;
+mod sensitive;
 
-#[tauri::command]
-async fn get_last_message(app: AppHandle) -> Result<String, String> {
-    // TODO: Query local DB for last message
-    Err("Not implemented".to_string())
-}
-
-#[tauri::command]
-async fn paste_last(app: AppHandle) -> Result<(), String> {
-    // TODO: Get last message, set clipboard, simulate paste
-    Ok(())
-}
-
-#[tauri::command]
-async fn get_history(app: AppHandle) -> Result<String, String> {
-    // TODO: Query local DB for all messages
-    Err("Not implemented".to_string())
-}
+/// Emitted to the webview when the tray asks for the history picker
+const OPEN_HISTORY_EVENT: &str = "open-history";
+/// Emitted to the webview after logout from the tray
+const LOGGED_OUT_EVENT: &str = "logged-out";
 
 fn main() {
     // Setup system tray
@@ -41,11 +34,13 @@ fn main() {
     let logout = CustomMenuItem::new("logout", "Logout");
     let paste_last = CustomMenuItem::new("paste_last", "Paste Last").accelerator("Ctrl+Shift+P");
     let paste_from = CustomMenuItem::new("paste_from", "Paste From...").accelerator("Ctrl+Shift+V");
+    let pause_sending = CustomMenuItem::new(commands::PAUSE_SENDING_ITEM, "Pause Sending");
     let settings = CustomMenuItem::new("settings", "Settings");
 
     let tray_menu = SystemTrayMenu::new()
         .add_item(paste_last)
         .add_item(paste_from)
+        .add_item(pause_sending)
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(settings)
         .add_item(logout)
@@ -56,19 +51,29 @@ fn main() {
 
     tauri::Builder::default()
         .system_tray(system_tray)
+        .manage(commands::SyncState::default())
+        .manage(commands::HistoryState::default())
+        .manage(commands::WatcherState::default())
+        .manage(commands::SensitiveState::default())
         .on_system_tray_event(|app, event| match event {
             SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
                 "quit" => {
                     std::process::exit(0);
                 }
                 "logout" => {
-                    // TODO: Handle logout
+                    let app = app.clone();
+                    tauri::async_runtime::spawn(async move {
+                        app.state::<commands::SyncState>().stop().await;
+                        app.state::<commands::WatcherState>().stop().await;
+                        app.state::<commands::HistoryState>().lock();
+                        let _ = app.emit_all(LOGGED_OUT_EVENT, ());
+                    });
                 }
-                "paste_last" => {
-                    // TODO: Handle paste last
-                }
-                "paste_from" => {
-                    // TODO: Handle paste from
+                "paste_last" => tray_paste_last(app),
+                "paste_from" => show_history(app),
+                commands::PAUSE_SENDING_ITEM => {
+                    let paused = app.state::<commands::WatcherState>().toggle_paused();
+                    commands::update_pause_item(app, paused);
                 }
                 "settings" => {
                     // TODO: Handle settings
@@ -78,12 +83,24 @@ fn main() {
             _ => {}
         })
         .invoke_handler(tauri::generate_handler![
-            get_last_message,
-            paste_last,
-            get_history,
+            commands::get_last_message,
+            commands::get_history,
+            commands::paste_last,
+            commands::paste_message,
+            commands::logout,
             commands::copy_image_to_clipboard,
             commands::save_image_to_file,
-            commands::detect_image_mime
+            commands::save_file_to_path,
+            commands::sanitize_file_name,
+            commands::detect_image_mime,
+            commands::start_inbox_sync,
+            commands::stop_inbox_sync,
+            commands::get_outbox_status,
+            commands::get_watch_rules,
+            commands::set_watch_rules,
+            commands::get_sensitive_policy,
+            commands::set_sensitive_policy,
+            commands::rekey_history
         ])
         .build(tauri::generate_context!())
         .expect("error while running tauri application")
@@ -94,3 +111,28 @@ fn main() {
             _ => {}
         });
 }
+
+/// Tray "Paste Last": paste into whatever window had focus before the click
+fn tray_paste_last(app: &AppHandle) {
+    let history = match app.state::<commands::HistoryState>().history() {
+        Ok(history) => history,
+        Err(e) => {
+            log::warn!("Paste Last: {}", e);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        if let Err(e) = history.paste_last() {
+            log::warn!("Paste Last failed: {}", e);
+        }
+    });
+}
+
+/// Tray "Paste From...": bring up the history window
+fn show_history(app: &AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    let _ = app.emit_all(OPEN_HISTORY_EVENT, ());
+}
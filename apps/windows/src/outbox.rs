@@ -0,0 +1,411 @@
+// Offline outbox for outgoing clips
+// src/outbox.rs
+//
+// Sends go through the outbox: the already-encrypted message document and
+// blob are queued locally (`db::SqliteOutbox` in the app) and the flusher
+// uploads them in queue order whenever the backend is reachable. Uploads are
+// idempotent per messageId: the blob path is deterministic and a message
+// document that already exists counts as delivered. Nothing is re-encrypted,
+// so the signed createdAtClient and envelopes stay as they were at copy time;
+// items older than the receivers' `FreshnessWindow::max_age` would be dropped
+// as stale by every recipient, so they are marked `Failed` instead of sent.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::crypto::replay::FreshnessWindow;
+use crate::crypto::schema::MessageDoc;
+use crate::crypto::sender::EncryptedMessage;
+use crate::transport::{Transport, TransportError};
+
+/// Delivery state of a queued message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Waiting for (another) upload attempt
+    Pending,
+    /// Blob and message document are on the server
+    Sent,
+    /// Rejected by the server; will not be retried
+    Failed,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "pending",
+            OutboxStatus::Sent => "sent",
+            OutboxStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(OutboxStatus::Pending),
+            "sent" => Some(OutboxStatus::Sent),
+            "failed" => Some(OutboxStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// One queued message (`seq` gives the upload order)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxItem {
+    pub seq: i64,
+    pub uid: String,
+    pub message_doc: MessageDoc,
+    pub blob: Vec<u8>,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub queued_at: i64,
+}
+
+/// Status of a queued item for the UI (without the payload)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxItemStatus {
+    pub message_id: String,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub queued_at: i64,
+}
+
+impl From<&OutboxItem> for OutboxItemStatus {
+    fn from(item: &OutboxItem) -> Self {
+        OutboxItemStatus {
+            message_id: item.message_doc.message_id.clone(),
+            status: item.status,
+            attempts: item.attempts,
+            last_error: item.last_error.clone(),
+            queued_at: item.queued_at,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum OutboxError {
+    #[error("Outbox storage error: {0}")]
+    Storage(String),
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+}
+
+pub type OutboxResult<T> = Result<T, OutboxError>;
+
+/// Persistent queue of encrypted messages
+pub trait OutboxStore: Send + Sync {
+    /// Queues a message; re-queuing the same messageId returns the existing item
+    fn enqueue(&self, uid: &str, message_doc: &MessageDoc, blob: &[u8]) -> OutboxResult<OutboxItem>;
+
+    /// Pending items in queue order
+    fn pending(&self) -> OutboxResult<Vec<OutboxItem>>;
+
+    /// All items in queue order
+    fn items(&self) -> OutboxResult<Vec<OutboxItem>>;
+
+    /// Records the outcome of an upload attempt (increments `attempts`)
+    fn record_attempt(&self, message_id: &str, status: OutboxStatus, error: Option<&str>) -> OutboxResult<()>;
+
+    /// Drops delivered items
+    fn purge_sent(&self) -> OutboxResult<()>;
+}
+
+/// In-memory outbox (tests, ephemeral sessions)
+#[derive(Default)]
+pub struct MemoryOutbox {
+    items: Mutex<BTreeMap<i64, OutboxItem>>,
+}
+
+impl MemoryOutbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> OutboxResult<std::sync::MutexGuard<'_, BTreeMap<i64, OutboxItem>>> {
+        self.items.lock()
+            .map_err(|_| OutboxError::Storage("Outbox lock poisoned".to_string()))
+    }
+}
+
+impl OutboxStore for MemoryOutbox {
+    fn enqueue(&self, uid: &str, message_doc: &MessageDoc, blob: &[u8]) -> OutboxResult<OutboxItem> {
+        let mut items = self.lock()?;
+        if let Some(existing) = items.values().find(|i| i.message_doc.message_id == message_doc.message_id) {
+            return Ok(existing.clone());
+        }
+
+        let seq = items.keys().next_back().map_or(1, |last| last + 1);
+        let item = OutboxItem {
+            seq,
+            uid: uid.to_string(),
+            message_doc: message_doc.clone(),
+            blob: blob.to_vec(),
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            queued_at: chrono::Utc::now().timestamp(),
+        };
+        items.insert(seq, item.clone());
+        Ok(item)
+    }
+
+    fn pending(&self) -> OutboxResult<Vec<OutboxItem>> {
+        Ok(self.lock()?.values().filter(|i| i.status == OutboxStatus::Pending).cloned().collect())
+    }
+
+    fn items(&self) -> OutboxResult<Vec<OutboxItem>> {
+        Ok(self.lock()?.values().cloned().collect())
+    }
+
+    fn record_attempt(&self, message_id: &str, status: OutboxStatus, error: Option<&str>) -> OutboxResult<()> {
+        let mut items = self.lock()?;
+        if let Some(item) = items.values_mut().find(|i| i.message_doc.message_id == message_id) {
+            item.status = status;
+            item.attempts += 1;
+            item.last_error = error.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    fn purge_sent(&self) -> OutboxResult<()> {
+        self.lock()?.retain(|_, item| item.status != OutboxStatus::Sent);
+        Ok(())
+    }
+}
+
+/// Result of one flush pass
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlushReport {
+    pub sent: usize,
+    pub failed: usize,
+    /// Items still pending (the backend became unreachable mid-flush)
+    pub remaining: usize,
+}
+
+pub struct OutboxFlusher {
+    store: Arc<dyn OutboxStore>,
+    transport: Arc<dyn Transport>,
+    window: FreshnessWindow,
+}
+
+impl OutboxFlusher {
+    pub fn new(store: Arc<dyn OutboxStore>, transport: Arc<dyn Transport>) -> Self {
+        OutboxFlusher { store, transport, window: FreshnessWindow::default() }
+    }
+
+    /// Window the recipients enforce (default: `FreshnessWindow::default()`)
+    pub fn with_freshness_window(mut self, window: FreshnessWindow) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Queues an encrypted message for delivery
+    pub fn enqueue(&self, uid: &str, message: &EncryptedMessage) -> OutboxResult<OutboxItemStatus> {
+        let item = self.store.enqueue(uid, &message.message_doc, &message.blob)?;
+        Ok(OutboxItemStatus::from(&item))
+    }
+
+    pub fn status(&self) -> OutboxResult<Vec<OutboxItemStatus>> {
+        Ok(self.store.items()?.iter().map(OutboxItemStatus::from).collect())
+    }
+
+    /// Uploads pending items in queue order
+    ///
+    /// Stops at the first transient failure so later clips never overtake an
+    /// earlier one; items the server rejects outright, or that are too old
+    /// for recipients to accept, are marked `Failed` and skipped.
+    pub async fn flush(&self) -> OutboxResult<FlushReport> {
+        let pending = self.store.pending()?;
+        let mut report = FlushReport::default();
+
+        for (index, item) in pending.iter().enumerate() {
+            let message_id = &item.message_doc.message_id;
+
+            if self.is_expired(item, Utc::now()) {
+                let error = format!(
+                    "Expired in outbox: recipients reject messages created more than {} hours ago",
+                    self.window.max_age.as_secs() / 3600,
+                );
+                log::warn!("Outbox item {} not sent: {}", message_id, error);
+                self.store.record_attempt(message_id, OutboxStatus::Failed, Some(&error))?;
+                report.failed += 1;
+                continue;
+            }
+
+            match self.upload(item).await {
+                Ok(()) => {
+                    self.store.record_attempt(message_id, OutboxStatus::Sent, None)?;
+                    report.sent += 1;
+                }
+                Err(e) if e.is_retryable() => {
+                    log::info!("Outbox flush paused at {}: {}", message_id, e);
+                    self.store.record_attempt(message_id, OutboxStatus::Pending, Some(&e.to_string()))?;
+                    report.remaining = pending.len() - index;
+                    return Ok(report);
+                }
+                Err(e) => {
+                    log::warn!("Outbox item {} rejected: {}", message_id, e);
+                    self.store.record_attempt(message_id, OutboxStatus::Failed, Some(&e.to_string()))?;
+                    report.failed += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Age from the signed createdAtClient, falling back to the queue time
+    fn is_expired(&self, item: &OutboxItem, now: DateTime<Utc>) -> bool {
+        let created_at = item.message_doc.created_at_client.as_deref()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.timestamp())
+            .unwrap_or(item.queued_at);
+        let max_age = i64::try_from(self.window.max_age.as_secs()).unwrap_or(i64::MAX);
+        now.timestamp() - created_at > max_age
+    }
+
+    async fn upload(&self, item: &OutboxItem) -> Result<(), TransportError> {
+        // Blob first: the message document must never point at a missing blob
+        self.transport.upload_blob(&item.message_doc.storage_path, &item.blob).await?;
+
+        match self.transport.put_message(&item.uid, &item.message_doc).await {
+            // Written by an earlier attempt whose response was lost
+            Ok(()) | Err(TransportError::AlreadyExists(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use async_trait::async_trait;
+    use crate::crypto::schema::DeviceDoc;
+    use crate::crypto::test_util::TestDevice;
+    use crate::transport::{MemoryTransport, MessageCursor, RemoteMessage, TransportResult};
+
+    /// Memory transport that is offline for the first `offline_puts` message writes
+    struct IntermittentTransport {
+        inner: MemoryTransport,
+        offline_puts: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Transport for IntermittentTransport {
+        async fn fetch_messages(&self, uid: &str, after: Option<&MessageCursor>, limit: usize)
+            -> TransportResult<Vec<RemoteMessage>> {
+            self.inner.fetch_messages(uid, after, limit).await
+        }
+        async fn put_message(&self, uid: &str, doc: &MessageDoc) -> TransportResult<()> {
+            let offline = self.offline_puts
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if offline {
+                return Err(TransportError::Network("offline".to_string()));
+            }
+            self.inner.put_message(uid, doc).await
+        }
+        async fn get_device(&self, uid: &str, device_id: &str) -> TransportResult<Option<DeviceDoc>> {
+            self.inner.get_device(uid, device_id).await
+        }
+        async fn list_devices(&self, uid: &str) -> TransportResult<Vec<DeviceDoc>> {
+            self.inner.list_devices(uid).await
+        }
+        async fn upsert_device(&self, uid: &str, doc: &DeviceDoc) -> TransportResult<()> {
+            self.inner.upsert_device(uid, doc).await
+        }
+        async fn download_blob(&self, storage_path: &str) -> TransportResult<Vec<u8>> {
+            self.inner.download_blob(storage_path).await
+        }
+        async fn upload_blob(&self, storage_path: &str, bytes: &[u8]) -> TransportResult<()> {
+            self.inner.upload_blob(storage_path, bytes).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_in_order_after_reconnect() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let transport = Arc::new(IntermittentTransport {
+            inner: MemoryTransport::new(),
+            offline_puts: AtomicUsize::new(1),
+        });
+        let flusher = OutboxFlusher::new(Arc::new(MemoryOutbox::new()), transport.clone());
+
+        let mut ids = Vec::new();
+        for text in ["one", "two", "three"] {
+            let encrypted = pc.sender().encrypt_text("uid-1", "dev-pc", &[phone.recipient()], text).unwrap();
+            flusher.enqueue("uid-1", &encrypted).unwrap();
+            ids.push(encrypted.message_id);
+        }
+
+        // Offline: nothing overtakes the first item
+        let report = flusher.flush().await.unwrap();
+        assert_eq!(report, FlushReport { sent: 0, failed: 0, remaining: 3 });
+        let status = flusher.status().unwrap();
+        assert_eq!(status[0].attempts, 1);
+        assert!(status[0].last_error.is_some());
+
+        let report = flusher.flush().await.unwrap();
+        assert_eq!(report, FlushReport { sent: 3, failed: 0, remaining: 0 });
+
+        let delivered = transport.fetch_messages("uid-1", None, 10).await.unwrap();
+        let delivered_ids: Vec<_> = delivered.iter().map(|m| m.message_id().to_string()).collect();
+        assert_eq!(delivered_ids, ids);
+        assert!(flusher.status().unwrap().iter().all(|s| s.status == OutboxStatus::Sent));
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_and_upload_are_idempotent() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let transport = Arc::new(MemoryTransport::new());
+        let store = Arc::new(MemoryOutbox::new());
+        let flusher = OutboxFlusher::new(store.clone(), transport.clone());
+
+        let encrypted = pc.sender().encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "once").unwrap();
+        flusher.enqueue("uid-1", &encrypted).unwrap();
+        flusher.enqueue("uid-1", &encrypted).unwrap();
+        assert_eq!(flusher.status().unwrap().len(), 1);
+
+        // Document already written by an attempt whose response never arrived
+        transport.put_message("uid-1", &encrypted.message_doc).await.unwrap();
+        assert_eq!(flusher.flush().await.unwrap().sent, 1);
+        assert_eq!(transport.fetch_messages("uid-1", None, 10).await.unwrap().len(), 1);
+
+        store.purge_sent().unwrap();
+        assert!(flusher.status().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_items_older_than_freshness_window_fail() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let transport = Arc::new(MemoryTransport::new());
+        let flusher = OutboxFlusher::new(Arc::new(MemoryOutbox::new()), transport.clone());
+
+        // Queued before a long offline period
+        let mut stale = pc.sender().encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "old").unwrap();
+        stale.message_doc.created_at_client = Some((Utc::now() - chrono::Duration::days(4)).to_rfc3339());
+        flusher.enqueue("uid-1", &stale).unwrap();
+        let fresh = pc.sender().encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "new").unwrap();
+        flusher.enqueue("uid-1", &fresh).unwrap();
+
+        let report = flusher.flush().await.unwrap();
+        assert_eq!(report, FlushReport { sent: 1, failed: 1, remaining: 0 });
+
+        let status = flusher.status().unwrap();
+        assert_eq!(status[0].status, OutboxStatus::Failed);
+        assert!(status[0].last_error.as_deref().unwrap().contains("72 hours"));
+        let delivered = transport.fetch_messages("uid-1", None, 10).await.unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].message_id(), fresh.message_id);
+    }
+}
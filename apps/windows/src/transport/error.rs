@@ -0,0 +1,92 @@
+//! Transport error hierarchy
+//!
+//! Mirrors `CryptoError`: each variant has a stable code
+//! (`TransportError::code`) and serializing yields `{ "code", "message" }`.
+//! `is_retryable` tells the sync worker whether backing off can help.
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use thiserror::Error;
+use crate::crypto::error::CryptoError;
+
+pub type TransportResult<T> = Result<T, TransportError>;
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    /// Connection failed, timed out or was reset
+    #[error("Network error: {0}")]
+    Network(String),
+    /// Backend answered with an unexpected status
+    #[error("HTTP {status}: {message}")]
+    Http { status: u16, message: String },
+    /// Missing or expired ID token, or security rules denied access
+    #[error("Not authorized: {0}")]
+    Unauthorized(String),
+    /// Document or blob does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// Create-only write hit an existing document
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    /// Response body could not be decoded
+    #[error("Malformed response: {0}")]
+    Malformed(String),
+}
+
+impl TransportError {
+    /// Stable machine-readable code for the frontend and telemetry
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransportError::Network(_) => "network",
+            TransportError::Http { .. } => "http",
+            TransportError::Unauthorized(_) => "unauthorized",
+            TransportError::NotFound(_) => "not_found",
+            TransportError::AlreadyExists(_) => "already_exists",
+            TransportError::Malformed(_) => "malformed_response",
+        }
+    }
+
+    /// True for failures that may succeed when retried later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TransportError::Network(_) => true,
+            TransportError::Http { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl Serialize for TransportError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TransportError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// Failure while receiving one message end to end
+#[derive(Debug, Error)]
+pub enum ReceiveError {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+}
+
+impl ReceiveError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReceiveError::Transport(e) => e.code(),
+            ReceiveError::Crypto(e) => e.code(),
+        }
+    }
+}
+
+impl Serialize for ReceiveError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ReceiveError::Transport(e) => e.serialize(serializer),
+            ReceiveError::Crypto(e) => e.serialize(serializer),
+        }
+    }
+}
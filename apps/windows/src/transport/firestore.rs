@@ -0,0 +1,134 @@
+//! Firestore REST value encoding
+//!
+//! The REST API wraps every field in a typed value (`{"stringValue": ".."}`,
+//! `{"integerValue": "42"}`, `{"mapValue": {"fields": {..}}}`, ...). These
+//! helpers convert between that shape and the plain JSON the schema types
+//! deserialize from. Timestamps and references decode to strings.
+
+use serde_json::{Map, Value};
+use super::error::{TransportError, TransportResult};
+
+/// Plain JSON object -> Firestore `fields` map
+pub fn encode_fields(object: &Map<String, Value>) -> Value {
+    Value::Object(
+        object.iter()
+            .map(|(key, value)| (key.clone(), encode_value(value)))
+            .collect(),
+    )
+}
+
+/// Plain JSON value -> typed Firestore value
+pub fn encode_value(value: &Value) -> Value {
+    let typed = match value {
+        Value::Null => ("nullValue", Value::Null),
+        Value::Bool(b) => ("booleanValue", Value::Bool(*b)),
+        Value::Number(n) => match n.as_i64() {
+            // int64 travels as a decimal string
+            Some(i) => ("integerValue", Value::String(i.to_string())),
+            None => ("doubleValue", Value::from(n.as_f64().unwrap_or_default())),
+        },
+        Value::String(s) => ("stringValue", Value::String(s.clone())),
+        Value::Array(items) => (
+            "arrayValue",
+            serde_json::json!({ "values": items.iter().map(encode_value).collect::<Vec<_>>() }),
+        ),
+        Value::Object(object) => ("mapValue", serde_json::json!({ "fields": encode_fields(object) })),
+    };
+
+    let mut wrapper = Map::new();
+    wrapper.insert(typed.0.to_string(), typed.1);
+    Value::Object(wrapper)
+}
+
+/// Firestore `fields` map -> plain JSON object
+pub fn decode_fields(fields: &Value) -> TransportResult<Value> {
+    let Some(fields) = fields.as_object() else {
+        return Ok(Value::Object(Map::new()));
+    };
+
+    let mut object = Map::new();
+    for (key, value) in fields {
+        object.insert(key.clone(), decode_value(value)?);
+    }
+    Ok(Value::Object(object))
+}
+
+/// Typed Firestore value -> plain JSON value
+pub fn decode_value(value: &Value) -> TransportResult<Value> {
+    let malformed = || TransportError::Malformed(format!("Unexpected Firestore value: {}", value));
+
+    let (kind, inner) = value.as_object()
+        .and_then(|object| object.iter().next())
+        .ok_or_else(malformed)?;
+
+    match kind.as_str() {
+        "nullValue" => Ok(Value::Null),
+        "booleanValue" | "doubleValue" => Ok(inner.clone()),
+        "integerValue" => {
+            let parsed = match inner {
+                Value::String(s) => s.parse::<i64>().ok(),
+                other => other.as_i64(),
+            };
+            parsed.map(Value::from).ok_or_else(malformed)
+        }
+        "stringValue" | "timestampValue" | "referenceValue" | "bytesValue" => Ok(inner.clone()),
+        "geoPointValue" => Ok(inner.clone()),
+        "arrayValue" => {
+            let values = inner.get("values").and_then(Value::as_array);
+            let decoded = values.map(|v| v.iter().map(decode_value).collect::<TransportResult<Vec<_>>>())
+                .transpose()?
+                .unwrap_or_default();
+            Ok(Value::Array(decoded))
+        }
+        "mapValue" => decode_fields(inner.get("fields").unwrap_or(&Value::Null)),
+        _ => Err(malformed()),
+    }
+}
+
+/// Last path segment of a document `name`
+pub fn document_id(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_roundtrip_plain_json() {
+        let plain = json!({
+            "messageId": "m-1",
+            "sizeBytes": 1234,
+            "ratio": 0.5,
+            "flag": true,
+            "missing": null,
+            "recipients": ["dev-a", "dev-b"],
+            "envelopes": { "dev-a": "ZW52" },
+        });
+
+        let encoded = encode_fields(plain.as_object().unwrap());
+        assert_eq!(encoded["sizeBytes"], json!({ "integerValue": "1234" }));
+        assert_eq!(encoded["envelopes"]["mapValue"]["fields"]["dev-a"], json!({ "stringValue": "ZW52" }));
+
+        assert_eq!(decode_fields(&encoded).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_decodes_server_types() {
+        let fields = json!({
+            "createdAt": { "timestampValue": "2026-01-28T16:45:00.123456Z" },
+            "empty": { "arrayValue": {} },
+            "nested": { "mapValue": {} },
+        });
+
+        let decoded = decode_fields(&fields).unwrap();
+        assert_eq!(decoded["createdAt"], "2026-01-28T16:45:00.123456Z");
+        assert_eq!(decoded["empty"], json!([]));
+        assert_eq!(decoded["nested"], json!({}));
+
+        assert!(decode_value(&json!({ "integerValue": "twelve" })).is_err());
+        assert!(decode_value(&json!("bare")).is_err());
+        assert_eq!(document_id("projects/p/databases/(default)/documents/users/u/messages/m-1"), "m-1");
+    }
+}
@@ -0,0 +1,210 @@
+//! In-memory backend (tests, offline demos)
+//!
+//! Behaves like Firestore + Storage for the operations in `Transport`:
+//! create-only message writes, server-assigned strictly increasing
+//! `createdAt`, and cursor paging in (createdAt, messageId) order.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use serde_json::Value;
+use crate::crypto::error::CryptoError;
+use crate::crypto::schema::{DeviceDoc, MessageDoc};
+use super::{MessageCursor, RemoteMessage, Transport};
+use super::error::{TransportError, TransportResult};
+
+#[derive(Default)]
+struct State {
+    /// uid -> cursor -> message document as stored (decoded on fetch)
+    messages: HashMap<String, BTreeMap<MessageCursor, Value>>,
+    /// uid -> deviceId -> device
+    devices: HashMap<String, BTreeMap<String, DeviceDoc>>,
+    blobs: HashMap<String, Vec<u8>>,
+    last_created_at_micros: i64,
+}
+
+#[derive(Default)]
+pub struct MemoryTransport {
+    state: Mutex<State>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers or replaces a device document
+    pub fn put_device(&self, uid: &str, doc: &DeviceDoc) -> TransportResult<()> {
+        self.lock()?
+            .devices.entry(uid.to_string()).or_default()
+            .insert(doc.device_id.clone(), doc.clone());
+        Ok(())
+    }
+
+    /// Creates a message document from raw JSON, the way a buggy or newer
+    /// client could write one, with a server `createdAt`
+    pub fn put_raw_message(&self, uid: &str, message_id: &str, mut document: Value) -> TransportResult<()> {
+        let mut state = self.lock()?;
+
+        let exists = state.messages.get(uid)
+            .is_some_and(|messages| messages.keys().any(|c| c.message_id == message_id));
+        if exists {
+            return Err(TransportError::AlreadyExists(format!("users/{}/messages/{}", uid, message_id)));
+        }
+
+        // Server clock, strictly increasing so ordering is deterministic
+        let created_at_micros = Utc::now().timestamp_micros().max(state.last_created_at_micros + 1);
+        state.last_created_at_micros = created_at_micros;
+
+        let created_at = Utc.timestamp_micros(created_at_micros).single()
+            .ok_or_else(|| TransportError::Malformed("Clock out of range".to_string()))?;
+        document["createdAt"] = created_at.to_rfc3339_opts(chrono::SecondsFormat::Micros, true).into();
+
+        let cursor = MessageCursor { created_at_micros, message_id: message_id.to_string() };
+        state.messages.entry(uid.to_string()).or_default().insert(cursor, document);
+        Ok(())
+    }
+
+    fn lock(&self) -> TransportResult<std::sync::MutexGuard<'_, State>> {
+        self.state.lock()
+            .map_err(|_| TransportError::Network("Memory transport lock poisoned".to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn fetch_messages(
+        &self,
+        uid: &str,
+        after: Option<&MessageCursor>,
+        limit: usize,
+    ) -> TransportResult<Vec<RemoteMessage>> {
+        let state = self.lock()?;
+        let Some(messages) = state.messages.get(uid) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(messages.iter()
+            .filter(|(cursor, _)| !matches!(after, Some(after) if *cursor <= after))
+            .take(limit)
+            .map(|(cursor, document)| RemoteMessage {
+                doc: MessageDoc::from_value(document).map_err(CryptoError::from),
+                cursor: cursor.clone(),
+            })
+            .collect())
+    }
+
+    async fn put_message(&self, uid: &str, doc: &MessageDoc) -> TransportResult<()> {
+        self.put_raw_message(uid, &doc.message_id, doc.to_value())
+    }
+
+    async fn get_device(&self, uid: &str, device_id: &str) -> TransportResult<Option<DeviceDoc>> {
+        Ok(self.lock()?
+            .devices.get(uid)
+            .and_then(|devices| devices.get(device_id))
+            .cloned())
+    }
+
+    async fn list_devices(&self, uid: &str) -> TransportResult<Vec<DeviceDoc>> {
+        Ok(self.lock()?
+            .devices.get(uid)
+            .map(|devices| devices.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn upsert_device(&self, uid: &str, doc: &DeviceDoc) -> TransportResult<()> {
+        let now = serde_json::Value::from(Utc::now().to_rfc3339());
+        let mut state = self.lock()?;
+        let devices = state.devices.entry(uid.to_string()).or_default();
+
+        let mut stored = doc.clone();
+        stored.last_seen_at = Some(now.clone());
+        match devices.get(&doc.device_id) {
+            Some(existing) => {
+                stored.created_at = existing.created_at.clone();
+                stored.status = existing.status;
+            }
+            None => stored.created_at = Some(now),
+        }
+        devices.insert(doc.device_id.clone(), stored);
+        Ok(())
+    }
+
+    async fn download_blob(&self, storage_path: &str) -> TransportResult<Vec<u8>> {
+        self.lock()?
+            .blobs.get(storage_path)
+            .cloned()
+            .ok_or_else(|| TransportError::NotFound(storage_path.to_string()))
+    }
+
+    async fn upload_blob(&self, storage_path: &str, bytes: &[u8]) -> TransportResult<()> {
+        self.lock()?.blobs.insert(storage_path.to_string(), bytes.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::identity::DeviceIdentity;
+    use crate::crypto::key_mgmt::KeyManager;
+    use crate::crypto::schema::{DeviceStatus, Recipients};
+
+    fn message(id: &str) -> MessageDoc {
+        MessageDoc::from_value(&serde_json::json!({
+            "messageId": id,
+            "senderDeviceId": "dev-pc",
+            "recipients": "all",
+            "storagePath": format!("users/uid-1/messages/{}.bin", id),
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_paging_from_cursor() {
+        let transport = MemoryTransport::new();
+        for id in ["m-1", "m-2", "m-3"] {
+            transport.put_message("uid-1", &message(id)).await.unwrap();
+        }
+        assert!(matches!(
+            transport.put_message("uid-1", &message("m-2")).await,
+            Err(TransportError::AlreadyExists(_))
+        ));
+
+        let first = transport.fetch_messages("uid-1", None, 2).await.unwrap();
+        assert_eq!(first.iter().map(|m| m.message_id()).collect::<Vec<_>>(), ["m-1", "m-2"]);
+        let doc = first[0].doc.as_ref().unwrap();
+        assert_eq!(first[0].cursor, MessageCursor::for_message(doc).unwrap());
+        assert_eq!(doc.recipients, Recipients::All);
+
+        let rest = transport.fetch_messages("uid-1", Some(&first[1].cursor), 10).await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].message_id(), "m-3");
+        assert!(transport.fetch_messages("uid-2", None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upsert_device_keeps_created_at_and_status() {
+        let transport = MemoryTransport::new();
+        let identity = DeviceIdentity::new(KeyManager::in_memory());
+        let registration = identity.registration_doc("Laptop").unwrap();
+
+        transport.upsert_device("uid-1", &registration).await.unwrap();
+        let created = transport.get_device("uid-1", &registration.device_id).await.unwrap().unwrap();
+        assert!(created.created_at.is_some());
+        assert_eq!(created.status, DeviceStatus::Active);
+
+        // Revoked from another device, then this one rotates and re-registers
+        let mut revoked = created.clone();
+        revoked.status = DeviceStatus::Revoked;
+        transport.put_device("uid-1", &revoked).unwrap();
+        let rotated = identity.rotate("Laptop", std::time::Duration::from_secs(60)).unwrap();
+        transport.upsert_device("uid-1", &rotated).await.unwrap();
+
+        let stored = transport.get_device("uid-1", &registration.device_id).await.unwrap().unwrap();
+        assert_eq!(stored.status, DeviceStatus::Revoked);
+        assert_eq!(stored.created_at, created.created_at);
+        assert_eq!(stored.pub_box_key, rotated.pub_box_key);
+    }
+}
@@ -0,0 +1,224 @@
+//! Backend transport for Remote Paste
+//!
+//! `Transport` covers everything the app needs from Firebase:
+//! - `users/{uid}/messages` in server `createdAt` order (polled from a cursor)
+//! - `users/{uid}/devices` documents
+//! - `.bin` blobs in Cloud Storage
+//!
+//! Implementations:
+//! - `FirebaseRestTransport`: Firestore + Storage REST APIs (production or the
+//!   local emulator suite)
+//! - `MemoryTransport`: in-process backend for tests
+//!
+//! `receive_message` / `open_message` tie a fetched message to `E2EEReceiver`.
+
+pub mod error;
+pub mod firestore;
+pub mod memory;
+pub mod rest;
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use crate::crypto::error::CryptoError;
+use crate::crypto::receiver::{DecryptionResult, E2EEReceiver};
+use crate::crypto::schema::{DeviceDoc, MessageDoc};
+
+pub use error::{ReceiveError, TransportError, TransportResult};
+pub use memory::MemoryTransport;
+pub use rest::{FirebaseConfig, FirebaseRestTransport};
+
+/// Position in the message stream: server `createdAt`, then document ID
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageCursor {
+    pub created_at_micros: i64,
+    pub message_id: String,
+}
+
+impl MessageCursor {
+    /// Cursor pointing at `doc` (requires the server `createdAt`)
+    pub fn for_message(doc: &MessageDoc) -> TransportResult<Self> {
+        Self::from_created_at(&doc.message_id, doc.created_at.as_ref().and_then(|v| v.as_str()))
+    }
+
+    /// Cursor for a message ID and its server `createdAt` (RFC 3339)
+    pub fn from_created_at(message_id: &str, created_at: Option<&str>) -> TransportResult<Self> {
+        let created_at = created_at
+            .ok_or_else(|| TransportError::Malformed(format!("Message {} has no createdAt", message_id)))?;
+        let parsed = chrono::DateTime::parse_from_rfc3339(created_at)
+            .map_err(|e| TransportError::Malformed(format!("Invalid createdAt {:?}: {}", created_at, e)))?;
+
+        Ok(MessageCursor {
+            created_at_micros: parsed.timestamp_micros(),
+            message_id: message_id.to_string(),
+        })
+    }
+}
+
+/// A message document as returned by the backend
+///
+/// `doc` is `Err` when the document does not decode (unknown `type`, missing
+/// fields...). Its cursor is still valid, so callers can reject it and move
+/// past it instead of fetching it again forever.
+#[derive(Debug, Clone)]
+pub struct RemoteMessage {
+    pub doc: Result<MessageDoc, CryptoError>,
+    pub cursor: MessageCursor,
+}
+
+impl RemoteMessage {
+    pub fn message_id(&self) -> &str {
+        &self.cursor.message_id
+    }
+}
+
+/// Firestore + Cloud Storage operations used by the app
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Messages strictly after `after`, oldest first, at most `limit`
+    ///
+    /// Documents that fail to decode are returned with `doc: Err` rather than
+    /// failing the page.
+    async fn fetch_messages(
+        &self,
+        uid: &str,
+        after: Option<&MessageCursor>,
+        limit: usize,
+    ) -> TransportResult<Vec<RemoteMessage>>;
+
+    /// Creates `users/{uid}/messages/{messageId}` with a server `createdAt`
+    ///
+    /// Fails with `AlreadyExists` if the messageId was written before.
+    async fn put_message(&self, uid: &str, doc: &MessageDoc) -> TransportResult<()>;
+
+    async fn get_device(&self, uid: &str, device_id: &str) -> TransportResult<Option<DeviceDoc>>;
+
+    /// All device documents; ones that fail to decode are skipped
+    async fn list_devices(&self, uid: &str) -> TransportResult<Vec<DeviceDoc>>;
+
+    /// Creates or updates `users/{uid}/devices/{deviceId}`
+    ///
+    /// The server sets `createdAt` on creation and `lastSeenAt` on every
+    /// write. Updates leave `status` alone so re-registering never reinstates
+    /// a revoked device.
+    async fn upsert_device(&self, uid: &str, doc: &DeviceDoc) -> TransportResult<()>;
+
+    async fn download_blob(&self, storage_path: &str) -> TransportResult<Vec<u8>>;
+
+    async fn upload_blob(&self, storage_path: &str, bytes: &[u8]) -> TransportResult<()>;
+}
+
+/// Polls for new messages and forwards them in order
+///
+/// The task stops when the receiving side is dropped. Fetch errors are
+/// forwarded and polling continues after `interval`.
+///
+/// # Arguments
+/// * `start` - Cursor of the last message already handled (None = from the beginning)
+pub fn listen_messages(
+    transport: Arc<dyn Transport>,
+    uid: String,
+    start: Option<MessageCursor>,
+    interval: Duration,
+) -> mpsc::Receiver<TransportResult<RemoteMessage>> {
+    const PAGE_SIZE: usize = 50;
+    let (tx, rx) = mpsc::channel(PAGE_SIZE);
+
+    tokio::spawn(async move {
+        let mut cursor = start;
+        loop {
+            match transport.fetch_messages(&uid, cursor.as_ref(), PAGE_SIZE).await {
+                Ok(page) => {
+                    let full_page = page.len() == PAGE_SIZE;
+                    for message in page {
+                        cursor = Some(message.cursor.clone());
+                        if tx.send(Ok(message)).await.is_err() {
+                            return;
+                        }
+                    }
+                    if full_page {
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    if tx.send(Err(e)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = tx.closed() => return,
+            }
+        }
+    });
+
+    rx
+}
+
+/// Fetches the sender document and blob for `message`, decrypts it and
+/// records it as seen
+///
+/// # Arguments
+/// * `this_device_id` - This device's UUID
+pub async fn receive_message(
+    transport: &dyn Transport,
+    receiver: &E2EEReceiver,
+    uid: &str,
+    this_device_id: &str,
+    message: &RemoteMessage,
+) -> Result<DecryptionResult, ReceiveError> {
+    let result = open_message(transport, receiver, uid, this_device_id, message).await?;
+    receiver.confirm_delivery(&result)?;
+    Ok(result)
+}
+
+/// Like `receive_message`, but leaves recording the message as seen to the
+/// caller (`E2EEReceiver::confirm_delivery`, after storing it)
+pub async fn open_message(
+    transport: &dyn Transport,
+    receiver: &E2EEReceiver,
+    uid: &str,
+    this_device_id: &str,
+    message: &RemoteMessage,
+) -> Result<DecryptionResult, ReceiveError> {
+    let doc = message.doc.as_ref().map_err(|e| e.clone())?;
+    let sender_id = &doc.sender_device_id;
+    let sender_doc = transport.get_device(uid, sender_id).await?
+        .ok_or_else(|| TransportError::NotFound(format!("users/{}/devices/{}", uid, sender_id)))?;
+    let blob = transport.download_blob(&doc.storage_path).await?;
+
+    Ok(receiver.open_message(doc, this_device_id, &sender_doc, &blob)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::test_util::TestDevice;
+
+    #[tokio::test]
+    async fn test_send_and_receive_through_memory_transport() {
+        let pc = TestDevice::provision("dev-pc");
+        let phone = TestDevice::provision("dev-phone");
+        let transport = Arc::new(MemoryTransport::new());
+        transport.put_device("uid-1", &pc.device_doc()).unwrap();
+
+        let encrypted = pc.sender()
+            .encrypt_text("uid-1", "dev-pc", &[phone.recipient()], "over the wire")
+            .unwrap();
+        transport.upload_blob(&encrypted.message_doc.storage_path, &encrypted.blob).await.unwrap();
+        transport.put_message("uid-1", &encrypted.message_doc).await.unwrap();
+
+        let mut messages = listen_messages(transport.clone(), "uid-1".to_string(), None, Duration::from_millis(10));
+        let message = messages.recv().await.unwrap().unwrap();
+
+        let result = receive_message(transport.as_ref(), &phone.receiver(), "uid-1", "dev-phone", &message)
+            .await
+            .unwrap();
+        assert_eq!(result.plaintext.as_deref(), Some("over the wire"));
+    }
+}
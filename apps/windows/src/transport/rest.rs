@@ -0,0 +1,442 @@
+//! Firestore + Cloud Storage over their REST APIs
+//!
+//! Works against production Firebase (with a Firebase Auth ID token) and the
+//! local emulator suite. Emulator hosts come from the standard
+//! `FIRESTORE_EMULATOR_HOST` / `FIREBASE_STORAGE_EMULATOR_HOST` variables,
+//! defaulting to the ports in `firebase.json`.
+//!
+//! Messages are read with `runQuery` ordered by (createdAt, __name__) and
+//! created with `commit` (create-only precondition, server `createdAt`).
+
+use std::sync::RwLock;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use crate::crypto::error::CryptoError;
+use crate::crypto::schema::{DeviceDoc, MessageDoc};
+use super::{MessageCursor, RemoteMessage, Transport};
+use super::error::{TransportError, TransportResult};
+use super::firestore;
+
+/// Token the emulators accept as an admin credential (bypasses security rules)
+const EMULATOR_OWNER_TOKEN: &str = "owner";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the backend lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirebaseConfig {
+    pub project_id: String,
+    pub storage_bucket: String,
+    /// e.g. `https://firestore.googleapis.com` or `http://127.0.0.1:8080`
+    pub firestore_url: String,
+    /// e.g. `https://firebasestorage.googleapis.com` or `http://127.0.0.1:9199`
+    pub storage_url: String,
+}
+
+impl FirebaseConfig {
+    pub fn production(project_id: &str, storage_bucket: &str) -> Self {
+        FirebaseConfig {
+            project_id: project_id.to_string(),
+            storage_bucket: storage_bucket.to_string(),
+            firestore_url: "https://firestore.googleapis.com".to_string(),
+            storage_url: "https://firebasestorage.googleapis.com".to_string(),
+        }
+    }
+
+    /// Local emulator suite
+    pub fn emulator(project_id: &str, storage_bucket: &str) -> Self {
+        let host = |var: &str, default: &str| {
+            format!("http://{}", std::env::var(var).unwrap_or_else(|_| default.to_string()))
+        };
+
+        FirebaseConfig {
+            project_id: project_id.to_string(),
+            storage_bucket: storage_bucket.to_string(),
+            firestore_url: host("FIRESTORE_EMULATOR_HOST", "127.0.0.1:8080"),
+            storage_url: host("FIREBASE_STORAGE_EMULATOR_HOST", "127.0.0.1:9199"),
+        }
+    }
+
+    pub fn is_emulator(&self) -> bool {
+        self.firestore_url.starts_with("http://")
+    }
+
+    /// `projects/{p}/databases/(default)/documents`
+    fn documents_root(&self) -> String {
+        format!("projects/{}/databases/(default)/documents", self.project_id)
+    }
+}
+
+pub struct FirebaseRestTransport {
+    config: FirebaseConfig,
+    client: reqwest::Client,
+    id_token: RwLock<Option<String>>,
+}
+
+impl FirebaseRestTransport {
+    pub fn new(config: FirebaseConfig) -> TransportResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| TransportError::Network(e.to_string()))?;
+
+        let id_token = config.is_emulator().then(|| EMULATOR_OWNER_TOKEN.to_string());
+        Ok(FirebaseRestTransport { config, client, id_token: RwLock::new(id_token) })
+    }
+
+    /// Replaces the Firebase Auth ID token (they expire after an hour)
+    pub fn set_id_token(&self, id_token: Option<String>) {
+        if let Ok(mut token) = self.id_token.write() {
+            *token = id_token;
+        }
+    }
+
+    pub fn config(&self) -> &FirebaseConfig {
+        &self.config
+    }
+
+    fn document_url(&self, path: &str) -> String {
+        format!("{}/v1/{}/{}", self.config.firestore_url, self.config.documents_root(), path)
+    }
+
+    fn document_name(&self, uid: &str, collection: &str, id: &str) -> String {
+        format!("{}/users/{}/{}/{}", self.config.documents_root(), uid, collection, id)
+    }
+
+    fn blob_url(&self, storage_path: &str) -> String {
+        format!(
+            "{}/v0/b/{}/o/{}",
+            self.config.storage_url,
+            self.config.storage_bucket,
+            percent_encode(storage_path),
+        )
+    }
+
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.id_token.read().ok().and_then(|t| t.clone()) {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder, what: &str) -> TransportResult<reqwest::Response> {
+        let response = self.authorized(request)
+            .send()
+            .await
+            .map_err(|e| TransportError::Network(e.to_string()))?;
+
+        let status = response.status().as_u16();
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let message = response.text().await.unwrap_or_default();
+        Err(match status {
+            401 | 403 => TransportError::Unauthorized(what.to_string()),
+            404 => TransportError::NotFound(what.to_string()),
+            409 => TransportError::AlreadyExists(what.to_string()),
+            _ => TransportError::Http { status, message },
+        })
+    }
+
+    async fn json(response: reqwest::Response) -> TransportResult<Value> {
+        response.json::<Value>()
+            .await
+            .map_err(|e| TransportError::Malformed(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for FirebaseRestTransport {
+    async fn fetch_messages(
+        &self,
+        uid: &str,
+        after: Option<&MessageCursor>,
+        limit: usize,
+    ) -> TransportResult<Vec<RemoteMessage>> {
+        let url = format!("{}:runQuery", self.document_url(&format!("users/{}", percent_encode(uid))));
+        let after_name = after.map(|c| self.document_name(uid, "messages", &c.message_id));
+        let body = messages_query(after, after_name.as_deref(), limit);
+
+        let response = self.send(self.client.post(url).json(&body), "messages query").await?;
+        decode_message_page(&Self::json(response).await?)
+    }
+
+    async fn put_message(&self, uid: &str, doc: &MessageDoc) -> TransportResult<()> {
+        let url = format!("{}/v1/{}:commit", self.config.firestore_url, self.config.documents_root());
+
+        let mut value = doc.to_value();
+        if let Some(object) = value.as_object_mut() {
+            object.remove("createdAt");  // set by the server below
+        }
+        let fields = firestore::encode_fields(value.as_object().expect("MessageDoc serializes to an object"));
+
+        let body = json!({
+            "writes": [{
+                "update": {
+                    "name": self.document_name(uid, "messages", &doc.message_id),
+                    "fields": fields,
+                },
+                "updateTransforms": [
+                    { "fieldPath": "createdAt", "setToServerValue": "REQUEST_TIME" }
+                ],
+                "currentDocument": { "exists": false },
+            }]
+        });
+
+        let what = format!("users/{}/messages/{}", uid, doc.message_id);
+        self.send(self.client.post(url).json(&body), &what).await?;
+        Ok(())
+    }
+
+    async fn get_device(&self, uid: &str, device_id: &str) -> TransportResult<Option<DeviceDoc>> {
+        let path = format!("users/{}/devices/{}", percent_encode(uid), percent_encode(device_id));
+        let response = match self.send(self.client.get(self.document_url(&path)), &path).await {
+            Ok(response) => response,
+            Err(TransportError::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let document = Self::json(response).await?;
+        decode_device(&document).map(Some)
+    }
+
+    async fn list_devices(&self, uid: &str) -> TransportResult<Vec<DeviceDoc>> {
+        let path = format!("users/{}/devices", percent_encode(uid));
+        let mut devices = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut request = self.client.get(self.document_url(&path)).query(&[("pageSize", "300")]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+
+            let page = Self::json(self.send(request, &path).await?).await?;
+            if let Some(documents) = page["documents"].as_array() {
+                for document in documents {
+                    match decode_device(document) {
+                        Ok(device) => devices.push(device),
+                        Err(e) => log::warn!("Skipping unreadable device document: {}", e),
+                    }
+                }
+            }
+
+            match page["nextPageToken"].as_str() {
+                Some(token) if !token.is_empty() => page_token = Some(token.to_string()),
+                _ => return Ok(devices),
+            }
+        }
+    }
+
+    async fn upsert_device(&self, uid: &str, doc: &DeviceDoc) -> TransportResult<()> {
+        let url = format!("{}/v1/{}:commit", self.config.firestore_url, self.config.documents_root());
+        let name = self.document_name(uid, "devices", &doc.device_id);
+        let what = format!("users/{}/devices/{}", uid, doc.device_id);
+
+        let mut value = doc.to_value();
+        let object = value.as_object_mut().expect("DeviceDoc serializes to an object");
+        object.remove("createdAt");  // set by the server below
+        object.remove("lastSeenAt");
+
+        // First registration: create-only, with server timestamps
+        let create = json!({
+            "writes": [{
+                "update": { "name": name, "fields": firestore::encode_fields(object) },
+                "updateTransforms": [
+                    { "fieldPath": "createdAt", "setToServerValue": "REQUEST_TIME" },
+                    { "fieldPath": "lastSeenAt", "setToServerValue": "REQUEST_TIME" }
+                ],
+                "currentDocument": { "exists": false },
+            }]
+        });
+        match self.send(self.client.post(&url).json(&create), &what).await {
+            Ok(_) => return Ok(()),
+            Err(TransportError::AlreadyExists(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        // Known device: refresh name and keys only, keeping createdAt and status
+        object.remove("status");
+        let field_paths: Vec<&String> = object.keys().collect();
+        let update = json!({
+            "writes": [{
+                "update": { "name": name, "fields": firestore::encode_fields(object) },
+                "updateMask": { "fieldPaths": field_paths },
+                "updateTransforms": [
+                    { "fieldPath": "lastSeenAt", "setToServerValue": "REQUEST_TIME" }
+                ],
+                "currentDocument": { "exists": true },
+            }]
+        });
+        self.send(self.client.post(url).json(&update), &what).await?;
+        Ok(())
+    }
+
+    async fn download_blob(&self, storage_path: &str) -> TransportResult<Vec<u8>> {
+        let request = self.client.get(self.blob_url(storage_path)).query(&[("alt", "media")]);
+        let response = self.send(request, storage_path).await?;
+
+        let bytes = response.bytes()
+            .await
+            .map_err(|e| TransportError::Network(e.to_string()))?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn upload_blob(&self, storage_path: &str, bytes: &[u8]) -> TransportResult<()> {
+        let url = format!("{}/v0/b/{}/o", self.config.storage_url, self.config.storage_bucket);
+        let request = self.client.post(url)
+            .query(&[("name", storage_path)])
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(bytes.to_vec());
+
+        self.send(request, storage_path).await?;
+        Ok(())
+    }
+}
+
+/// structuredQuery over `messages` ordered by (createdAt, __name__), after a cursor
+fn messages_query(after: Option<&MessageCursor>, after_name: Option<&str>, limit: usize) -> Value {
+    let mut query = json!({
+        "from": [{ "collectionId": "messages" }],
+        "orderBy": [
+            { "field": { "fieldPath": "createdAt" }, "direction": "ASCENDING" },
+            { "field": { "fieldPath": "__name__" }, "direction": "ASCENDING" },
+        ],
+        "limit": limit,
+    });
+
+    if let (Some(cursor), Some(name)) = (after, after_name) {
+        let created_at = chrono::DateTime::from_timestamp_micros(cursor.created_at_micros)
+            .unwrap_or_default()
+            .to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        query["startAt"] = json!({
+            "values": [
+                { "timestampValue": created_at },
+                { "referenceValue": name },
+            ],
+            "before": false,
+        });
+    }
+
+    json!({ "structuredQuery": query })
+}
+
+/// runQuery results -> messages, oldest first
+fn decode_message_page(results: &Value) -> TransportResult<Vec<RemoteMessage>> {
+    results.as_array()
+        .ok_or_else(|| TransportError::Malformed("runQuery did not return an array".to_string()))?
+        .iter()
+        .filter_map(|result| result.get("document"))
+        .map(decode_message)
+        .collect()
+}
+
+/// One message document; fails only if it cannot be placed in the stream
+fn decode_message(document: &Value) -> TransportResult<RemoteMessage> {
+    let message_id = firestore::document_id(document["name"].as_str().unwrap_or_default());
+    let created_at = document["fields"]["createdAt"]["timestampValue"].as_str()
+        .or_else(|| document["createTime"].as_str());
+    let cursor = MessageCursor::from_created_at(message_id, created_at)?;
+
+    let doc = firestore::decode_fields(&document["fields"])
+        .map_err(|e| CryptoError::MalformedDocument(e.to_string()))
+        .and_then(|mut fields| {
+            // Legacy documents may omit messageId; the document ID is authoritative
+            if fields.get("messageId").is_none() {
+                fields["messageId"] = Value::from(message_id);
+            }
+            Ok(MessageDoc::from_value(&fields)?)
+        });
+    Ok(RemoteMessage { doc, cursor })
+}
+
+fn decode_device(document: &Value) -> TransportResult<DeviceDoc> {
+    let name = document["name"].as_str().unwrap_or_default();
+    let mut fields = firestore::decode_fields(&document["fields"])?;
+    if fields.get("deviceId").is_none() {
+        fields["deviceId"] = Value::from(firestore::document_id(name));
+    }
+    DeviceDoc::from_value(&fields)
+        .map_err(|e| TransportError::Malformed(format!("{}: {}", name, e)))
+}
+
+/// Percent-encodes everything except RFC 3986 unreserved characters
+fn percent_encode(segment: &str) -> String {
+    segment.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emulator_urls() {
+        let transport = FirebaseRestTransport::new(FirebaseConfig {
+            project_id: "demo-scap".to_string(),
+            storage_bucket: "demo-scap.appspot.com".to_string(),
+            firestore_url: "http://127.0.0.1:8080".to_string(),
+            storage_url: "http://127.0.0.1:9199".to_string(),
+        })
+        .unwrap();
+
+        assert!(transport.config().is_emulator());
+        assert_eq!(
+            transport.document_url("users/u1/devices/d1"),
+            "http://127.0.0.1:8080/v1/projects/demo-scap/databases/(default)/documents/users/u1/devices/d1"
+        );
+        assert_eq!(
+            transport.blob_url("users/u1/messages/m 1.bin"),
+            "http://127.0.0.1:9199/v0/b/demo-scap.appspot.com/o/users%2Fu1%2Fmessages%2Fm%201.bin"
+        );
+    }
+
+    #[test]
+    fn test_messages_query_cursor() {
+        let cursor = MessageCursor { created_at_micros: 1_769_618_700_000_001, message_id: "m-1".to_string() };
+        let query = messages_query(Some(&cursor), Some("projects/p/databases/(default)/documents/users/u/messages/m-1"), 25);
+
+        assert_eq!(query["structuredQuery"]["limit"], 25);
+        assert_eq!(
+            query["structuredQuery"]["startAt"]["values"][0]["timestampValue"],
+            "2026-01-28T16:45:00.000001Z"
+        );
+        assert_eq!(query["structuredQuery"]["startAt"]["before"], false);
+        assert!(messages_query(None, None, 25)["structuredQuery"].get("startAt").is_none());
+    }
+
+    #[test]
+    fn test_malformed_message_does_not_fail_page() {
+        let document = |id: &str, created_at: &str, kind: &str, storage_path: &str| json!({
+            "document": {
+                "name": format!("projects/p/databases/(default)/documents/users/u/messages/{}", id),
+                "fields": {
+                    "senderDeviceId": { "stringValue": "dev-pc" },
+                    "recipients": { "stringValue": "all" },
+                    "type": { "stringValue": kind },
+                    "storagePath": { "stringValue": storage_path },
+                    "createdAt": { "timestampValue": created_at },
+                },
+            }
+        });
+        let results = json!([
+            document("m-1", "2026-01-28T16:45:00Z", "text", "users/u/messages/m-1.bin"),
+            document("m-2", "2026-01-28T16:45:01Z", "video", "users/u/messages/m-2.bin"),
+            document("m-3", "2026-01-28T16:45:02Z", "text", ""),
+            document("m-4", "2026-01-28T16:45:03Z", "text", "users/u/messages/m-4.bin"),
+        ]);
+
+        let page = decode_message_page(&results).unwrap();
+        assert_eq!(page.iter().map(|m| m.message_id()).collect::<Vec<_>>(), ["m-1", "m-2", "m-3", "m-4"]);
+        assert_eq!(page[0].doc.as_ref().unwrap().message_id, "m-1");
+        assert!(matches!(&page[1].doc, Err(CryptoError::UnsupportedMessageType(t)) if t == "video"));
+        assert!(matches!(&page[2].doc, Err(CryptoError::MalformedDocument(_))));
+        assert_eq!(page[2].cursor.created_at_micros, 1_769_618_702_000_000);
+        assert!(page[3].doc.is_ok());
+    }
+}